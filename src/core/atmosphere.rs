@@ -0,0 +1,331 @@
+/**
+ * Atmosphere of every deck: each open cell holds some oxygen, nitrogen and carbon dioxide, and its pressure
+ * follows from the total. Each fixed tick gas diffuses between neighbouring open cells, leaks out through
+ * cells open to space, and vents and scrubbers trade gas with the gas pipe network they are fitted into.
+ * Whether a cell holds gas, blocks it or opens to space comes from its tiles (see `Space`). Like
+ * `core::fluids`, the step only reads the map and visits cells in `AirCell` order, so a run is fully
+ * deterministic and can be driven without an app or window.
+ */
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use crate::core::events::MapResized;
+use crate::core::fluids::{Fluid, FluidMix, FluidState};
+use crate::core::map::MapState;
+use crate::core::pipes::{PipeCell, PipeComponent, PipeMap, UtilityKind};
+use crate::core::tile::{TileId, Tileset};
+
+/** Gas one cell holds at a pressure of one atmosphere. */
+pub const ONE_ATMOSPHERE: f32 = 100.0;
+
+/**
+ * Fraction of each gas's difference that crosses between two neighbouring cells per tick. A cell has at
+ * most four neighbours, so keeping this below 1/4 means a cell can never give away more than it holds.
+ */
+pub const DIFFUSION_PER_TICK: f32 = 0.2;
+
+/** Gas a vent releases from its pipe per tick while its room is below `VENT_TARGET_PRESSURE`. */
+pub const VENT_PER_TICK: f32 = 2.0;
+
+/** Pressure, in atmospheres, a vent fills its room up to. */
+pub const VENT_TARGET_PRESSURE: f32 = 1.0;
+
+/** Carbon dioxide a scrubber pulls from its room into its pipe per tick. */
+pub const SCRUBBER_PER_TICK: f32 = 1.0;
+
+/** Fluids that make up the atmosphere; water only flows through pipes. */
+pub const GASES: [Fluid; 3] = [Fluid::Oxygen, Fluid::Nitrogen, Fluid::CarbonDioxide];
+
+/** Amounts below this are treated as empty and dropped, so evacuated cells stop being simulated. */
+const EMPTY_EPSILON: f32 = 1e-4;
+
+/** A cell of the atmosphere: storage cell (x,y) on deck `level`. Ordered by deck, row, then column. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct AirCell { pub level: u32, pub y: u32, pub x: u32 }
+
+impl AirCell {
+    pub fn new(level: u32, x: u32, y: u32) -> Self { Self { level, y, x } }
+}
+
+/** What a cell does to gas, decided by its tiles. */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Space {
+    /** Holds gas: a built base tile and nothing airtight. */
+    Open,
+    /** Blocks gas: an airtight base or overlay tile, or outside the map. */
+    Sealed,
+    /** Open to space: an `Empty` base. Gas flowing in is lost. */
+    Vacuum,
+}
+
+/** What `cell` does to gas on the current map. */
+pub fn space_at(map: &MapState, tileset: &Tileset, cell: AirCell) -> Space {
+    let AirCell { level, x, y } = cell;
+    if level >= map.levels() || x >= map.size.w || y >= map.size.h { return Space::Sealed }
+    let base = map.get_base(level, x, y);
+    if [Some(base), map.get_overlay(level, x, y)].into_iter().flatten().any(|id| tileset.def(id).props.airtight) {
+        return Space::Sealed;
+    }
+    if base == TileId::EMPTY { Space::Vacuum } else { Space::Open }
+}
+
+/**
+ * Gas mix per open cell. Cells without an entry hold nothing. Follows map growth via
+ * `rebase_atmosphere`; a load replaces it with the saved one.
+ */
+#[derive(Resource, Default)]
+pub struct Atmosphere {
+    gas: BTreeMap<AirCell, FluidMix>,
+}
+
+impl Atmosphere {
+    /** Gas held by `cell`, by gas. */
+    pub fn mix(&self, cell: AirCell) -> FluidMix { self.gas.get(&cell).copied().unwrap_or_default() }
+
+    /** Total gas held by `cell`. */
+    pub fn amount(&self, cell: AirCell) -> f32 { self.mix(cell).total() }
+
+    /** Pressure of `cell` in atmospheres; 0 in vacuum and sealed cells. */
+    pub fn pressure(&self, cell: AirCell) -> f32 { self.amount(cell) / ONE_ATMOSPHERE }
+
+    /** Sets the amount of `gas` held by `cell`; ignored unless the cell is open and `gas` is one of `GASES`. */
+    pub fn set_amount(&mut self, map: &MapState, tileset: &Tileset, cell: AirCell, gas: Fluid, amount: f32) {
+        if !GASES.contains(&gas) || space_at(map, tileset, cell) != Space::Open { return }
+        let mix = self.gas.entry(cell).or_default();
+        mix.0[gas.index()] = amount.max(0.0);
+        if mix.total() < EMPTY_EPSILON { self.gas.remove(&cell); }
+    }
+
+    /**
+     * Advances the atmosphere by one tick: gas in cells that are no longer open is lost, vents and scrubbers
+     * trade with their pipes, then every pair of neighbouring cells moves `DIFFUSION_PER_TICK` of each gas's
+     * difference from the fuller cell to the emptier one, and cells next to vacuum lose that share of their
+     * gas to space. All diffusion is computed from the amounts at the start of the pass.
+     *
+     * @param map - tiles deciding which cells are open, sealed or vacuum
+     * @param tileset - airtightness of the tiles
+     * @param pipes - where vents and scrubbers are fitted
+     * @param fluids - contents of the gas pipes vents draw from and scrubbers fill
+     */
+    pub fn step(&mut self, map: &MapState, tileset: &Tileset, pipes: &PipeMap, fluids: &mut FluidState) {
+        self.gas.retain(|&cell, _| space_at(map, tileset, cell) == Space::Open);
+
+        for level in 0..map.levels().min(pipes.levels()) {
+            let devices: Vec<(u32, u32, PipeComponent)> = pipes.components_on(UtilityKind::Gas, level).filter(|(_, _, c)| c.needs_gas()).collect();
+            for (x, y, device) in devices {
+                let (air, pipe) = (AirCell::new(level, x, y), PipeCell::new(UtilityKind::Gas, level, x, y));
+                if space_at(map, tileset, air) != Space::Open { continue }
+                let room = self.gas.entry(air).or_default();
+                match device {
+                    PipeComponent::Vent => {
+                        let wanted = (VENT_TARGET_PRESSURE * ONE_ATMOSPHERE - room.total()).max(0.0);
+                        // Only gas is released; water in the gas pipe stays there.
+                        let released = fluids.drain(pipe, &GASES, VENT_PER_TICK.min(wanted));
+                        for gas in GASES { room.0[gas.index()] += released.get(gas); }
+                    }
+                    PipeComponent::Scrubber => {
+                        let co2 = Fluid::CarbonDioxide.index();
+                        room.0[co2] -= fluids.fill(pipes, pipe, Fluid::CarbonDioxide, SCRUBBER_PER_TICK.min(room.0[co2]));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut delta: BTreeMap<AirCell, FluidMix> = BTreeMap::new();
+        for (&cell, &mix) in &self.gas {
+            let AirCell { x, y, .. } = cell;
+            for (x, y) in [(x, y.wrapping_sub(1)), (x + 1, y), (x, y + 1), (x.wrapping_sub(1), y)] {
+                let next = AirCell { x, y, ..cell };
+                // Each pair once: from its lower cell, or from this one when the other is empty. `None` is vacuum.
+                let other = match self.gas.get(&next) {
+                    Some(_) if next < cell => continue,
+                    Some(&other) => Some(other),
+                    None => match space_at(map, tileset, next) {
+                        Space::Open => Some(FluidMix::default()),
+                        Space::Vacuum => None,
+                        Space::Sealed => continue,
+                    },
+                };
+                for gas in GASES {
+                    let i = gas.index();
+                    let flow = DIFFUSION_PER_TICK * (mix.0[i] - other.map_or(0.0, |other| other.0[i]));
+                    delta.entry(cell).or_default().0[i] -= flow;
+                    if other.is_some() { delta.entry(next).or_default().0[i] += flow; }
+                }
+            }
+        }
+        for (cell, d) in delta {
+            let mix = self.gas.entry(cell).or_default();
+            for (amount, change) in mix.0.iter_mut().zip(d.0) { *amount = (*amount + change).max(0.0); }
+        }
+        self.gas.retain(|_, mix| mix.total() >= EMPTY_EPSILON);
+    }
+
+    /** Moves every cell by a map growth shift. */
+    fn shift(&mut self, shift: UVec2) {
+        let moved = |c: AirCell| AirCell { x: c.x + shift.x, y: c.y + shift.y, ..c };
+        self.gas = std::mem::take(&mut self.gas).into_iter().map(|(c, m)| (moved(c), m)).collect();
+    }
+}
+
+/** Runs one atmosphere tick per `FixedUpdate`, after the pipes moved their fluid. */
+pub fn step_atmosphere(
+    map: Res<MapState>,
+    tileset: Res<Tileset>,
+    pipes: Res<PipeMap>,
+    mut fluids: ResMut<FluidState>,
+    mut atmosphere: ResMut<Atmosphere>,
+) {
+    atmosphere.step(&map, &tileset, &pipes, &mut fluids);
+}
+
+/** Follows map growth. A load (zero growth) inserted the saved atmosphere along with the map, so it is left alone. */
+pub fn rebase_atmosphere(mut resized: MessageReader<MapResized>, mut atmosphere: ResMut<Atmosphere>) {
+    for ev in resized.read() {
+        if !ev.growth.is_zero() { atmosphere.shift(ev.growth.shift()); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::catalog::test_tileset;
+    use crate::core::fluids::{FluidPort, PIPE_CAPACITY};
+    use crate::core::test_support::Fixture;
+
+    const CATALOG: &str = r#"{ "tiles": [
+        { "id": "Empty", "layer": "Base", "color": [0, 0, 0, 0] },
+        { "id": "Deck", "layer": "Base", "color": [1, 1, 1, 1], "properties": { "tags": ["floor"] } },
+        { "id": "Wall", "layer": "Base", "color": [1, 1, 1, 1], "properties": { "airtight": true } }
+    ] }"#;
+
+    /** One deck drawn row by row: `D` deck, `W` wall, `.` empty (open to space). */
+    fn fixture(rows: &[&str]) -> Fixture { Fixture::drawn(test_tileset(CATALOG), rows, &[('D', "Deck"), ('W', "Wall")]) }
+
+    impl Fixture {
+        fn set(&mut self, x: u32, y: u32, gas: Fluid, amount: f32) {
+            self.air.set_amount(&self.map, &self.tileset, AirCell::new(0, x, y), gas, amount);
+        }
+
+        fn amount(&self, x: u32, y: u32) -> f32 { self.air.amount(AirCell::new(0, x, y)) }
+
+        fn total(&self) -> f32 { self.air.gas.values().map(FluidMix::total).sum() }
+
+        /** Fits `device` into a gas pipe at (x,y) and returns the pipe cell. */
+        fn fit(&mut self, x: u32, y: u32, device: PipeComponent) -> PipeCell {
+            self.pipes.set(UtilityKind::Gas, 0, x, y, true);
+            self.pipes.set_component(UtilityKind::Gas, 0, x, y, Some(device));
+            PipeCell::new(UtilityKind::Gas, 0, x, y)
+        }
+
+        fn step(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                self.fluids.step(&self.pipes);
+                self.air.step(&self.map, &self.tileset, &self.pipes, &mut self.fluids);
+            }
+        }
+    }
+
+    const ROOM: [&str; 3] = ["WWW", "WDW", "WWW"];
+
+    #[test]
+    fn diffusion_conserves_gas_between_open_cells() {
+        let mut f = fixture(&["WWWWWW", "WDDDDW", "WWWWWW"]);
+        f.set(1, 1, Fluid::Oxygen, 60.0);
+        f.step(200);
+        assert!((f.total() - 60.0).abs() < 1e-3);
+        for x in 1..5 { assert!((f.amount(x, 1) - 15.0).abs() < 1e-3); }
+        assert_eq!(f.air.mix(AirCell::new(0, 4, 1)).get(Fluid::Nitrogen), 0.0);
+        assert!((f.air.pressure(AirCell::new(0, 4, 1)) - 0.15).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sealed_cells_block_gas() {
+        let mut f = fixture(&["WWWWW", "WDWDW", "WWWWW"]);
+        assert_eq!(space_at(&f.map, &f.tileset, AirCell::new(0, 2, 1)), Space::Sealed);
+        f.set(2, 1, Fluid::Oxygen, 50.0);
+        assert_eq!(f.amount(2, 1), 0.0, "sealed cells hold no gas");
+        f.set(1, 1, Fluid::Oxygen, 50.0);
+        f.step(50);
+        assert_eq!(f.amount(1, 1), 50.0);
+        assert_eq!(f.amount(3, 1), 0.0);
+    }
+
+    #[test]
+    fn cells_next_to_space_drain() {
+        let mut f = fixture(&["WWWW", "WD.W", "WWWW"]);
+        assert_eq!(space_at(&f.map, &f.tileset, AirCell::new(0, 2, 1)), Space::Vacuum);
+        f.set(1, 1, Fluid::Nitrogen, 50.0);
+        f.step(1);
+        assert!((f.amount(1, 1) - 40.0).abs() < 1e-3);
+        f.step(100);
+        assert_eq!(f.amount(1, 1), 0.0);
+        assert_eq!(f.amount(2, 1), 0.0, "space never holds gas");
+    }
+
+    #[test]
+    fn rooms_on_the_map_edge_keep_their_gas() {
+        let mut f = fixture(&["DD", "DD"]);
+        assert_eq!(space_at(&f.map, &f.tileset, AirCell::new(0, 2, 1)), Space::Sealed, "off the map is sealed");
+        assert_eq!(space_at(&f.map, &f.tileset, AirCell::new(0, 0, u32::MAX)), Space::Sealed);
+        f.set(0, 0, Fluid::Oxygen, 40.0);
+        f.step(100);
+        assert!((f.total() - 40.0).abs() < 1e-3);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] { assert!((f.amount(x, y) - 10.0).abs() < 1e-3); }
+    }
+
+    #[test]
+    fn vents_fill_their_room_to_the_target_pressure() {
+        let mut f = fixture(&ROOM);
+        let pipe = f.fit(1, 1, PipeComponent::Vent);
+        f.fluids.attach_port(pipe, FluidPort { rate: 5.0, fluid: Fluid::Oxygen });
+        f.step(200);
+        let room = AirCell::new(0, 1, 1);
+        assert!((f.air.pressure(room) - VENT_TARGET_PRESSURE).abs() < 1e-3);
+        f.step(20);
+        assert!((f.air.amount(room) - VENT_TARGET_PRESSURE * ONE_ATMOSPHERE).abs() < 1e-3, "a filled room gets no more");
+        assert!((f.fluids.amount(pipe) - PIPE_CAPACITY).abs() < 1e-3, "the pipe backs up once the room is full");
+    }
+
+    #[test]
+    fn vents_release_only_gas_and_conserve_the_total() {
+        let mut f = fixture(&ROOM);
+        let pipe = f.fit(1, 1, PipeComponent::Vent);
+        f.fluids.set_amount(&f.pipes, pipe, Fluid::Water, 6.0);
+        f.fluids.set_amount(&f.pipes, pipe, Fluid::Oxygen, 3.0);
+        f.step(5);
+        assert_eq!(f.fluids.mix(pipe).get(Fluid::Water), 6.0, "water stays in the pipe");
+        assert_eq!(f.fluids.mix(pipe).get(Fluid::Oxygen), 0.0);
+        assert!((f.fluids.amount(pipe) + f.total() - 9.0).abs() < 1e-3, "fluid plus gas is conserved");
+    }
+
+    #[test]
+    fn scrubbers_move_co2_into_their_pipe_up_to_its_capacity() {
+        let mut f = fixture(&ROOM);
+        let pipe = f.fit(1, 1, PipeComponent::Scrubber);
+        f.set(1, 1, Fluid::CarbonDioxide, 50.0);
+        f.set(1, 1, Fluid::Oxygen, 20.0);
+        f.step(10);
+        assert!((f.amount(1, 1) - 60.0).abs() < 1e-3, "only CO2 is taken");
+        assert!((f.fluids.mix(pipe).get(Fluid::CarbonDioxide) - 10.0).abs() < 1e-3);
+
+        f.fluids.set_amount(&f.pipes, pipe, Fluid::Nitrogen, PIPE_CAPACITY - 10.5);
+        f.step(5);
+        assert!((f.fluids.amount(pipe) - PIPE_CAPACITY).abs() < 1e-3);
+        assert!((f.air.mix(AirCell::new(0, 1, 1)).get(Fluid::CarbonDioxide) - 39.5).abs() < 1e-3, "a full pipe takes no more");
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let run = || {
+            let mut f = fixture(&["WWWWWW", "WDDDDW", "WDD.DW", "WWWWWW"]);
+            let vent = f.fit(1, 1, PipeComponent::Vent);
+            f.fluids.attach_port(vent, FluidPort { rate: 3.0, fluid: Fluid::Nitrogen });
+            f.fit(4, 2, PipeComponent::Scrubber);
+            f.set(4, 1, Fluid::CarbonDioxide, 30.0);
+            f.step(50);
+            f.air.gas
+        };
+        assert_eq!(run(), run());
+    }
+}
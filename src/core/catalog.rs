@@ -1,18 +1,93 @@
-use bevy::asset::{io::Reader, Asset, AssetLoader, LoadContext};
+use bevy::asset::{io::Reader, Asset, AssetLoadFailedEvent, AssetLoader, LoadContext};
 use bevy::prelude::*;
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::core::inventory::Inventory;
+use crate::core::map::MapState;
+use crate::core::structure::{parse_mask, StructureId};
+use crate::core::tile::{PlacementRule, TileId, TileLayer, TileProperties, Tileset};
 
 /**
- * Runtime tile catalog asset loaded from JSON files.
+ * Runtime tile catalog asset loaded from JSON files; the source of truth for `Tileset`.
+ * Entries may reference a cell of a texture atlas (a grid of `GridConfig::tile_size` cells, row-major),
+ * optionally with extra variant cells; `color` then tints the art. Gameplay `properties` (see
+ * `TileProperties`) and placement `rules` (see `PlacementRule`) are optional. Building `materials`, which
+ * `build_cost`s are paid in, and multi-cell `structures` (see `StructureDef`) are declared next to the tiles;
+ * a bare array of tiles declares neither. A structure's `mask` rows run top to bottom; without one the
+ * whole `size` rectangle is covered. Its optional `sprite` is one image drawn over the whole footprint.
  * Example JSON:
- * [
- *   { "id": "Dirt", "layer": "Base", "color": [0.55, 0.42, 0.35, 1.0] },
- *   { "id": "Marker", "layer": "Overlay", "color": [1.0, 1.0, 0.0, 1.0] }
- * ]
+ * {
+ *   "materials": [ { "id": "Regolith", "starting": 100 } ],
+ *   "tiles": [
+ *     { "id": "Dirt", "layer": "Base", "color": [1.0, 1.0, 1.0, 1.0],
+ *       "sprite": { "atlas": "tiles/terrain.png", "index": 0, "variants": [1, 2] },
+ *       "properties": { "walkable": true, "build_cost": { "Regolith": 1 }, "tags": ["floor"] } },
+ *     { "id": "Marker", "layer": "Overlay", "color": [1.0, 1.0, 0.0, 1.0], "rules": ["requires_base"] }
+ *   ],
+ *   "structures": [
+ *     { "id": "Machine", "size": [2, 2], "mask": ["#.", "##"], "color": [0.6, 0.6, 0.7, 1.0],
+ *       "sprite": "structures/machine.png", "rules": ["requires_floor"] }
+ *   ]
+ * }
  */
 #[derive(Asset, TypePath, Clone, Default)]
 pub struct TileCatalog {
+    pub materials: Vec<MaterialEntry>,
     pub defs: Vec<TileCatalogEntry>,
+    pub structures: Vec<StructureCatalogEntry>,
+    /** Every distinct atlas and sprite referenced, so the catalog only counts as loaded once its art has. */
+    #[dependency]
+    pub atlases: Vec<Handle<Image>>,
+}
+
+/** Catalog material: an id for `build_cost`s and the amount a new station has in stock. */
+#[derive(Clone, Deserialize)]
+pub struct MaterialEntry {
+    pub id: String,
+    #[serde(default)]
+    pub starting: u32,
+}
+
+/** Object form of a catalog file; the bare-array form is just `tiles`. */
+#[derive(Deserialize)]
+struct CatalogFile {
+    #[serde(default)]
+    materials: Vec<MaterialEntry>,
+    tiles: Vec<TileCatalogEntry>,
+    #[serde(default)]
+    structures: Vec<StructureCatalogEntry>,
+}
+
+/** Catalog structure: a `size` footprint (width, height in cells), optionally masked. */
+#[derive(Clone, Deserialize)]
+pub struct StructureCatalogEntry {
+    pub id: String,
+    pub size: [u32; 2],
+    #[serde(default)]
+    pub mask: Vec<String>,
+    pub color: [f32; 4],
+    #[serde(default)]
+    pub sprite: Option<String>,
+    #[serde(default)]
+    pub properties: TilePropertiesEntry,
+    #[serde(default)]
+    pub rules: Vec<PlacementRule>,
+    /** Resolved by the loader from `sprite`. */
+    #[serde(skip)]
+    pub sprite_handle: Option<Handle<Image>>,
+}
+
+impl StructureCatalogEntry {
+    /** Covered cells of the unrotated footprint; see `parse_mask`. */
+    pub fn cells(&self) -> Result<Vec<UVec2>, String> {
+        let size = UVec2::from_array(self.size);
+        if size.x == 0 || size.y == 0 { return Err(format!("size {}x{} is empty", size.x, size.y)) }
+        if self.mask.is_empty() {
+            return Ok((0..size.y).flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y))).collect());
+        }
+        parse_mask(size, &self.mask)
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -20,6 +95,33 @@ pub struct TileCatalogEntry {
     pub id: String,
     pub layer: String,
     pub color: [f32; 4],
+    #[serde(default)]
+    pub sprite: Option<TileSpriteEntry>,
+    #[serde(default)]
+    pub properties: TilePropertiesEntry,
+    #[serde(default)]
+    pub rules: Vec<PlacementRule>,
+}
+
+/** Catalog `properties` object: recognized keys, plus any others kept aside so they can be reported. */
+#[derive(Clone, Default, Deserialize)]
+pub struct TilePropertiesEntry {
+    #[serde(flatten)]
+    pub known: TileProperties,
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>,
+}
+
+/** Atlas art for a catalog entry: `index` is the primary cell, `variants` are alternates picked per cell. */
+#[derive(Clone, Deserialize)]
+pub struct TileSpriteEntry {
+    pub atlas: String,
+    pub index: u32,
+    #[serde(default)]
+    pub variants: Vec<u32>,
+    /** Resolved by the loader from `atlas`. */
+    #[serde(skip)]
+    pub handle: Handle<Image>,
 }
 
 #[derive(Default)]
@@ -34,24 +136,214 @@ impl AssetLoader for TileCatalogLoader {
         &self,
         reader: &mut dyn Reader,
         _settings: &Self::Settings,
-        _load_context: &mut LoadContext<'_>,
+        load_context: &mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
+        let path = load_context.path().display().to_string();
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
-        let defs: Vec<TileCatalogEntry> = serde_json::from_slice(&bytes)?;
-        Ok(TileCatalog { defs })
+        let root: Value = serde_json::from_slice(&bytes)
+            .map_err(|err| anyhow::anyhow!("{path}: invalid catalog JSON: {err}"))?;
+        let file = if root.is_array() {
+            serde_json::from_value(root).map(|tiles| CatalogFile { materials: Vec::new(), tiles, structures: Vec::new() })
+        } else {
+            serde_json::from_value(root)
+        };
+        let CatalogFile { materials, tiles: mut defs, mut structures } = file
+            .map_err(|err| anyhow::anyhow!("{path}: invalid catalog JSON: {err}"))?;
+        let problems = validate_catalog(&materials, &defs, &structures);
+        if !problems.is_empty() {
+            anyhow::bail!("{path}: {} problem(s) in tile catalog:\n  {}", problems.len(), problems.join("\n  "));
+        }
+        let entries = defs.iter().enumerate().map(|(i, e)| (format!("entry {i}"), &e.id, &e.properties));
+        let structure_entries = structures.iter().enumerate().map(|(i, s)| (format!("structure {i}"), &s.id, &s.properties));
+        for (at, id, properties) in entries.chain(structure_entries) {
+            let mut unknown: Vec<&str> = properties.unknown.keys().map(String::as_str).collect();
+            unknown.sort_unstable();
+            for key in unknown {
+                warn!("{path}: {at} ('{id}'): unknown property '{key}' is ignored");
+            }
+        }
+        let mut atlases: HashMap<String, Handle<Image>> = HashMap::new();
+        for sprite in defs.iter_mut().filter_map(|d| d.sprite.as_mut()) {
+            sprite.handle = atlases.entry(sprite.atlas.clone())
+                .or_insert_with(|| load_context.load(sprite.atlas.clone()))
+                .clone();
+        }
+        for structure in &mut structures {
+            let Some(sprite) = &structure.sprite else { continue };
+            let handle = atlases.entry(sprite.clone()).or_insert_with(|| load_context.load(sprite.clone()));
+            structure.sprite_handle = Some(handle.clone());
+        }
+        Ok(TileCatalog { materials, defs, structures, atlases: atlases.into_values().collect() })
     }
 
     fn extensions(&self) -> &[&str] { &["json"] }
 }
 
+/**
+ * Checks every catalog entry and returns one message per problem, so a broken file can be fixed in one pass.
+ * Catches what `Tileset::from_catalog` would otherwise trip over later: empty or duplicate ids, unknown
+ * layers, colors outside 0..1, sprites without an atlas path, negative or non-finite property values,
+ * build costs in undeclared materials, empty or duplicate material ids, malformed structure footprints,
+ * and a missing `Empty` entry.
+ * Unknown property keys are only warned about by the loader.
+ * Atlas cell indices can only be checked once the art has loaded (see `rebuild_tile_textures`).
+ *
+ * @param materials - parsed material entries in file order
+ * @param defs - parsed catalog entries in file order
+ * @param structures - parsed structure entries in file order
+ */
+pub fn validate_catalog(materials: &[MaterialEntry], defs: &[TileCatalogEntry], structures: &[StructureCatalogEntry]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut declared: HashMap<&str, usize> = HashMap::new();
+    for (i, material) in materials.iter().enumerate() {
+        if material.id.trim().is_empty() {
+            problems.push(format!("material {i}: id is empty"));
+        } else if let Some(first) = declared.insert(&material.id, i) {
+            problems.push(format!("material {i} ('{}'): duplicate id, first defined at material {first}", material.id));
+        }
+    }
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (i, entry) in defs.iter().enumerate() {
+        let at = format!("entry {i} ('{}')", entry.id);
+        if entry.id.trim().is_empty() {
+            problems.push(format!("{at}: id is empty"));
+        } else if let Some(first) = seen.insert(&entry.id, i) {
+            problems.push(format!("{at}: duplicate id, first defined at entry {first}"));
+        }
+        if TileLayer::parse(&entry.layer).is_none() {
+            problems.push(format!("{at}: unknown layer '{}', expected \"Base\" or \"Overlay\"", entry.layer));
+        }
+        validate_common(&at, entry.color, &entry.properties.known, &declared, &mut problems);
+        if entry.sprite.as_ref().is_some_and(|sprite| sprite.atlas.trim().is_empty()) {
+            problems.push(format!("{at}: sprite has an empty atlas path"));
+        }
+    }
+    if !seen.contains_key("Empty") {
+        problems.push("missing required 'Empty' entry".to_string());
+    }
+    let mut seen_structures: HashMap<&str, usize> = HashMap::new();
+    for (i, structure) in structures.iter().enumerate() {
+        let at = format!("structure {i} ('{}')", structure.id);
+        if structure.id.trim().is_empty() {
+            problems.push(format!("{at}: id is empty"));
+        } else if let Some(first) = seen_structures.insert(&structure.id, i) {
+            problems.push(format!("{at}: duplicate id, first defined at structure {first}"));
+        }
+        if let Err(err) = structure.cells() { problems.push(format!("{at}: {err}")); }
+        validate_common(&at, structure.color, &structure.properties.known, &declared, &mut problems);
+        if structure.sprite.as_ref().is_some_and(|sprite| sprite.trim().is_empty()) {
+            problems.push(format!("{at}: sprite path is empty"));
+        }
+    }
+    problems
+}
+
+/** Checks shared by tiles and structures: color range, property values and build cost materials. */
+fn validate_common(at: &str, color: [f32; 4], props: &TileProperties, declared: &HashMap<&str, usize>, problems: &mut Vec<String>) {
+    if let Some(c) = color.iter().find(|c| !(0.0..=1.0).contains(*c)) {
+        problems.push(format!("{at}: color component {c} is outside 0..1"));
+    }
+    for (key, value) in [("build_time", props.build_time), ("thermal_conductivity", props.thermal_conductivity)] {
+        if !value.is_finite() || value < 0.0 {
+            problems.push(format!("{at}: property '{key}' must be a non-negative number, got {value}"));
+        }
+    }
+    for material in props.build_cost.keys().filter(|m| !declared.contains_key(m.as_str())) {
+        problems.push(format!("{at}: build_cost uses undeclared material '{material}'"));
+    }
+}
+
+/** Asset path of the tile catalog loaded at startup (relative to `assets/`). */
+pub const TILE_CATALOG_PATH: &str = "tiles/catalog.json";
+
+/** Keeps the startup catalog handle alive and identifies it among asset events. */
+#[derive(Resource)]
+pub struct TileCatalogHandle(pub Handle<TileCatalog>);
+
 pub struct CoreTilesPlugin;
 
 impl Plugin for CoreTilesPlugin {
     fn build(&self, app: &mut App) {
         app.init_asset::<TileCatalog>()
-            .init_asset_loader::<TileCatalogLoader>();
+            .init_asset_loader::<TileCatalogLoader>()
+            .add_systems(Startup, load_tile_catalog)
+            .add_systems(PreUpdate, build_tileset_from_catalog);
     }
 }
 
+fn load_tile_catalog(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(TileCatalogHandle(assets.load(TILE_CATALOG_PATH)));
+}
+
+/**
+ * Rebuilds the `Tileset` resource when the catalog finishes loading or is modified on disk (hot reload with
+ * the `dev` feature). Gameplay and render code only ever see interned `TileId`s from this tileset, so placed
+ * tiles and structures are re-interned by name and the map is marked fully dirty to re-tint them. Newly declared materials
+ * are added to the `Inventory` at their starting amount.
+ * A catalog that fails validation, drops a tile or structure still placed on the map, or changes the footprint
+ * (`size` or `mask`) of a placed structure never replaces the tileset, so the last valid one stays active.
+ */
+fn build_tileset_from_catalog(
+    mut events: MessageReader<AssetEvent<TileCatalog>>,
+    mut failures: MessageReader<AssetLoadFailedEvent<TileCatalog>>,
+    handle: Option<Res<TileCatalogHandle>>,
+    catalogs: Res<Assets<TileCatalog>>,
+    mut tileset: ResMut<Tileset>,
+    mut map: ResMut<MapState>,
+    mut inventory: ResMut<Inventory>,
+) {
+    let Some(handle) = handle else { return };
+    for failed in failures.read() {
+        if failed.id != handle.0.id() { continue }
+        warn!("tile catalog {} rejected; keeping the previous tileset ({} tiles)", failed.path, tileset.defs.len());
+    }
+    let changed = events.read().any(|ev| matches!(ev,
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } if *id == handle.0.id()));
+    if !changed { return }
+    let Some(catalog) = catalogs.get(&handle.0) else { return };
+    let built = match Tileset::from_catalog(catalog) {
+        Ok(built) => built,
+        Err(err) => { error!("failed to build tileset from {TILE_CATALOG_PATH}: {err}"); return }
+    };
 
+    let remap: Vec<Option<TileId>> = tileset.defs.iter().map(|def| built.id(&def.name)).collect();
+    let remap_structures: Vec<Option<StructureId>> = tileset.structures.iter().map(|def| built.structure_id(&def.name)).collect();
+    let mut missing: Vec<&str> = map.tiles_in_use().into_iter()
+        .filter(|id| remap[id.index()].is_none())
+        .map(|id| tileset.def(id).name.as_str())
+        .chain(map.structures_in_use().into_iter()
+            .filter(|id| remap_structures[id.index()].is_none())
+            .map(|id| tileset.structure(id).name.as_str()))
+        .collect();
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        error!("{TILE_CATALOG_PATH} removes tiles or structures still placed on the map ({}); keeping the previous tileset", missing.join(", "));
+        return;
+    }
+    // Placed parts only record their offset, so a structure's covered cells must stay where they are.
+    let mut reshaped: Vec<&str> = map.structures_in_use().into_iter()
+        .filter_map(|id| remap_structures[id.index()].map(|new| (tileset.structure(id), built.structure(new))))
+        .filter(|(old, new)| old.size != new.size || old.cells != new.cells)
+        .map(|(old, _)| old.name.as_str())
+        .collect();
+    if !reshaped.is_empty() {
+        reshaped.sort_unstable();
+        error!("{TILE_CATALOG_PATH} changes the footprint of structures still placed on the map ({}); keeping the previous tileset", reshaped.join(", "));
+        return;
+    }
+    map.remap_tiles(|id| remap[id.index()].unwrap_or(TileId::EMPTY));
+    map.remap_structures(|id| remap_structures[id.index()].unwrap_or(id));
+    inventory.stock_materials(&built.materials);
+    info!("tile catalog loaded: {} tiles", built.defs.len());
+    *tileset = built;
+}
+
+/** Builds a tileset from catalog JSON in the object form, without loading any art. */
+#[cfg(test)]
+pub(crate) fn test_tileset(json: &str) -> Tileset {
+    let file: CatalogFile = serde_json::from_str(json).expect("test catalog parses");
+    assert_eq!(validate_catalog(&file.materials, &file.tiles, &file.structures), Vec::<String>::new());
+    let catalog = TileCatalog { materials: file.materials, defs: file.tiles, structures: file.structures, atlases: Vec::new() };
+    Tileset::from_catalog(&catalog).expect("test catalog builds")
+}
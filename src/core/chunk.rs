@@ -0,0 +1,198 @@
+/**
+ * Chunked cell storage shared by map-sized layers (`MapState`, `PipeMap`).
+ * Cells are grouped into fixed `CHUNK_SIZE` squares that are only allocated on the first write of a
+ * non-default value, and writers record which cells changed so consumers can skip untouched regions.
+ */
+use bevy::prelude::*;
+
+/** Edge length, in cells, of one storage chunk. Render chunks use the same size. */
+pub const CHUNK_SIZE: u32 = 16;
+
+/** Chunk coordinate containing cell (x,y). */
+pub fn chunk_of(x: u32, y: u32) -> UVec2 { UVec2::new(x / CHUNK_SIZE, y / CHUNK_SIZE) }
+
+/** Number of chunks needed to cover a `w` x `h` map. */
+pub fn chunk_dims(w: u32, h: u32) -> UVec2 { UVec2::new(w.div_ceil(CHUNK_SIZE), h.div_ceil(CHUNK_SIZE)) }
+
+/**
+ * One lazily allocated layer of `T` per cell. Unallocated chunks read as `default`.
+ * Invariant: `chunks.len() == dims.x * dims.y`; allocated chunks hold `CHUNK_SIZE^2` cells.
+ */
+#[derive(Clone)]
+pub struct ChunkedLayer<T> {
+    w: u32,
+    h: u32,
+    dims: UVec2,
+    default: T,
+    chunks: Vec<Option<Box<[T]>>>,
+}
+
+impl<T: Copy + PartialEq> ChunkedLayer<T> {
+    /** A `w` x `h` layer reading `default` everywhere, with no chunk allocated yet. */
+    pub fn new(w: u32, h: u32, default: T) -> Self {
+        let dims = chunk_dims(w, h);
+        Self { w, h, dims, default, chunks: vec![None; (dims.x * dims.y) as usize] }
+    }
+
+    fn slot(&self, x: u32, y: u32) -> (usize, usize) {
+        let c = chunk_of(x, y);
+        let chunk = (c.y * self.dims.x + c.x) as usize;
+        let cell = ((y % CHUNK_SIZE) * CHUNK_SIZE + (x % CHUNK_SIZE)) as usize;
+        (chunk, cell)
+    }
+
+    /** Value of in-bounds cell (x,y); `default` while its chunk is unallocated. */
+    pub fn get(&self, x: u32, y: u32) -> T {
+        debug_assert!(x < self.w && y < self.h);
+        let (chunk, cell) = self.slot(x, y);
+        self.chunks[chunk].as_ref().map_or(self.default, |c| c[cell])
+    }
+
+    /** Writes a cell, allocating its chunk if needed. Returns whether the value changed. */
+    pub fn set(&mut self, x: u32, y: u32, val: T) -> bool {
+        debug_assert!(x < self.w && y < self.h);
+        let (chunk, cell) = self.slot(x, y);
+        let default = self.default;
+        let data = match &mut self.chunks[chunk] {
+            Some(data) => data,
+            None if val == default => return false,
+            slot => slot.insert(vec![default; (CHUNK_SIZE * CHUNK_SIZE) as usize].into_boxed_slice()),
+        };
+        let changed = data[cell] != val;
+        data[cell] = val;
+        changed
+    }
+
+    /** Values of every allocated chunk, including padding past the map edge; unallocated chunks are all `default`. */
+    pub fn allocated_values(&self) -> impl Iterator<Item = T> + '_ {
+        self.chunks.iter().flatten().flat_map(|c| c.iter().copied())
+    }
+
+    /** Every in-bounds cell holding a value other than `default` as `(x, y, value)`, chunk by chunk, row by row within a chunk. */
+    pub fn non_default(&self) -> impl Iterator<Item = (u32, u32, T)> + '_ {
+        self.chunks.iter().enumerate().filter_map(|(i, c)| Some((i as u32, c.as_ref()?))).flat_map(move |(i, cells)| {
+            let origin = UVec2::new(i % self.dims.x, i / self.dims.x) * CHUNK_SIZE;
+            cells.iter().enumerate().filter_map(move |(j, &val)| {
+                let (x, y) = (origin.x + j as u32 % CHUNK_SIZE, origin.y + j as u32 / CHUNK_SIZE);
+                (val != self.default && x < self.w && y < self.h).then_some((x, y, val))
+            })
+        })
+    }
+
+    /** Rewrites every allocated cell through `f`, which must map `default` to itself. */
+    pub fn map_in_place(&mut self, f: impl Fn(T) -> T) {
+        for cell in self.chunks.iter_mut().flatten().flat_map(|c| c.iter_mut()) { *cell = f(*cell); }
+    }
+}
+
+/**
+ * Set of cells modified since the last `take`, deduplicated via a per-cell flag.
+ * Owned by the layer container; each dirty set has exactly one consumer.
+ */
+#[derive(Clone)]
+pub struct DirtyCells {
+    w: u32,
+    h: u32,
+    flags: ChunkedLayer<bool>,
+    list: Vec<UVec2>,
+    all: bool,
+}
+
+impl DirtyCells {
+    /** An empty dirty set for a `w` x `h` layer. */
+    pub fn new(w: u32, h: u32) -> Self {
+        Self { w, h, flags: ChunkedLayer::new(w, h, false), list: Vec::new(), all: false }
+    }
+
+    /** Marks (x,y) dirty; a cell already marked, or any cell while all are dirty, is not listed again. */
+    pub fn mark_cell(&mut self, x: u32, y: u32) {
+        if !self.all && self.flags.set(x, y, true) { self.list.push(UVec2::new(x, y)); }
+    }
+
+    /** Marks every cell dirty, e.g. after a load replaced the whole layer. */
+    pub fn mark_all(&mut self) {
+        self.all = true;
+        self.list.clear();
+        self.flags = ChunkedLayer::new(self.w, self.h, false);
+    }
+
+    /** Returns and clears the dirty cells: in first-modified order, or every cell row by row when all are dirty. */
+    pub fn take(&mut self) -> Vec<UVec2> {
+        if std::mem::take(&mut self.all) {
+            return (0..self.h).flat_map(|y| (0..self.w).map(move |x| UVec2::new(x, y))).collect();
+        }
+        for c in &self.list { self.flags.set(c.x, c.y, false); }
+        std::mem::take(&mut self.list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_allocated_on_the_first_non_default_write() {
+        let mut layer = ChunkedLayer::new(40, 20, 0u8);
+        assert!(!layer.set(3, 3, 0), "writing the default changes nothing");
+        assert_eq!(layer.allocated_values().count(), 0);
+        assert!(layer.set(20, 3, 7));
+        assert!(!layer.set(20, 3, 7), "rewriting the same value is no change");
+        assert_eq!(layer.allocated_values().count(), (CHUNK_SIZE * CHUNK_SIZE) as usize, "only the written cell's chunk");
+        assert_eq!(layer.get(20, 3), 7);
+        assert_eq!(layer.get(3, 3), 0);
+        assert!(layer.set(20, 3, 0), "a cell can be reset in an allocated chunk");
+        assert_eq!(layer.get(20, 3), 0);
+    }
+
+    #[test]
+    fn padding_past_the_map_edge_is_not_reported() {
+        // 20 x 20 cells span 2 x 2 chunks; the last chunk is mostly padding.
+        let mut layer = ChunkedLayer::new(20, 20, 0u8);
+        layer.set(19, 19, 1);
+        layer.map_in_place(|v| if v == 0 { 0 } else { v + 1 });
+        assert_eq!(layer.allocated_values().filter(|&v| v != 0).count(), 1);
+        assert_eq!(layer.allocated_values().count(), (CHUNK_SIZE * CHUNK_SIZE) as usize, "padding is allocated with the chunk");
+        assert_eq!(layer.non_default().collect::<Vec<_>>(), [(19, 19, 2)]);
+    }
+
+    #[test]
+    fn non_default_lists_cells_chunk_by_chunk() {
+        let mut layer = ChunkedLayer::new(40, 40, 0u8);
+        for (x, y, v) in [(17, 0, 3), (1, 2, 1), (0, 20, 4), (5, 0, 2)] { layer.set(x, y, v); }
+        layer.set(1, 2, 0);
+        assert_eq!(layer.non_default().collect::<Vec<_>>(), [(5, 0, 2), (17, 0, 3), (0, 20, 4)]);
+    }
+
+    #[test]
+    fn map_in_place_rewrites_allocated_cells() {
+        let mut layer = ChunkedLayer::new(40, 4, 0u8);
+        layer.set(2, 1, 3);
+        layer.set(30, 2, 5);
+        layer.map_in_place(|v| v * 2);
+        assert_eq!((layer.get(2, 1), layer.get(30, 2), layer.get(10, 1)), (6, 10, 0));
+    }
+
+    #[test]
+    fn dirty_cells_are_listed_once_in_first_modified_order() {
+        let mut dirty = DirtyCells::new(40, 40);
+        for (x, y) in [(3, 4), (30, 1), (3, 4), (0, 0), (30, 1)] { dirty.mark_cell(x, y); }
+        assert_eq!(dirty.take(), [UVec2::new(3, 4), UVec2::new(30, 1), UVec2::new(0, 0)]);
+        assert!(dirty.take().is_empty(), "take clears the set");
+        dirty.mark_cell(3, 4);
+        assert_eq!(dirty.take(), [UVec2::new(3, 4)], "a taken cell can be marked again");
+    }
+
+    #[test]
+    fn mark_all_lists_every_cell_once() {
+        let mut dirty = DirtyCells::new(3, 2);
+        dirty.mark_cell(1, 1);
+        dirty.mark_all();
+        dirty.mark_cell(2, 0);
+        let all = dirty.take();
+        assert_eq!(all.len(), 6);
+        assert_eq!((all[0], all[5]), (UVec2::new(0, 0), UVec2::new(2, 1)), "row by row");
+        assert!(dirty.take().is_empty());
+        dirty.mark_cell(1, 1);
+        assert_eq!(dirty.take(), [UVec2::new(1, 1)], "marks made before mark_all were dropped with it");
+    }
+}
@@ -0,0 +1,449 @@
+/**
+ * The single authoritative path for map edits. Tools write `TileEdit`s; `apply_tile_edits`
+ * validates them against the current map, tileset, `Inventory` and the installed `PlacementCheck`, writes
+ * `MapState`/`PipeMap` and the ports in `FluidState`, settles build costs, records them in the undo history and reports every real change as a `TileChanged`
+ * (or an `EditRejected` with the reason). Finished construction (`ConstructTile`) takes the same path but is
+ * not recorded. `grow_near_edits` then requests growth when something was built
+ * near an edge.
+ */
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use crate::core::fluids::FluidState;
+use crate::core::events::{CellContent, CellLayer, ConstructTile, EditRejected, GrowMap, PlaceStructure, PlaceTile, RemoveTile, TileChanged, TileEdit};
+use crate::core::history::EditHistory;
+use crate::core::inventory::{refund_share, Inventory, MaterialCost, REFUND_FRACTION};
+use crate::core::map::MapState;
+use crate::core::pipes::{PipeCell, PipeMap, UtilityKind};
+use crate::core::structure::StructurePart;
+use crate::core::tile::{TileId, TileLayer, Tileset};
+
+/**
+ * Gameplay legality check for placements, installed by `gameplay::rules`. Core calls it after its own
+ * structural checks (bounds, known tile, riser ends), so it may assume the cell and tile are valid.
+ * Returns the reason a placement is illegal.
+ */
+#[derive(Resource, Clone, Copy)]
+pub struct PlacementCheck(pub fn(&MapState, &PipeMap, &Tileset, &PlaceTile) -> Result<(), String>);
+
+/**
+ * Everything an edit reads or writes: the map layers, the fluid ports, the tileset, the stock that pays for
+ * builds and the installed placement rules.
+ */
+#[derive(SystemParam)]
+pub struct EditTarget<'w> {
+    pub map: ResMut<'w, MapState>,
+    pub pipes: ResMut<'w, PipeMap>,
+    pub fluids: ResMut<'w, FluidState>,
+    pub tileset: Res<'w, Tileset>,
+    pub inventory: ResMut<'w, Inventory>,
+    pub check: Option<Res<'w, PlacementCheck>>,
+}
+
+/** This frame's edit requests: tool edits and finished construction. */
+#[derive(SystemParam)]
+pub struct EditRequests<'w, 's> {
+    edits: MessageReader<'w, 's, TileEdit>,
+    constructions: MessageReader<'w, 's, ConstructTile>,
+}
+
+/**
+ * Applies this frame's edit requests in `MapSet::Edit`, before any growth they trigger.
+ * Tool edits are applied in the order they were written, so later edits see earlier ones (a riser sees
+ * the pipes placed just before it, a removal clears what was placed before it); finished construction
+ * comes last. Illegal or unaffordable requests are dropped and reported as `EditRejected`.
+ *
+ * @param requests - removals, placements and structures from tools, finished blueprints from construction
+ * @param changed - one notification per cell layer that actually changed
+ * @param rejected - one notification per dropped request, with the reason
+ * @param target - map layers, tileset, inventory and placement rules
+ * @param history - receives the applied tool edits as (part of) an undo step
+ */
+pub fn apply_tile_edits(
+    mut requests: EditRequests,
+    mut changed: MessageWriter<TileChanged>,
+    mut rejected: MessageWriter<EditRejected>,
+    mut target: EditTarget,
+    mut history: ResMut<EditHistory>,
+) {
+    let EditRequests { edits, constructions } = &mut requests;
+    if edits.is_empty() && constructions.is_empty() { return }
+    let EditTarget { map, pipes, fluids, tileset, inventory, check } = &mut target;
+    let check = check.as_deref().copied();
+    let mut editor = MapEditor::new(map, pipes, fluids, tileset, inventory);
+
+    for edit in edits.read() {
+        let (level, x, y, applied) = match edit {
+            TileEdit::Remove(e) => (e.level, e.x, e.y, editor.remove(e)),
+            TileEdit::Place(e) => (e.level, e.x, e.y, editor.place(check, e)),
+            TileEdit::Structure(e) => (e.level, e.x, e.y, editor.place_structure(check, e)),
+        };
+        if let Err(reason) = applied { rejected.write(EditRejected { level, x, y, reason }); }
+    }
+    history.record(&editor.changes);
+    for edit in constructions.read() {
+        if let Err(reason) = editor.construct(check, edit) {
+            rejected.write(EditRejected { level: edit.level, x: edit.x, y: edit.y, reason });
+        }
+    }
+    changed.write_batch(editor.changes);
+}
+
+/**
+ * Requests map growth for every cell that received content this frame within `AUTO_EXPAND_MARGIN` of an edge.
+ * Runs last in `MapSet::Edit`, so it sees edits and undo/redo alike, before `apply_map_growth`.
+ */
+pub fn grow_near_edits(mut changed: MessageReader<TileChanged>, map: Res<MapState>, mut grow: MessageWriter<GrowMap>) {
+    for ev in changed.read() {
+        if ev.after.is_none() { continue }
+        if let Some(growth) = map.edge_growth(ev.x, ev.y) { grow.write(GrowMap { growth }); }
+    }
+}
+
+/** Layer a placed `content` goes to; catalog tiles use the layer their `TileDef` declares. */
+pub fn content_layer(tileset: &Tileset, content: CellContent) -> CellLayer {
+    match content {
+        CellContent::Tile(id) => match tileset.def(id).layer {
+            TileLayer::Base => CellLayer::Base,
+            TileLayer::Overlay => CellLayer::Overlay,
+        },
+        CellContent::Planned(id) => match tileset.def(id).layer {
+            TileLayer::Base => CellLayer::PlannedBase,
+            TileLayer::Overlay => CellLayer::PlannedOverlay,
+        },
+        CellContent::Structure(_) => CellLayer::Structure,
+        CellContent::Pipe(kind) => CellLayer::Pipe(kind),
+        CellContent::Riser(kind) => CellLayer::Riser(kind),
+        CellContent::Component(kind, _) => CellLayer::Component(kind),
+        CellContent::Port(kind, _) => CellLayer::Port(kind),
+    }
+}
+
+/**
+ * Current contents of one map cell layer; `None` when it is empty (a base cell holding `Empty`). Ports
+ * live in `FluidState`, not the map, so a `Port` layer always reads `None` here; `MapEditor` reads them.
+ */
+pub fn cell_content(map: &MapState, pipes: &PipeMap, level: u32, x: u32, y: u32, layer: CellLayer) -> Option<CellContent> {
+    match layer {
+        CellLayer::Base => Some(map.get_base(level, x, y)).filter(|&id| id != TileId::EMPTY).map(CellContent::Tile),
+        CellLayer::Overlay => map.get_overlay(level, x, y).map(CellContent::Tile),
+        CellLayer::PlannedBase => map.get_planned(level, x, y, TileLayer::Base).map(CellContent::Planned),
+        CellLayer::PlannedOverlay => map.get_planned(level, x, y, TileLayer::Overlay).map(CellContent::Planned),
+        CellLayer::Structure => map.get_structure(level, x, y).map(CellContent::Structure),
+        CellLayer::Pipe(kind) => pipes.has(kind, level, x, y).then_some(CellContent::Pipe(kind)),
+        CellLayer::Riser(kind) => pipes.riser(kind, level, x, y).then_some(CellContent::Riser(kind)),
+        CellLayer::Component(kind) => pipes.component(kind, level, x, y).map(|c| CellContent::Component(kind, c)),
+        CellLayer::Port(_) => None,
+    }
+}
+
+/**
+ * Reads and writes cell layers across `MapState`, `PipeMap` and the ports in `FluidState`, recording each
+ * change and settling its build cost with the `Inventory`.
+ */
+pub(crate) struct MapEditor<'a> {
+    map: &'a mut MapState,
+    pipes: &'a mut PipeMap,
+    fluids: &'a mut FluidState,
+    tileset: &'a Tileset,
+    inventory: &'a mut Inventory,
+    pub changes: Vec<TileChanged>,
+}
+
+impl<'a> MapEditor<'a> {
+    pub fn new(map: &'a mut MapState, pipes: &'a mut PipeMap, fluids: &'a mut FluidState, tileset: &'a Tileset, inventory: &'a mut Inventory) -> Self {
+        Self { map, pipes, fluids, tileset, inventory, changes: Vec::new() }
+    }
+
+    /** Current contents of one cell layer, ports included (see `cell_content`). */
+    fn content(&self, level: u32, x: u32, y: u32, layer: CellLayer) -> Option<CellContent> {
+        match layer {
+            CellLayer::Port(kind) => self.fluids.port(PipeCell::new(kind, level, x, y)).map(|port| CellContent::Port(kind, port)),
+            _ => cell_content(self.map, self.pipes, level, x, y, layer),
+        }
+    }
+
+    /** What `content` costs to build; a structure's cost is carried by its primary cell, pipes and risers are free. */
+    fn cost(tileset: &Tileset, content: CellContent) -> Option<&MaterialCost> {
+        match content {
+            CellContent::Tile(id) | CellContent::Planned(id) => Some(&tileset.props(id).build_cost),
+            CellContent::Structure(part) => {
+                let def = tileset.structure(part.structure);
+                (part.offset == def.primary(part.rotation)).then_some(&def.props.build_cost)
+            }
+            CellContent::Pipe(_) | CellContent::Riser(_) | CellContent::Component(..) | CellContent::Port(..) => None,
+        }
+    }
+
+    fn check_cell(&self, level: u32, x: u32, y: u32) -> Result<(), String> {
+        if level >= self.map.levels() { return Err(format!("deck {level} does not exist")) }
+        if x >= self.map.size.w || y >= self.map.size.h { return Err(format!("cell ({x},{y}) is outside the map")) }
+        Ok(())
+    }
+
+    /** Whether tile `id` is already built on its layer at (x,y). */
+    fn is_built(&self, level: u32, x: u32, y: u32, id: TileId) -> bool {
+        let built = CellContent::Tile(id);
+        self.content(level, x, y, content_layer(self.tileset, built)) == Some(built)
+    }
+
+    fn remove(&mut self, edit: &RemoveTile) -> Result<(), String> {
+        let RemoveTile { level, x, y, layer } = *edit;
+        self.check_cell(level, x, y)?;
+        if matches!(layer, CellLayer::Riser(_)) && level + 1 >= self.map.levels() { return Err(format!("no deck above {level}")) }
+        if let CellLayer::Pipe(kind) = layer {
+            // Report the component and risers that go with the pipe so consumers see every change.
+            self.write(level, x, y, CellLayer::Component(kind), None)?;
+            self.write(level, x, y, CellLayer::Riser(kind), None)?;
+            if level > 0 { self.write(level - 1, x, y, CellLayer::Riser(kind), None)?; }
+        }
+        if layer == CellLayer::Structure && let Some(part) = self.map.get_structure(level, x, y) {
+            let anchor = UVec2::new(x, y) - part.offset;
+            let def = self.tileset.structure(part.structure);
+            for cell in def.footprint(part.rotation).map(|offset| anchor + offset) {
+                self.write(level, cell.x, cell.y, layer, None)?;
+            }
+        }
+        self.write(level, x, y, layer, None)
+    }
+
+    fn place(&mut self, check: Option<PlacementCheck>, edit: &PlaceTile) -> Result<(), String> {
+        let PlaceTile { level, x, y, content } = *edit;
+        let tileset = self.tileset;
+        self.check_cell(level, x, y)?;
+        match content {
+            CellContent::Tile(TileId::EMPTY) | CellContent::Planned(TileId::EMPTY) => {
+                return Err("cannot place Empty; remove the tile instead".into());
+            }
+            CellContent::Tile(id) | CellContent::Planned(id) if id.index() >= tileset.defs.len() => {
+                return Err(format!("unknown tile id {}", id.0));
+            }
+            CellContent::Planned(id) if self.is_built(level, x, y, id) => {
+                return Err(format!("{} is already built here", tileset.def(id).name));
+            }
+            CellContent::Structure(_) => return Err("structures are placed whole with PlaceStructure".into()),
+            CellContent::Riser(_) if level + 1 >= self.map.levels() => return Err(format!("no deck above {level}")),
+            CellContent::Riser(kind) if !self.pipes.has(kind, level, x, y) || !self.pipes.has(kind, level + 1, x, y) => {
+                return Err(format!("{} riser needs a {} segment on decks {level} and {}", kind.name(), kind.name(), level + 1));
+            }
+            CellContent::Component(kind, component) if !self.pipes.has(kind, level, x, y) => {
+                return Err(format!("a {} needs a {} to fit into", component.name(), kind.segment_name()));
+            }
+            CellContent::Component(kind, component)
+                if (component.needs_fluid() && !kind.carries_fluid()) || (component.needs_gas() && kind != UtilityKind::Gas) => {
+                return Err(format!("a {} can't be fitted into a {}", component.name(), kind.segment_name()));
+            }
+            CellContent::Port(kind, _) if !kind.carries_fluid() => return Err(format!("a port can't be attached to a {}", kind.segment_name())),
+            CellContent::Port(kind, _) if !self.pipes.has(kind, level, x, y) => {
+                return Err(format!("a port needs a {} to attach to", kind.segment_name()));
+            }
+            _ => {}
+        }
+        let layer = content_layer(tileset, content);
+        if let Some(PlacementCheck(check)) = check { check(self.map, self.pipes, tileset, edit)?; }
+        if let Some(shortfall) = Self::cost(tileset, content).and_then(|cost| self.inventory.shortfall(cost)) { return Err(shortfall) }
+        self.write(level, x, y, layer, Some(content))
+    }
+
+    /** Swaps a blueprint for the tile it plans, if the built tile passes the placement rules now. */
+    fn construct(&mut self, check: Option<PlacementCheck>, edit: &ConstructTile) -> Result<(), String> {
+        let ConstructTile { level, x, y, layer } = *edit;
+        self.check_cell(level, x, y)?;
+        let Some(id) = self.map.get_planned(level, x, y, layer) else { return Err("no blueprint to build here".into()) };
+        let built = PlaceTile { level, x, y, content: CellContent::Tile(id) };
+        if let Some(PlacementCheck(check)) = check { check(self.map, self.pipes, self.tileset, &built)?; }
+        let planned = content_layer(self.tileset, CellContent::Planned(id));
+        self.write(level, x, y, planned, None)?;
+        self.write(level, x, y, content_layer(self.tileset, built.content), Some(built.content))
+    }
+
+    /**
+     * Writes a recorded history step back: its `after` values in order, settling each change as the edit
+     * did, or its `before` values in reverse order when undoing, reversing exactly what each change charged
+     * and refunded. The whole step is refused, with nothing written, when the stock can't pay for it.
+     */
+    pub fn restore(&mut self, step: &[TileChanged], undo: bool) -> Result<(), EditRejected> {
+        let writes: Vec<(&TileChanged, Option<CellContent>)> = match undo {
+            true => step.iter().rev().map(|c| (c, c.before)).collect(),
+            false => step.iter().map(|c| (c, c.after)).collect(),
+        };
+        let mut stock = self.inventory.clone();
+        for &(c, _) in &writes {
+            let settled = match undo {
+                true => Self::unsettle(self.tileset, &mut stock, c.before, c.after),
+                false => Self::settle(self.tileset, &mut stock, c.before, c.after),
+            };
+            settled.map_err(|reason| EditRejected { level: c.level, x: c.x, y: c.y, reason })?;
+        }
+        *self.inventory = stock;
+        for (c, after) in writes { self.set(c.level, c.x, c.y, c.layer, after); }
+        Ok(())
+    }
+
+    /** Places every cell of a structure after checking all of them, so a structure is never left half-built. */
+    fn place_structure(&mut self, check: Option<PlacementCheck>, edit: &PlaceStructure) -> Result<(), String> {
+        let PlaceStructure { level, x, y, structure, rotation } = *edit;
+        let tileset = self.tileset;
+        if structure.index() >= tileset.structures.len() { return Err(format!("unknown structure id {}", structure.0)) }
+        let def = tileset.structure(structure);
+        let anchor = UVec2::new(x, y);
+        let mut parts = Vec::with_capacity(def.cells.len());
+        for offset in def.footprint(rotation) {
+            let cell = anchor + offset;
+            self.check_cell(level, cell.x, cell.y)?;
+            if let Some(other) = self.map.get_structure(level, cell.x, cell.y) {
+                return Err(format!("cell ({},{}) is taken by a {}", cell.x, cell.y, tileset.structure(other.structure).name));
+            }
+            let content = CellContent::Structure(StructurePart { structure, rotation, offset });
+            let place = PlaceTile { level, x: cell.x, y: cell.y, content };
+            if let Some(PlacementCheck(check)) = check { check(self.map, self.pipes, tileset, &place)?; }
+            parts.push(place);
+        }
+        if let Some(shortfall) = self.inventory.shortfall(&def.props.build_cost) { return Err(shortfall) }
+        for PlaceTile { level, x, y, content } in parts { self.write(level, x, y, CellLayer::Structure, Some(content))?; }
+        Ok(())
+    }
+
+    /**
+     * Settles a layer change with `stock`: the content leaving is refunded (a blueprint in full, a built
+     * tile partially), then the content arriving is charged. Fails, possibly after refunding, when the
+     * stock can't pay.
+     */
+    fn settle(tileset: &Tileset, stock: &mut Inventory, before: Option<CellContent>, after: Option<CellContent>) -> Result<(), String> {
+        if let Some(content) = before && let Some(cost) = Self::cost(tileset, content) { stock.refund(cost, Self::refund_fraction(content)); }
+        match after.and_then(|content| Self::cost(tileset, content)) {
+            Some(cost) => stock.charge(cost),
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * Exactly reverses what `settle` did for a change from `before` to `after`: the content that arrived is
+     * refunded in full, then what the content that left returned is charged back. Fails, possibly after
+     * refunding, when the stock no longer holds that.
+     */
+    fn unsettle(tileset: &Tileset, stock: &mut Inventory, before: Option<CellContent>, after: Option<CellContent>) -> Result<(), String> {
+        if let Some(cost) = after.and_then(|content| Self::cost(tileset, content)) { stock.refund(cost, 1.0); }
+        match before.and_then(|content| Self::cost(tileset, content).map(|cost| refund_share(cost, Self::refund_fraction(content)))) {
+            Some(returned) => stock.charge(&returned),
+            None => Ok(()),
+        }
+    }
+
+    /** Share of its cost that leaving content returns: all of it for a blueprint, `REFUND_FRACTION` for a built tile. */
+    fn refund_fraction(content: CellContent) -> f32 {
+        if matches!(content, CellContent::Planned(_)) { 1.0 } else { REFUND_FRACTION }
+    }
+
+    /**
+     * Writes one cell layer unvalidated and records the change; a no-op write records nothing. Its cost is
+     * settled first (see `settle`); when the stock can't pay, nothing is written.
+     */
+    pub fn write(&mut self, level: u32, x: u32, y: u32, layer: CellLayer, after: Option<CellContent>) -> Result<(), String> {
+        let before = self.content(level, x, y, layer);
+        if before == after { return Ok(()) }
+        let mut stock = self.inventory.clone();
+        Self::settle(self.tileset, &mut stock, before, after)?;
+        *self.inventory = stock;
+        self.set(level, x, y, layer, after);
+        Ok(())
+    }
+
+    /** Writes one cell layer without settling its cost and records the change; a no-op write records nothing. */
+    fn set(&mut self, level: u32, x: u32, y: u32, layer: CellLayer, after: Option<CellContent>) {
+        let before = self.content(level, x, y, layer);
+        if before == after { return }
+        let tile = match after { Some(CellContent::Tile(id) | CellContent::Planned(id)) => Some(id), _ => None };
+        match layer {
+            CellLayer::Base => self.map.set_base(level, x, y, tile.unwrap_or(TileId::EMPTY)),
+            CellLayer::Overlay => self.map.set_overlay(level, x, y, tile),
+            CellLayer::PlannedBase => self.map.set_planned(level, x, y, TileLayer::Base, tile),
+            CellLayer::PlannedOverlay => self.map.set_planned(level, x, y, TileLayer::Overlay, tile),
+            CellLayer::Structure => {
+                let part = match after { Some(CellContent::Structure(part)) => Some(part), _ => None };
+                self.map.set_structure(level, x, y, part);
+            }
+            CellLayer::Pipe(kind) => self.pipes.set(kind, level, x, y, after.is_some()),
+            CellLayer::Riser(kind) => self.pipes.set_riser(kind, level, x, y, after.is_some()),
+            CellLayer::Component(kind) => {
+                let component = match after { Some(CellContent::Component(_, component)) => Some(component), _ => None };
+                self.pipes.set_component(kind, level, x, y, component);
+            }
+            CellLayer::Port(kind) => {
+                let cell = PipeCell::new(kind, level, x, y);
+                match after { Some(CellContent::Port(_, port)) => self.fluids.attach_port(cell, port), _ => { self.fluids.detach_port(cell); } }
+            }
+        }
+        self.changes.push(TileChanged { level, x, y, layer, before, after });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::fluids::{Fluid, FluidPort};
+    use crate::core::map::MapSize;
+
+    const GAS: UtilityKind = UtilityKind::Gas;
+
+    /** An app running only `apply_tile_edits` on an empty 4x4 single-deck map. */
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_message::<TileEdit>()
+            .add_message::<ConstructTile>()
+            .add_message::<TileChanged>()
+            .add_message::<EditRejected>()
+            .insert_resource(MapState::new(MapSize { w: 4, h: 4 }, 1))
+            .insert_resource(PipeMap::new((4, 4), 1))
+            .init_resource::<FluidState>()
+            .init_resource::<Tileset>()
+            .init_resource::<Inventory>()
+            .init_resource::<EditHistory>()
+            .add_systems(Update, apply_tile_edits);
+        app
+    }
+
+    fn changes(app: &App) -> Vec<TileChanged> {
+        app.world().resource::<Messages<TileChanged>>().iter_current_update_messages().copied().collect()
+    }
+
+    #[test]
+    fn edits_apply_in_the_order_they_were_written() {
+        let mut app = app();
+        app.world_mut().write_message_batch::<TileEdit>([
+            PlaceTile { level: 0, x: 1, y: 1, content: CellContent::Pipe(GAS) }.into(),
+            RemoveTile { level: 0, x: 1, y: 1, layer: CellLayer::Pipe(GAS) }.into(),
+            RemoveTile { level: 0, x: 2, y: 1, layer: CellLayer::Pipe(GAS) }.into(),
+            PlaceTile { level: 0, x: 2, y: 1, content: CellContent::Pipe(GAS) }.into(),
+        ]);
+        app.update();
+        let pipes = app.world().resource::<PipeMap>();
+        assert!(!pipes.has(GAS, 0, 1, 1), "a removal written after a placement clears it");
+        assert!(pipes.has(GAS, 0, 2, 1), "a placement written after a removal stays");
+        let changed: Vec<_> = changes(&app).iter().map(|c| (c.x, c.after)).collect();
+        assert_eq!(changed, [(1, Some(CellContent::Pipe(GAS))), (1, None), (2, Some(CellContent::Pipe(GAS)))]);
+    }
+
+    #[test]
+    fn ports_attach_through_the_edit_pipeline() {
+        let mut app = app();
+        let port = FluidPort { rate: 1.0, fluid: Fluid::Water };
+        app.world_mut().write_message_batch::<TileEdit>([
+            PlaceTile { level: 0, x: 1, y: 1, content: CellContent::Port(GAS, port) }.into(),
+            PlaceTile { level: 0, x: 2, y: 1, content: CellContent::Pipe(GAS) }.into(),
+            PlaceTile { level: 0, x: 2, y: 1, content: CellContent::Port(GAS, port) }.into(),
+            PlaceTile { level: 0, x: 2, y: 1, content: CellContent::Port(UtilityKind::Power, port) }.into(),
+        ]);
+        app.update();
+        let fluids = app.world().resource::<FluidState>();
+        assert_eq!(fluids.port(PipeCell::new(GAS, 0, 1, 1)), None, "a port needs a pipe");
+        assert_eq!(fluids.port(PipeCell::new(GAS, 0, 2, 1)), Some(port));
+        assert_eq!(app.world().resource::<Messages<EditRejected>>().iter_current_update_messages().count(), 2);
+        let last = *changes(&app).last().unwrap();
+        assert_eq!((last.layer, last.before, last.after), (CellLayer::Port(GAS), None, Some(CellContent::Port(GAS, port))));
+
+        app.world_mut().write_message::<TileEdit>(RemoveTile { level: 0, x: 2, y: 1, layer: CellLayer::Port(GAS) }.into());
+        app.update();
+        assert_eq!(app.world().resource::<FluidState>().port(PipeCell::new(GAS, 0, 2, 1)), None);
+        assert_eq!(changes(&app)[0].before, Some(CellContent::Port(GAS, port)));
+    }
+}
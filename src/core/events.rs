@@ -1,8 +1,133 @@
 use bevy::prelude::*;
-use crate::core::tile::TileId;
+use crate::core::fluids::FluidPort;
+use crate::core::map::MapGrowth;
+use crate::core::pipes::{PipeComponent, UtilityKind};
+use crate::core::structure::{Rotation, StructureId, StructurePart};
+use crate::core::tile::{TileId, TileLayer};
 
+/**
+ * One per-cell layer of a deck that edits can target. `Planned*` hold blueprints awaiting construction;
+ * `Structure` holds the cells covered by multi-cell structures; every utility kind has its own `Pipe`,
+ * `Riser`, inline `Component` and fluid `Port` layers.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CellLayer { Base, Overlay, PlannedBase, PlannedOverlay, Structure, Pipe(UtilityKind), Riser(UtilityKind), Component(UtilityKind), Port(UtilityKind) }
+
+/**
+ * What occupies a cell layer: a built catalog tile on `Base`/`Overlay` or a blueprint of one on
+ * `PlannedBase`/`PlannedOverlay` (tiles go to the layer their `TileDef` declares), one cell of a structure
+ * on `Structure`, or a pipe / riser segment, inline component or fluid port of one utility kind on that
+ * kind's layers. Ports are kept in `FluidState` rather than the map, but are edited like any other layer.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CellContent {
+    Tile(TileId),
+    Planned(TileId),
+    Structure(StructurePart),
+    Pipe(UtilityKind),
+    Riser(UtilityKind),
+    Component(UtilityKind, PipeComponent),
+    Port(UtilityKind, FluidPort),
+}
+
+/**
+ * Request to put `content` into cell (x,y) of deck `level`. Coordinates are storage cells of the map
+ * as it is this frame. Written by tools as a `TileEdit`; core `apply_tile_edits` validates it.
+ * A riser joins (level, x, y) to the deck above and needs a segment of its kind on both ends; a component
+ * is fitted into an existing segment of its kind. Structures are placed whole
+ * with `PlaceStructure` instead.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct PlaceTile { pub level: u32, pub x: u32, pub y: u32, pub content: CellContent }
+
+/**
+ * Request to place a whole structure with its rotated footprint's bottom-left corner at cell (x,y) of deck
+ * `level`. All covered cells are placed or none are. Written by tools as a `TileEdit`.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct PlaceStructure { pub level: u32, pub x: u32, pub y: u32, pub structure: StructureId, pub rotation: Rotation }
+
+/**
+ * Request to clear one layer of cell (x,y) on deck `level` (a base cell reverts to `Empty`).
+ * Removing a pipe also removes its component and the risers of its kind attached to it, and removing any cell of a structure removes the
+ * whole structure. Written by tools as a `TileEdit`.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct RemoveTile { pub level: u32, pub x: u32, pub y: u32, pub layer: CellLayer }
+
+/**
+ * One tool edit request. Produced by tools; consumed only by core `apply_tile_edits`, which applies a
+ * frame's edits in the order they were written, so a removal written after a placement of the same cell
+ * undoes it.
+ */
+#[derive(Message, Clone, Copy, Debug)]
+pub enum TileEdit { Place(PlaceTile), Remove(RemoveTile), Structure(PlaceStructure) }
+
+impl From<PlaceTile> for TileEdit {
+    fn from(edit: PlaceTile) -> Self { Self::Place(edit) }
+}
+
+impl From<RemoveTile> for TileEdit {
+    fn from(edit: RemoveTile) -> Self { Self::Remove(edit) }
+}
+
+impl From<PlaceStructure> for TileEdit {
+    fn from(edit: PlaceStructure) -> Self { Self::Structure(edit) }
+}
+
+/**
+ * Request to swap the blueprint on `layer` of cell (x,y) on deck `level` for the tile it plans, once its
+ * construction is done. Produced by gameplay construction; consumed only by core `apply_tile_edits`, which
+ * checks the built tile like a placement but keeps the swap out of the undo history, since finishing
+ * construction is not a player edit.
+ */
+#[derive(Message, Clone, Copy, Debug)]
+pub struct ConstructTile { pub level: u32, pub x: u32, pub y: u32, pub layer: TileLayer }
+
+/**
+ * A cell layer actually changed (no-op edits are not reported); `None` is an empty layer.
+ * Produced in `MapSet::Edit` by `apply_tile_edits` (including finished construction) and by undo/redo; the change is already visible in
+ * `MapState`/`PipeMap` and their dirty cells when this is read.
+ */
+#[derive(Message, Clone, Copy, Debug)]
+pub struct TileChanged {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+    pub layer: CellLayer,
+    pub before: Option<CellContent>,
+    pub after: Option<CellContent>,
+}
+
+/**
+ * An edit request was dropped because it was illegal; `reason` is a short player-facing explanation.
+ * Produced by `apply_tile_edits`; consumed by gameplay to tell the player why nothing happened.
+ */
+#[derive(Message, Clone, Debug)]
+pub struct EditRejected { pub level: u32, pub x: u32, pub y: u32, pub reason: String }
+
+/**
+ * Brackets a multi-frame gesture (e.g. a pipe drag) so all of its edits undo as one step.
+ * Outside a stroke, each frame's edits form one step. Produced by tools; consumed by core history.
+ */
+#[derive(Message, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EditStroke { Begin, End }
+
+/** Undo or redo one history step. Produced by gameplay from the keybinds; consumed by core history. */
+#[derive(Message, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryCommand { Undo, Redo }
+
+/**
+ * Request to grow the map. Produced by core `grow_near_edits` (auto-expand near edges) and the explicit resize keybind.
+ * Consumed by core `apply_map_growth`.
+ */
 #[derive(Message, Clone, Copy)]
-pub struct PlaceTile { pub x: u32, pub y: u32, pub tile: TileId }
+pub struct GrowMap { pub growth: MapGrowth }
 
+/**
+ * The map was replaced with different geometry (grown, or loaded from a save).
+ * `growth` is the shift to apply to map-sized data that was not replaced (zero after a load).
+ * Produced by core growth and save loading; consumed by pipes, tilemaps and the debug grid.
+ */
 #[derive(Message, Clone, Copy)]
-pub struct RemoveTile { pub x: u32, pub y: u32 }
+pub struct MapResized { pub growth: MapGrowth }
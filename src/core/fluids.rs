@@ -0,0 +1,406 @@
+/**
+ * Fluid in the gas and liquid pipe layers (wires and cables carry none): every pipe cell holds a mix of
+ * fluids up to `PIPE_CAPACITY` in total, and each fixed tick moves every fluid along every pipe connection
+ * (the `PipeMap` connectivity masks, risers included) from the cell holding more of it to the one holding
+ * less. Inline components shape the flow: closed valves block it, filters pass only their fluid and pumps
+ * allow flow one way while pushing extra fluid out of their outlet. Ports attached to cells add (sources)
+ * or remove (sinks) a fixed amount per tick, and vents and scrubbers trade gas with the rooms around them
+ * through `drain` and `fill` (see `core::atmosphere`). The step only reads `PipeMap` and visits cells in `PipeCell`
+ * order, so a run is fully deterministic and can be driven without an app or window.
+ */
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use crate::core::events::MapResized;
+use crate::core::networks::{NetworkId, PipeNetworks};
+use crate::core::pipes::{side_of, PipeCell, PipeComponent, PipeMap, SIDES_ALL};
+
+/** Fluid one pipe cell holds at full pressure. */
+pub const PIPE_CAPACITY: f32 = 100.0;
+
+/**
+ * Fraction of the amount difference that crosses one connection per tick. A cell has at most six
+ * connections, so keeping this at or below 1/6 means a cell can never give away more than it holds.
+ */
+pub const FLOW_PER_TICK: f32 = 0.125;
+
+/** Fluid a pump pushes from its cell into its outlet per tick, on top of the normal flow. */
+pub const PUMP_PER_TICK: f32 = 4.0;
+
+/** Amounts below this are treated as empty and dropped, so drained pipes stop being simulated. */
+const EMPTY_EPSILON: f32 = 1e-4;
+
+/** Fluids the pipes carry. Filters pass exactly one of them. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum Fluid { Oxygen, Nitrogen, CarbonDioxide, Water }
+
+impl Fluid {
+    pub const ALL: [Fluid; 4] = [Fluid::Oxygen, Fluid::Nitrogen, Fluid::CarbonDioxide, Fluid::Water];
+
+    pub fn index(self) -> usize { self as usize }
+
+    /** Stable lowercase name, used in saves and messages. */
+    pub fn name(self) -> &'static str {
+        match self {
+            Fluid::Oxygen => "oxygen",
+            Fluid::Nitrogen => "nitrogen",
+            Fluid::CarbonDioxide => "carbon_dioxide",
+            Fluid::Water => "water",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|fluid| fluid.name() == name) }
+}
+
+/** Amount of each fluid in one cell, indexed by `Fluid::index`. */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FluidMix(pub [f32; Fluid::ALL.len()]);
+
+impl FluidMix {
+    pub fn get(&self, fluid: Fluid) -> f32 { self.0[fluid.index()] }
+    pub fn total(&self) -> f32 { self.0.iter().sum() }
+
+    /** Scales every fluid so the total becomes `total` (an empty mix stays empty). */
+    fn scaled_to(self, total: f32) -> Self {
+        let current = self.total();
+        if current <= 0.0 { return Self::default() }
+        Self(self.0.map(|amount| amount * total / current))
+    }
+}
+
+/**
+ * A source (positive `rate`) of `fluid`, or a sink (negative `rate`) draining every fluid in proportion,
+ * in amount per tick.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FluidPort { pub rate: f32, pub fluid: Fluid }
+
+/**
+ * Fluid mix per pipe cell plus the attached ports. Cells without an entry are empty. Follows map growth
+ * via `rebase_fluids`; a load replaces it with the saved one.
+ */
+#[derive(Resource, Default)]
+pub struct FluidState {
+    amounts: BTreeMap<PipeCell, FluidMix>,
+    ports: BTreeMap<PipeCell, FluidPort>,
+}
+
+impl FluidState {
+    /** Fluid held by the pipe at `cell`, by fluid. */
+    pub fn mix(&self, cell: PipeCell) -> FluidMix { self.amounts.get(&cell).copied().unwrap_or_default() }
+
+    /** Total fluid held by the pipe at `cell`. */
+    pub fn amount(&self, cell: PipeCell) -> f32 { self.mix(cell).total() }
+
+    /** Fill level of the pipe at `cell`, from 0 (empty) to 1 (at capacity). */
+    pub fn pressure(&self, cell: PipeCell) -> f32 { self.amount(cell) / PIPE_CAPACITY }
+
+    /**
+     * Sets the amount of `fluid` held at `cell`, clamped to the room the other fluids leave; ignored where
+     * there is no pipe.
+     */
+    pub fn set_amount(&mut self, pipes: &PipeMap, cell: PipeCell, fluid: Fluid, amount: f32) {
+        if !has_pipe(pipes, cell) { return }
+        let mut mix = self.mix(cell);
+        let room = PIPE_CAPACITY - (mix.total() - mix.get(fluid));
+        mix.0[fluid.index()] = amount.clamp(0.0, room.max(0.0));
+        if mix.total() < EMPTY_EPSILON { self.amounts.remove(&cell); } else { self.amounts.insert(cell, mix); }
+    }
+
+    /**
+     * Removes up to `amount` of the fluids in `only` from the pipe at `cell`, in proportion to each other;
+     * other fluids stay in the pipe. Returns what was removed.
+     */
+    pub fn drain(&mut self, cell: PipeCell, only: &[Fluid], amount: f32) -> FluidMix {
+        let Some(&mix) = self.amounts.get(&cell) else { return FluidMix::default() };
+        let drainable = FluidMix(Fluid::ALL.map(|fluid| if only.contains(&fluid) { mix.get(fluid) } else { 0.0 }));
+        let taken = drainable.scaled_to(amount.clamp(0.0, drainable.total()));
+        let rest = FluidMix(Fluid::ALL.map(|fluid| mix.get(fluid) - taken.get(fluid)));
+        if rest.total() < EMPTY_EPSILON { self.amounts.remove(&cell); } else { self.amounts.insert(cell, rest); }
+        taken
+    }
+
+    /** Adds up to `amount` of `fluid` to the pipe at `cell` as far as its capacity allows; returns the amount added. */
+    pub fn fill(&mut self, pipes: &PipeMap, cell: PipeCell, fluid: Fluid, amount: f32) -> f32 {
+        if !has_pipe(pipes, cell) { return 0.0 }
+        let mix = self.amounts.entry(cell).or_default();
+        let added = amount.clamp(0.0, (PIPE_CAPACITY - mix.total()).max(0.0));
+        mix.0[fluid.index()] += added;
+        added
+    }
+
+    /** Total fluid held by network `id`. */
+    pub fn network_amount(&self, networks: &PipeNetworks, id: NetworkId) -> f32 {
+        networks.cells(id).map(|cell| self.amount(cell)).sum()
+    }
+
+    /** Port attached at `cell`, if any. */
+    pub fn port(&self, cell: PipeCell) -> Option<FluidPort> { self.ports.get(&cell).copied() }
+
+    /** Attaches (or replaces) the port at `cell`. Ports stay attached but idle while the cell has no pipe. */
+    pub fn attach_port(&mut self, cell: PipeCell, port: FluidPort) { self.ports.insert(cell, port); }
+
+    /** Detaches the port at `cell`, returning it. */
+    pub fn detach_port(&mut self, cell: PipeCell) -> Option<FluidPort> { self.ports.remove(&cell) }
+
+    /** Attached ports, in `PipeCell` order. */
+    pub fn ports(&self) -> impl Iterator<Item = (PipeCell, FluidPort)> + '_ { self.ports.iter().map(|(&c, &p)| (c, p)) }
+
+    /**
+     * Advances the simulation by one tick: fluid in erased pipes is lost, ports fill or drain their cell,
+     * then every connection moves `FLOW_PER_TICK` of each fluid's difference from the end holding more to
+     * the end holding less, as far as its components allow, and pumps push fluid into their outlets.
+     * All flows are computed from the amounts at the start of the flow pass; each connection's net flow is
+     * then limited to the room left at its receiving end, in `PipeCell` order, so the pass moves fluid
+     * without creating or destroying any.
+     *
+     * @param pipes - pipe layer; its connectivity and components decide where fluid can flow
+     */
+    pub fn step(&mut self, pipes: &PipeMap) {
+        self.amounts.retain(|&cell, _| has_pipe(pipes, cell));
+        for (&cell, port) in &self.ports {
+            if !has_pipe(pipes, cell) { continue }
+            let mix = self.amounts.entry(cell).or_default();
+            if port.rate >= 0.0 {
+                let room = (PIPE_CAPACITY - mix.total()).max(0.0);
+                mix.0[port.fluid.index()] += port.rate.min(room);
+            } else {
+                *mix = mix.scaled_to((mix.total() + port.rate).max(0.0));
+            }
+        }
+
+        // Per-fluid flow along each connection (from the first cell to the second), from the amounts at the start of the pass.
+        let mut transfers: Vec<(PipeCell, PipeCell, FluidMix)> = Vec::new();
+        for (&cell, &mix) in &self.amounts {
+            let here = component(pipes, cell);
+            if let Some(PipeComponent::Pump { rotation }) = here
+                && let Some((_, outlet)) = pipes.connected_sides(cell).find(|&(side, _)| side == side_of(rotation)) {
+                // Capped at a quarter of what is there and of the room left, so the normal flow still fits.
+                let room = (PIPE_CAPACITY - self.amount(outlet)).max(0.0);
+                let push = PUMP_PER_TICK.min(mix.total() / 4.0).min(room / 4.0);
+                transfers.push((cell, outlet, mix.scaled_to(push)));
+            }
+            for (side, next) in pipes.connected_sides(cell) {
+                // Each connection once: from its lower end, or from this end when the other one is empty.
+                let other = match self.amounts.get(&next) {
+                    Some(_) if next < cell => continue,
+                    Some(&other) => other,
+                    None => FluidMix::default(),
+                };
+                let Some(link) = Link::between(side, here, component(pipes, next)) else { continue };
+                let mut flow = FluidMix::default();
+                for fluid in Fluid::ALL {
+                    if link.passes.is_some_and(|passes| passes != fluid) { continue }
+                    let i = fluid.index();
+                    let amount = FLOW_PER_TICK * (mix.0[i] - other.0[i]);
+                    if link.forward.is_some_and(|forward| (amount > 0.0) != forward) { continue }
+                    flow.0[i] = amount;
+                }
+                transfers.push((cell, next, flow));
+            }
+        }
+        // Fluids crossing both ways cancel out, but the net flow of a connection (a single fluid through a
+        // filter or pump) is cut to the room its receiving end has left, so fluid is never pushed past
+        // capacity and lost.
+        for (from, to, flow) in transfers {
+            let net = flow.total();
+            let receiver = if net >= 0.0 { to } else { from };
+            let room = (PIPE_CAPACITY - self.amount(receiver)).max(0.0);
+            let scale = if net.abs() > room { room / net.abs() } else { 1.0 };
+            for (cell, sign) in [(from, -1.0), (to, 1.0)] {
+                let mix = self.amounts.entry(cell).or_default();
+                for (amount, moved) in mix.0.iter_mut().zip(flow.0) { *amount = (*amount + sign * moved * scale).max(0.0); }
+            }
+        }
+        self.amounts.retain(|_, mix| mix.total() >= EMPTY_EPSILON);
+    }
+
+    /** Moves every cell and port by a map growth shift. */
+    fn shift(&mut self, shift: UVec2) {
+        let moved = |c: PipeCell| PipeCell { x: c.x + shift.x, y: c.y + shift.y, ..c };
+        self.amounts = std::mem::take(&mut self.amounts).into_iter().map(|(c, a)| (moved(c), a)).collect();
+        self.ports = std::mem::take(&mut self.ports).into_iter().map(|(c, p)| (moved(c), p)).collect();
+    }
+}
+
+/** What the components at both ends of one connection let through. */
+struct Link {
+    /** Only this fluid crosses (a filter at either end). */
+    passes: Option<Fluid>,
+    /** Flow only away from (`true`) or only toward (`false`) the end the connection was found from (a pump). */
+    forward: Option<bool>,
+}
+
+impl Link {
+    /**
+     * Combines the components at both ends of the connection leaving `from` through `side`. `None` when
+     * nothing can cross: a closed valve, filters for different fluids or pumps facing each other.
+     */
+    fn between(side: u8, from: Option<PipeComponent>, to: Option<PipeComponent>) -> Option<Self> {
+        let mut link = Link { passes: None, forward: None };
+        for component in [from, to].into_iter().flatten() {
+            match component {
+                PipeComponent::Valve { open: false, .. } => return None,
+                PipeComponent::Filter { passes, .. } => {
+                    if link.passes.is_some_and(|other| other != passes) { return None }
+                    link.passes = Some(passes);
+                }
+                // A pump's rotation points downstream on both of its sides; risers are not restricted.
+                PipeComponent::Pump { rotation } if side & SIDES_ALL != 0 => {
+                    let forward = side_of(rotation) == side;
+                    if link.forward.is_some_and(|other| other != forward) { return None }
+                    link.forward = Some(forward);
+                }
+                _ => {}
+            }
+        }
+        Some(link)
+    }
+}
+
+/** Whether `cell` lies inside the pipe layer and holds a pipe node of a kind that carries fluid. */
+fn has_pipe(pipes: &PipeMap, cell: PipeCell) -> bool { cell.kind.carries_fluid() && pipes.is_node(cell) }
+
+fn component(pipes: &PipeMap, cell: PipeCell) -> Option<PipeComponent> { pipes.component(cell.kind, cell.level, cell.x, cell.y) }
+
+/** Runs one simulation tick per `FixedUpdate`, so flow rates do not depend on the frame rate. */
+pub fn step_fluids(pipes: Res<PipeMap>, mut fluids: ResMut<FluidState>) { fluids.step(&pipes); }
+
+/** Follows map growth. A load (zero growth) inserted the saved state along with the map, so it is left alone. */
+pub fn rebase_fluids(mut resized: MessageReader<MapResized>, mut fluids: ResMut<FluidState>) {
+    for ev in resized.read() {
+        if !ev.growth.is_zero() { fluids.shift(ev.growth.shift()); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::pipes::UtilityKind;
+    use crate::core::structure::Rotation;
+
+    const GAS: UtilityKind = UtilityKind::Gas;
+
+    fn cell(x: u32) -> PipeCell { PipeCell::new(GAS, 0, x, 0) }
+
+    /** A straight gas run along row 0 of a `len` x 1 deck. */
+    fn run(len: u32) -> PipeMap {
+        let mut pipes = PipeMap::new((len, 1), 1);
+        for x in 0..len { pipes.set(GAS, 0, x, 0, true); }
+        pipes
+    }
+
+    fn total(fluids: &FluidState) -> f32 { fluids.amounts.values().map(FluidMix::total).sum() }
+
+    #[test]
+    fn connected_cells_equalize() {
+        let pipes = run(2);
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Oxygen, 80.0);
+        for _ in 0..200 { fluids.step(&pipes); }
+        assert!((fluids.amount(cell(0)) - 40.0).abs() < 1e-3);
+        assert!((fluids.amount(cell(1)) - 40.0).abs() < 1e-3);
+        assert!((total(&fluids) - 80.0).abs() < 1e-3);
+        assert!((fluids.pressure(cell(1)) - 0.4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cells_never_exceed_capacity() {
+        let pipes = run(1);
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Oxygen, 60.0);
+        fluids.set_amount(&pipes, cell(0), Fluid::Nitrogen, 60.0);
+        assert_eq!(fluids.mix(cell(0)).get(Fluid::Nitrogen), PIPE_CAPACITY - 60.0);
+        assert_eq!(fluids.fill(&pipes, cell(0), Fluid::Water, 5.0), 0.0);
+        fluids.attach_port(cell(0), FluidPort { rate: 10.0, fluid: Fluid::Oxygen });
+        for _ in 0..10 { fluids.step(&pipes); }
+        assert!(fluids.amount(cell(0)) <= PIPE_CAPACITY);
+    }
+
+    #[test]
+    fn ports_fill_and_drain_their_network() {
+        let pipes = run(3);
+        let mut fluids = FluidState::default();
+        fluids.attach_port(cell(0), FluidPort { rate: 2.0, fluid: Fluid::Water });
+        for _ in 0..10 { fluids.step(&pipes); }
+        assert!((total(&fluids) - 20.0).abs() < 1e-3, "a source adds its rate every tick");
+        assert!(fluids.amount(cell(2)) > 0.0, "fluid spreads away from the source");
+
+        assert_eq!(fluids.detach_port(cell(0)).map(|port| port.fluid), Some(Fluid::Water));
+        fluids.attach_port(cell(2), FluidPort { rate: -1.0, fluid: Fluid::Water });
+        for _ in 0..400 { fluids.step(&pipes); }
+        assert_eq!(fluids.ports().count(), 1);
+        assert!(total(&fluids) < 1e-3, "a sink drains the whole network");
+    }
+
+    #[test]
+    fn closed_valve_blocks_flow() {
+        let mut pipes = run(3);
+        let valve = |open| Some(PipeComponent::Valve { rotation: Rotation::R90, open });
+        pipes.set_component(GAS, 0, 1, 0, valve(false));
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Oxygen, 50.0);
+        for _ in 0..50 { fluids.step(&pipes); }
+        assert_eq!(fluids.amount(cell(0)), 50.0);
+        assert_eq!(fluids.amount(cell(2)), 0.0);
+
+        pipes.set_component(GAS, 0, 1, 0, valve(true));
+        for _ in 0..50 { fluids.step(&pipes); }
+        assert!(fluids.amount(cell(2)) > 0.0);
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        let mut pipes = run(6);
+        pipes.set_component(GAS, 0, 2, 0, Some(PipeComponent::Pump { rotation: Rotation::R90 }));
+        let simulate = || {
+            let mut fluids = FluidState::default();
+            fluids.set_amount(&pipes, cell(1), Fluid::Nitrogen, 70.0);
+            fluids.attach_port(cell(0), FluidPort { rate: 3.0, fluid: Fluid::Oxygen });
+            fluids.attach_port(cell(5), FluidPort { rate: -0.5, fluid: Fluid::Oxygen });
+            for _ in 0..300 { fluids.step(&pipes); }
+            fluids.amounts
+        };
+        let first = simulate();
+        assert!(!first.is_empty());
+        assert_eq!(first, simulate());
+    }
+
+    #[test]
+    fn filtered_flow_into_a_full_cell_conserves_mass() {
+        let mut pipes = run(3);
+        pipes.set_component(GAS, 0, 1, 0, Some(PipeComponent::Filter { rotation: Rotation::R90, passes: Fluid::Oxygen }));
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Nitrogen, PIPE_CAPACITY);
+        fluids.set_amount(&pipes, cell(2), Fluid::Oxygen, PIPE_CAPACITY);
+        for _ in 0..200 {
+            fluids.step(&pipes);
+            assert!((total(&fluids) - 2.0 * PIPE_CAPACITY).abs() < 1e-2, "total changed to {}", total(&fluids));
+            assert!((0..3).all(|x| fluids.amount(cell(x)) <= PIPE_CAPACITY + 1e-3));
+        }
+        assert_eq!(fluids.mix(cell(0)).get(Fluid::Oxygen), 0.0, "no room for oxygen in the full nitrogen cell");
+    }
+
+    #[test]
+    fn full_cells_still_mix() {
+        let pipes = run(2);
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Nitrogen, PIPE_CAPACITY);
+        fluids.set_amount(&pipes, cell(1), Fluid::Oxygen, PIPE_CAPACITY);
+        for _ in 0..200 { fluids.step(&pipes); }
+        for x in 0..2 {
+            let mix = fluids.mix(cell(x));
+            assert!((mix.get(Fluid::Nitrogen) - 50.0).abs() < 1e-2 && (mix.get(Fluid::Oxygen) - 50.0).abs() < 1e-2, "{mix:?}");
+        }
+    }
+
+    #[test]
+    fn pumps_conserve_mass() {
+        let mut pipes = run(4);
+        pipes.set_component(GAS, 0, 1, 0, Some(PipeComponent::Pump { rotation: Rotation::R90 }));
+        let mut fluids = FluidState::default();
+        for x in 0..4 { fluids.set_amount(&pipes, cell(x), Fluid::Oxygen, 90.0); }
+        for _ in 0..200 { fluids.step(&pipes); }
+        assert!((total(&fluids) - 360.0).abs() < 1e-2);
+        assert!(fluids.amount(cell(3)) > fluids.amount(cell(0)), "the pump moves gas east");
+    }
+}
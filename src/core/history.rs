@@ -0,0 +1,329 @@
+/**
+ * Undo/redo for map edits. Every tool edit applied by `apply_tile_edits` is recorded with its before and
+ * after contents; undo writes the `before` values back in reverse order, redo re-applies the `after`
+ * values. Restores bypass the placement rules since they return cells to states that were valid when
+ * recorded. Undo reverses exactly what the edit charged or refunded (undoing a build refunds it in full,
+ * undoing a deconstruction takes back only what it returned) and redo settles it as the edit did, so
+ * undo/redo cycles never change the stock. A step the stock can't pay for is refused whole and stays on
+ * its stack.
+ */
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use crate::core::edit::{EditTarget, MapEditor};
+use crate::core::events::{EditRejected, EditStroke, HistoryCommand, MapResized, TileChanged};
+use crate::core::tile::Tileset;
+
+/** Maximum number of undo steps kept; the oldest step is dropped beyond this. */
+pub const HISTORY_LIMIT: usize = 100;
+
+/**
+ * Bounded undo/redo stacks of edit steps. A step is all changes from one frame, or from a whole
+ * `EditStroke` while one is open. Cell coordinates follow the map as it grows.
+ */
+#[derive(Resource, Default)]
+pub struct EditHistory {
+    undo: VecDeque<Vec<TileChanged>>,
+    redo: Vec<Vec<TileChanged>>,
+    stroke: Option<Vec<TileChanged>>,
+}
+
+impl EditHistory {
+    /** Records applied edits; any new edit invalidates the redo stack. */
+    pub fn record(&mut self, changes: &[TileChanged]) {
+        if changes.is_empty() { return }
+        self.redo.clear();
+        match &mut self.stroke {
+            Some(step) => step.extend_from_slice(changes),
+            None => self.push(changes.to_vec()),
+        }
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Vec::new());
+    }
+
+    /** Closes the open stroke as one step; an empty stroke leaves no step. */
+    pub fn end_stroke(&mut self) {
+        if let Some(step) = self.stroke.take() && !step.is_empty() { self.push(step); }
+    }
+
+    fn push(&mut self, step: Vec<TileChanged>) {
+        self.undo.push_back(step);
+        if self.undo.len() > HISTORY_LIMIT { self.undo.pop_front(); }
+    }
+
+    /** Drops all steps, e.g. when the map or tileset they refer to was replaced. */
+    pub fn clear(&mut self) { *self = Self::default(); }
+
+    /** Writes the last step's `before` values back and moves the step to the redo stack, unless it was refused. */
+    pub(crate) fn undo(&mut self, editor: &mut MapEditor) -> Result<(), EditRejected> {
+        let Some(step) = self.undo.pop_back() else { return Ok(()) };
+        if let Err(rejected) = editor.restore(&step, true) {
+            self.undo.push_back(step);
+            return Err(rejected);
+        }
+        self.redo.push(step);
+        Ok(())
+    }
+
+    /** Re-applies the last undone step's `after` values and moves the step back to the undo stack, unless it was refused. */
+    pub(crate) fn redo(&mut self, editor: &mut MapEditor) -> Result<(), EditRejected> {
+        let Some(step) = self.redo.pop() else { return Ok(()) };
+        if let Err(rejected) = editor.restore(&step, false) {
+            self.redo.push(step);
+            return Err(rejected);
+        }
+        self.push(step);
+        Ok(())
+    }
+
+    /** Moves every recorded cell by a map growth shift. */
+    fn shift(&mut self, shift: UVec2) {
+        let steps = self.undo.iter_mut().chain(self.redo.iter_mut()).chain(self.stroke.iter_mut());
+        for change in steps.flatten() { change.x += shift.x; change.y += shift.y; }
+    }
+}
+
+/** Opens and closes strokes from tools. Runs before `apply_tile_edits` in `MapSet::Edit`. */
+pub fn track_edit_strokes(mut strokes: MessageReader<EditStroke>, mut history: ResMut<EditHistory>) {
+    for stroke in strokes.read() {
+        match stroke {
+            EditStroke::Begin => history.begin_stroke(),
+            EditStroke::End => history.end_stroke(),
+        }
+    }
+}
+
+/**
+ * Applies undo/redo commands after this frame's edits, reporting the restored cells as `TileChanged`.
+ * An open stroke is closed first so it can be undone as a whole.
+ *
+ * @param commands - undo/redo requests
+ * @param history - steps to pop and move between the stacks
+ * @param target - map layers to restore and the stock that pays for them (rules are not re-checked)
+ * @param changed - notifications for restored cell layers
+ * @param rejected - one notification per step the stock couldn't pay for
+ */
+pub fn undo_redo_edits(
+    mut commands: MessageReader<HistoryCommand>,
+    mut history: ResMut<EditHistory>,
+    mut target: EditTarget,
+    mut changed: MessageWriter<TileChanged>,
+    mut rejected: MessageWriter<EditRejected>,
+) {
+    if commands.is_empty() { return }
+    history.end_stroke();
+    let EditTarget { map, pipes, fluids, tileset, inventory, .. } = &mut target;
+    let mut editor = MapEditor::new(map, pipes, fluids, tileset, inventory);
+    for command in commands.read() {
+        let restored = match command {
+            HistoryCommand::Undo => history.undo(&mut editor),
+            HistoryCommand::Redo => history.redo(&mut editor),
+        };
+        if let Err(refused) = restored { rejected.write(refused); }
+    }
+    changed.write_batch(editor.changes);
+}
+
+/**
+ * Keeps recorded coordinates valid: shifts them when the map grows, and clears the history when the map
+ * was replaced by a load (zero growth) or the tileset was rebuilt (recorded `TileId`s may be stale).
+ */
+pub fn rebase_edit_history(
+    mut resized: MessageReader<MapResized>,
+    tileset: Res<Tileset>,
+    mut history: ResMut<EditHistory>,
+) {
+    if tileset.is_changed() { history.clear(); }
+    for ev in resized.read() {
+        if ev.growth.is_zero() { history.clear(); } else { history.shift(ev.growth.shift()); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{CellContent, CellLayer};
+    use crate::core::fluids::{Fluid, FluidPort};
+    use crate::core::inventory::MaterialCost;
+    use crate::core::map::MapGrowth;
+    use crate::core::pipes::{PipeCell, UtilityKind};
+    use crate::core::test_support::Fixture;
+
+    const GAS: UtilityKind = UtilityKind::Gas;
+
+    /** A 4x4 single-deck map with only the `Empty` tile. */
+    fn fixture() -> Fixture { Fixture::new(Tileset::default(), 4, 4, 1) }
+
+    impl Fixture {
+        /** Lays or clears a gas pipe at (x,0) and returns the recorded change. */
+        fn pipe(&mut self, x: u32, on: bool) -> Vec<TileChanged> {
+            let mut editor = self.editor();
+            editor.write(0, x, 0, CellLayer::Pipe(GAS), on.then_some(CellContent::Pipe(GAS))).unwrap();
+            editor.changes
+        }
+
+        fn undo(&mut self, history: &mut EditHistory) -> Vec<TileChanged> {
+            let mut editor = self.editor();
+            history.undo(&mut editor).unwrap();
+            editor.changes
+        }
+
+        fn redo(&mut self, history: &mut EditHistory) -> Vec<TileChanged> {
+            let mut editor = self.editor();
+            history.redo(&mut editor).unwrap();
+            editor.changes
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_restore_cells() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        history.record(&fx.pipe(1, true));
+        let undone = fx.undo(&mut history);
+        assert!(!fx.pipes.has(GAS, 0, 1, 0));
+        assert_eq!(undone.len(), 1);
+        assert_eq!((undone[0].before, undone[0].after), (Some(CellContent::Pipe(GAS)), None));
+        fx.redo(&mut history);
+        assert!(fx.pipes.has(GAS, 0, 1, 0));
+        assert!(fx.redo(&mut history).is_empty(), "nothing left to redo");
+    }
+
+    #[test]
+    fn stroke_undoes_as_one_step() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        history.begin_stroke();
+        history.record(&fx.pipe(0, true));
+        history.record(&fx.pipe(1, true));
+        history.end_stroke();
+        history.record(&fx.pipe(2, true));
+        fx.undo(&mut history);
+        assert!(fx.pipes.has(GAS, 0, 1, 0) && !fx.pipes.has(GAS, 0, 2, 0));
+        assert_eq!(fx.undo(&mut history).len(), 2);
+        assert!(!fx.pipes.has(GAS, 0, 0, 0) && !fx.pipes.has(GAS, 0, 1, 0));
+    }
+
+    #[test]
+    fn empty_stroke_leaves_no_step() {
+        let mut history = EditHistory::default();
+        history.begin_stroke();
+        history.end_stroke();
+        assert!(history.undo.is_empty());
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        history.record(&fx.pipe(1, true));
+        fx.undo(&mut history);
+        history.record(&fx.pipe(2, true));
+        assert!(fx.redo(&mut history).is_empty());
+        assert!(!fx.pipes.has(GAS, 0, 1, 0));
+    }
+
+    #[test]
+    fn oldest_step_is_dropped_beyond_limit() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        for i in 0..=HISTORY_LIMIT { history.record(&fx.pipe(1, i % 2 == 0)); }
+        assert_eq!(history.undo.len(), HISTORY_LIMIT);
+        assert_eq!(history.undo[0][0].after, None, "the first placement was dropped");
+    }
+
+    #[test]
+    fn shift_follows_map_growth() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        history.record(&fx.pipe(1, true));
+        history.shift(UVec2::new(2, 3));
+        let c = history.undo[0][0];
+        assert_eq!((c.x, c.y), (3, 3));
+    }
+
+    #[test]
+    fn undo_after_growing_left_and_down_clears_the_moved_cell() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        history.record(&fx.pipe(1, true));
+        let growth = MapGrowth { left: 2, bottom: 3, ..default() };
+        fx.map = fx.map.expanded(growth);
+        fx.pipes = fx.pipes.expanded(growth);
+        history.shift(growth.shift());
+        assert!(fx.pipes.has(GAS, 0, 3, 3));
+        let undone = fx.undo(&mut history);
+        assert_eq!((undone[0].x, undone[0].y), (3, 3));
+        assert!(!fx.pipes.has(GAS, 0, 3, 3));
+        fx.redo(&mut history);
+        assert!(fx.pipes.has(GAS, 0, 3, 3) && !fx.pipes.has(GAS, 0, 1, 0));
+    }
+
+    /** Fixture whose stock holds `steel` Steel and whose Plate tile costs 2 Steel, with the Plate id. */
+    fn plate_fixture(steel: u32) -> (Fixture, CellContent) {
+        let fx = Fixture::new(crate::core::catalog::test_tileset(&format!(r#"{{
+            "materials": [ {{ "id": "Steel", "starting": {steel} }} ],
+            "tiles": [ {{ "id": "Empty", "layer": "Base", "color": [0, 0, 0, 0] }},
+                       {{ "id": "Plate", "layer": "Base", "color": [1, 1, 1, 1], "properties": {{ "build_cost": {{ "Steel": 2 }} }} }} ]
+        }}"#)), 4, 4, 1);
+        let plate = CellContent::Tile(fx.tile("Plate"));
+        (fx, plate)
+    }
+
+    #[test]
+    fn redo_the_stock_cannot_pay_for_is_refused() {
+        let (mut fx, plate) = plate_fixture(2);
+        let mut history = EditHistory::default();
+        let mut editor = fx.editor();
+        editor.write(0, 1, 0, CellLayer::Base, Some(plate)).unwrap();
+        history.record(&editor.changes);
+        assert_eq!(fx.inventory.amount("Steel"), 0);
+
+        fx.undo(&mut history);
+        assert_eq!(fx.inventory.amount("Steel"), 2, "undoing a build refunds all of it");
+        fx.inventory.charge(&MaterialCost::from([("Steel".to_string(), 1)])).unwrap();
+        let mut editor = fx.editor();
+        let refused = history.redo(&mut editor).unwrap_err();
+        assert!(editor.changes.is_empty());
+        assert_eq!((refused.x, refused.y), (1, 0));
+        assert_eq!(fx.inventory.amount("Steel"), 1);
+        assert_eq!(fx.map.get_base(0, 1, 0), crate::core::tile::TileId::EMPTY);
+        assert_eq!(history.redo.len(), 1, "the refused step can be redone once the stock allows");
+    }
+
+    #[test]
+    fn undo_redo_cycles_leave_the_stock_unchanged() {
+        let (mut fx, plate) = plate_fixture(10);
+        let mut history = EditHistory::default();
+        let mut editor = fx.editor();
+        editor.write(0, 1, 0, CellLayer::Base, Some(plate)).unwrap();
+        history.record(&editor.changes);
+        let mut editor = fx.editor();
+        editor.write(0, 1, 0, CellLayer::Base, None).unwrap();
+        history.record(&editor.changes);
+        assert_eq!(fx.inventory.amount("Steel"), 9, "deconstruction refunds half");
+
+        for _ in 0..3 {
+            fx.undo(&mut history);
+            assert_eq!(fx.inventory.amount("Steel"), 8, "undoing the deconstruction takes back only its refund");
+            fx.undo(&mut history);
+            assert_eq!(fx.inventory.amount("Steel"), 10, "undoing the build refunds it in full");
+            fx.redo(&mut history);
+            assert_eq!(fx.inventory.amount("Steel"), 8);
+            fx.redo(&mut history);
+            assert_eq!(fx.inventory.amount("Steel"), 9);
+        }
+    }
+
+    #[test]
+    fn port_changes_undo_and_redo() {
+        let (mut fx, mut history) = (fixture(), EditHistory::default());
+        let (cell, port) = (PipeCell::new(GAS, 0, 1, 0), FluidPort { rate: -1.0, fluid: Fluid::Oxygen });
+        history.record(&fx.pipe(1, true));
+        let mut editor = fx.editor();
+        editor.write(0, 1, 0, CellLayer::Port(GAS), Some(CellContent::Port(GAS, port))).unwrap();
+        history.record(&editor.changes);
+        assert_eq!(fx.fluids.port(cell), Some(port));
+        fx.undo(&mut history);
+        assert_eq!(fx.fluids.port(cell), None);
+        assert!(fx.pipes.has(GAS, 0, 1, 0), "only the port is undone");
+        fx.redo(&mut history);
+        assert_eq!(fx.fluids.port(cell), Some(port));
+    }
+}
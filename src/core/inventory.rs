@@ -0,0 +1,57 @@
+/**
+ * Station inventory: material stock by catalog material id. The edit pipeline charges a tile's
+ * `build_cost` when it lands on a map layer and refunds it when it leaves: in full for a blueprint that
+ * never got built, `REFUND_FRACTION` of it for a built tile that is deconstructed or replaced. Building a
+ * blueprint swaps it for the built tile, so the refund and charge cancel out. Pipes are free.
+ */
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use crate::core::tile::MaterialDef;
+
+/** Share of a built tile's cost returned when it is deconstructed, per material, rounded down. */
+pub const REFUND_FRACTION: f32 = 0.5;
+
+/** Material units per material id, e.g. a tile's `build_cost`. */
+pub type MaterialCost = BTreeMap<String, u32>;
+
+/** Materials in stock. Materials the catalog declares start at their `starting` amount. */
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Inventory { stock: MaterialCost }
+
+impl Inventory {
+    pub fn new(stock: MaterialCost) -> Self { Self { stock } }
+
+    pub fn stock(&self) -> &MaterialCost { &self.stock }
+
+    pub fn amount(&self, material: &str) -> u32 { self.stock.get(material).copied().unwrap_or(0) }
+
+    /** Adds the starting amount of every material not stocked yet (new catalog materials, older saves). */
+    pub fn stock_materials(&mut self, materials: &[MaterialDef]) {
+        for material in materials {
+            self.stock.entry(material.name.clone()).or_insert(material.starting);
+        }
+    }
+
+    /** Describes the first material `cost` needs more of than is in stock, if any. */
+    pub fn shortfall(&self, cost: &MaterialCost) -> Option<String> {
+        cost.iter().find(|&(material, &need)| self.amount(material) < need)
+            .map(|(material, need)| format!("not enough {material} (need {need}, have {})", self.amount(material)))
+    }
+
+    /** Takes `cost` out of stock, or leaves the stock untouched and describes the shortfall. */
+    pub fn charge(&mut self, cost: &MaterialCost) -> Result<(), String> {
+        if let Some(shortfall) = self.shortfall(cost) { return Err(shortfall) }
+        for (material, &need) in cost { *self.stock.entry(material.clone()).or_default() -= need; }
+        Ok(())
+    }
+
+    /** Returns `fraction` of `cost` to stock, rounded down per material. */
+    pub fn refund(&mut self, cost: &MaterialCost, fraction: f32) {
+        for (material, returned) in refund_share(cost, fraction) { *self.stock.entry(material).or_default() += returned; }
+    }
+}
+
+/** What a refund of `fraction` of `cost` returns, rounded down per material. */
+pub fn refund_share(cost: &MaterialCost, fraction: f32) -> MaterialCost {
+    cost.iter().map(|(material, &paid)| (material.clone(), (paid as f32 * fraction) as u32)).collect()
+}
@@ -1,36 +1,317 @@
 use bevy::prelude::*;
-use crate::core::tile::TileId;
+use std::collections::HashSet;
+use crate::core::chunk::{ChunkedLayer, DirtyCells, CHUNK_SIZE};
+use crate::core::events::{GrowMap, MapResized};
+use crate::core::pipes::PipeMap;
+use crate::core::structure::{StructureId, StructurePart};
+use crate::core::tile::{TileId, TileLayer, Tileset};
 
-#[derive(Clone, Copy)]
+/** Building within this many cells of an edge grows the map on that side. */
+pub const AUTO_EXPAND_MARGIN: u32 = 2;
+
+/** Cells added per side by auto-expansion and the explicit resize command. */
+pub const EXPAND_STEP: u32 = CHUNK_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MapSize { pub w: u32, pub h: u32 }
 
+/**
+ * Cells to add on each side of the map. Storage row 0 is the bottom row (tilemaps are
+ * anchored top-left with y increasing upward), so `bottom` growth shifts rows up.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MapGrowth { pub left: u32, pub right: u32, pub top: u32, pub bottom: u32 }
+
+impl MapGrowth {
+    pub fn is_zero(&self) -> bool { *self == MapGrowth::default() }
+
+    /** Per-side maximum, so overlapping requests for the same edge do not stack. */
+    pub fn max(self, other: MapGrowth) -> MapGrowth {
+        MapGrowth {
+            left: self.left.max(other.left),
+            right: self.right.max(other.right),
+            top: self.top.max(other.top),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    /** Offset applied to existing storage cells when this growth is applied. */
+    pub fn shift(&self) -> UVec2 { UVec2::new(self.left, self.bottom) }
+}
+
+/**
+ * PostUpdate ordering for map edits and geometry: core applies edits, then growth, dependents rebuild,
+ * then render sync.
+ */
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapSet { Edit, Resize, Rebuild, Sync }
+
+/** Number of decks a fresh map starts with. */
+pub const DEFAULT_LEVELS: u32 = 3;
+
+/**
+ * Base and overlay layers of one deck, with their own dirty set.
+ * `planned_*` hold blueprints: tiles ordered but not yet built, one per layer and cell.
+ * `structures` holds the part of a multi-cell structure covering each cell; it is drawn as sprites,
+ * not tilemaps, so it does not mark cells dirty.
+ */
+struct Level {
+    base: ChunkedLayer<TileId>,
+    overlay: ChunkedLayer<Option<TileId>>, // optional overlay marker
+    planned_base: ChunkedLayer<Option<TileId>>,
+    planned_overlay: ChunkedLayer<Option<TileId>>,
+    structures: ChunkedLayer<Option<StructurePart>>,
+    dirty: DirtyCells,
+}
+
+impl Level {
+    fn new(size: MapSize) -> Self {
+        Self {
+            base: ChunkedLayer::new(size.w, size.h, TileId::EMPTY),
+            overlay: ChunkedLayer::new(size.w, size.h, None),
+            planned_base: ChunkedLayer::new(size.w, size.h, None),
+            planned_overlay: ChunkedLayer::new(size.w, size.h, None),
+            structures: ChunkedLayer::new(size.w, size.h, None),
+            dirty: DirtyCells::new(size.w, size.h),
+        }
+    }
+
+    fn planned(&self, layer: TileLayer) -> &ChunkedLayer<Option<TileId>> {
+        match layer { TileLayer::Base => &self.planned_base, TileLayer::Overlay => &self.planned_overlay }
+    }
+}
+
+/**
+ * Authoritative tile layers for the map: a stack of decks (levels) sharing one footprint,
+ * each stored in lazily allocated chunks. Level 0 is the bottom deck.
+ * Every write that changes a cell marks the cell dirty on that level; render sync drains the dirty sets.
+ * Storage cells are `0..w` x `0..h`; `origin` is the logical cell of storage (0,0), so logical
+ * coordinates (and world positions) stay fixed when the map grows in negative directions.
+ */
 #[derive(Resource)]
 pub struct MapState {
     pub size: MapSize,
-    pub base: Vec<TileId>,
-    pub overlay: Vec<Option<TileId>>, // optional overlay marker
+    pub origin: IVec2,
+    levels: Vec<Level>,
 }
 impl MapState {
-    pub fn new(size: MapSize) -> Self {
-        let num = (size.w * size.h) as usize;
+    /** Creates an empty map with `levels` decks whose top-left corner sits at the world origin. */
+    pub fn new(size: MapSize, levels: u32) -> Self {
         Self {
             size,
-            base: vec![TileId::Empty; num],
-            overlay: vec![None; num],
+            origin: IVec2::new(0, -(size.h as i32)),
+            levels: (0..levels.max(1)).map(|_| Level::new(size)).collect(),
         }
     }
 
+    /** Number of decks; always at least one. */
+    pub fn levels(&self) -> u32 { self.levels.len() as u32 }
+
+    /** Row-major cell index, for flat per-cell data kept alongside the map (e.g. saves). */
     pub fn idx(&self, x: u32, y: u32) -> usize { (y * self.size.w + x) as usize }
 
-    pub fn get_base(&self, x: u32, y: u32) -> TileId { self.base[self.idx(x, y)] }
-    pub fn set_base(&mut self, x: u32, y: u32, tile: TileId) {
-        let idx = self.idx(x, y);
-        self.base[idx] = tile;
+    pub fn get_base(&self, level: u32, x: u32, y: u32) -> TileId { self.levels[level as usize].base.get(x, y) }
+    pub fn set_base(&mut self, level: u32, x: u32, y: u32, tile: TileId) {
+        let l = &mut self.levels[level as usize];
+        if l.base.set(x, y, tile) { l.dirty.mark_cell(x, y); }
+    }
+
+    pub fn get_overlay(&self, level: u32, x: u32, y: u32) -> Option<TileId> { self.levels[level as usize].overlay.get(x, y) }
+    pub fn set_overlay(&mut self, level: u32, x: u32, y: u32, tile: Option<TileId>) {
+        let l = &mut self.levels[level as usize];
+        if l.overlay.set(x, y, tile) { l.dirty.mark_cell(x, y); }
+    }
+
+    /** Blueprint on `layer` of cell (x,y), i.e. a tile planned there but not built yet. */
+    pub fn get_planned(&self, level: u32, x: u32, y: u32, layer: TileLayer) -> Option<TileId> {
+        self.levels[level as usize].planned(layer).get(x, y)
+    }
+    pub fn set_planned(&mut self, level: u32, x: u32, y: u32, layer: TileLayer, tile: Option<TileId>) {
+        let l = &mut self.levels[level as usize];
+        let planned = match layer { TileLayer::Base => &mut l.planned_base, TileLayer::Overlay => &mut l.planned_overlay };
+        if planned.set(x, y, tile) { l.dirty.mark_cell(x, y); }
+    }
+
+    /** Every blueprint on `level` as `(x, y, layer, tile)`, in storage order. */
+    pub fn planned_cells(&self, level: u32) -> Vec<(u32, u32, TileLayer, TileId)> {
+        let mut out = Vec::new();
+        for y in 0..self.size.h { for x in 0..self.size.w {
+            for layer in [TileLayer::Base, TileLayer::Overlay] {
+                if let Some(tile) = self.get_planned(level, x, y, layer) { out.push((x, y, layer, tile)); }
+            }
+        }}
+        out
+    }
+
+    /** Part of a structure covering cell (x,y), if any. */
+    pub fn get_structure(&self, level: u32, x: u32, y: u32) -> Option<StructurePart> {
+        self.levels[level as usize].structures.get(x, y)
+    }
+    pub fn set_structure(&mut self, level: u32, x: u32, y: u32, part: Option<StructurePart>) {
+        self.levels[level as usize].structures.set(x, y, part);
+    }
+
+    /** Every structure on `level` as the position of its anchor and the part at its primary cell. */
+    pub fn structures_on(&self, level: u32, tileset: &Tileset) -> Vec<(UVec2, StructurePart)> {
+        let mut out = Vec::new();
+        for y in 0..self.size.h { for x in 0..self.size.w {
+            let Some(part) = self.get_structure(level, x, y) else { continue };
+            if part.offset != tileset.structure(part.structure).primary(part.rotation) { continue }
+            out.push((UVec2::new(x, y) - part.offset, part));
+        }}
+        out
     }
 
-    pub fn get_overlay(&self, x: u32, y: u32) -> Option<TileId> { self.overlay[self.idx(x, y)] }
-    pub fn set_overlay(&mut self, x: u32, y: u32, tile: Option<TileId>) {
-        let idx = self.idx(x, y);
-        self.overlay[idx] = tile;
+    /** Every structure kind currently placed on any deck. */
+    pub fn structures_in_use(&self) -> HashSet<StructureId> {
+        self.levels.iter().flat_map(|l| l.structures.allocated_values().flatten().map(|p| p.structure)).collect()
     }
-}
\ No newline at end of file
+
+    /** Rewrites every placed structure id through `remap` after the tileset was rebuilt. */
+    pub fn remap_structures(&mut self, remap: impl Fn(StructureId) -> StructureId) {
+        for l in &mut self.levels {
+            l.structures.map_in_place(|o| o.map(|p| StructurePart { structure: remap(p.structure), ..p }));
+        }
+    }
+
+    /** Marks the whole map for re-sync, e.g. after it was rebuilt from a save. */
+    pub fn mark_all_dirty(&mut self) {
+        for l in &mut self.levels { l.dirty.mark_all(); }
+    }
+
+    /** Every tile id currently placed or planned on any deck (always includes `Empty`). */
+    pub fn tiles_in_use(&self) -> HashSet<TileId> {
+        let mut used = HashSet::from([TileId::EMPTY]);
+        for l in &self.levels {
+            used.extend(l.base.allocated_values());
+            for layer in [&l.overlay, &l.planned_base, &l.planned_overlay] { used.extend(layer.allocated_values().flatten()); }
+        }
+        used
+    }
+
+    /**
+     * Rewrites every placed tile id through `remap` after the tileset was rebuilt, and marks the map
+     * for a full re-sync. `remap` must keep `Empty` at `TileId::EMPTY`.
+     */
+    pub fn remap_tiles(&mut self, remap: impl Fn(TileId) -> TileId) {
+        for l in &mut self.levels {
+            l.base.map_in_place(&remap);
+            for layer in [&mut l.overlay, &mut l.planned_base, &mut l.planned_overlay] { layer.map_in_place(|o| o.map(&remap)); }
+        }
+        self.mark_all_dirty();
+    }
+
+    /** Drains the cells of `level` changed since the last call. Single consumer: render sync. */
+    pub fn take_dirty_cells(&mut self, level: u32) -> Vec<UVec2> { self.levels[level as usize].dirty.take() }
+
+    /**
+     * World-space position of the map's top-left corner, where tilemaps and the debug grid are anchored.
+     *
+     * @param tile_size - grid cell size in world units
+     */
+    pub fn world_anchor(&self, tile_size: f32) -> Vec2 {
+        Vec2::new(self.origin.x as f32, (self.origin.y + self.size.h as i32) as f32) * tile_size
+    }
+
+    /**
+     * Returns a copy grown by `growth` on every deck, with existing cells shifted so their logical positions are unchanged.
+     * The result is fully dirty.
+     */
+    pub fn expanded(&self, growth: MapGrowth) -> MapState {
+        let size = MapSize { w: self.size.w + growth.left + growth.right, h: self.size.h + growth.top + growth.bottom };
+        let shift = growth.shift();
+        let mut out = MapState::new(size, self.levels());
+        out.origin = self.origin - shift.as_ivec2();
+        for level in 0..self.levels() {
+            for y in 0..self.size.h { for x in 0..self.size.w {
+                out.set_base(level, x + shift.x, y + shift.y, self.get_base(level, x, y));
+                out.set_overlay(level, x + shift.x, y + shift.y, self.get_overlay(level, x, y));
+                for layer in [TileLayer::Base, TileLayer::Overlay] {
+                    out.set_planned(level, x + shift.x, y + shift.y, layer, self.get_planned(level, x, y, layer));
+                }
+                out.set_structure(level, x + shift.x, y + shift.y, self.get_structure(level, x, y));
+            }}
+        }
+        out.mark_all_dirty();
+        out
+    }
+
+    /**
+     * Growth needed to keep `AUTO_EXPAND_MARGIN` free cells around a newly built cell (x,y).
+     * Returns `None` when the cell is far enough from every edge.
+     */
+    pub fn edge_growth(&self, x: u32, y: u32) -> Option<MapGrowth> {
+        let grow = |near: bool| if near { EXPAND_STEP } else { 0 };
+        let growth = MapGrowth {
+            left: grow(x < AUTO_EXPAND_MARGIN),
+            right: grow(x + AUTO_EXPAND_MARGIN >= self.size.w),
+            bottom: grow(y < AUTO_EXPAND_MARGIN),
+            top: grow(y + AUTO_EXPAND_MARGIN >= self.size.h),
+        };
+        (!growth.is_zero()).then_some(growth)
+    }
+}
+
+/**
+ * Applies all `GrowMap` requests from this frame as one growth to `MapState` and `PipeMap`, and announces
+ * it via `MapResized`. Runs in `MapSet::Resize`, ahead of the systems that rebuild tilemaps and the debug grid.
+ */
+pub fn apply_map_growth(
+    mut requests: MessageReader<GrowMap>,
+    mut resized: MessageWriter<MapResized>,
+    mut map: ResMut<MapState>,
+    mut pipes: ResMut<PipeMap>,
+) {
+    let growth = requests.read().fold(MapGrowth::default(), |acc, req| acc.max(req.growth));
+    if growth.is_zero() { return }
+    *map = map.expanded(growth);
+    *pipes = pipes.expanded(growth);
+    info!("map grown to {}x{} (origin {})", map.size.w, map.size.h, map.origin);
+    resized.write(MapResized { growth });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::structure::Rotation;
+
+    const GROW_LEFT_DOWN: MapGrowth = MapGrowth { left: 3, right: 0, top: 0, bottom: 2 };
+
+    #[test]
+    fn growing_left_and_down_keeps_every_cell_at_its_logical_position() {
+        let mut map = MapState::new(MapSize { w: 4, h: 4 }, 2);
+        let part = StructurePart { structure: StructureId(1), rotation: Rotation::R90, offset: UVec2::new(1, 0) };
+        map.set_base(0, 0, 0, TileId(2));
+        map.set_overlay(1, 3, 1, Some(TileId(3)));
+        map.set_planned(0, 2, 3, TileLayer::Base, Some(TileId(4)));
+        map.set_planned(1, 1, 2, TileLayer::Overlay, Some(TileId(5)));
+        map.set_structure(1, 0, 3, Some(part));
+
+        let grown = map.expanded(GROW_LEFT_DOWN);
+        assert_eq!(grown.size, MapSize { w: 7, h: 6 });
+        assert_eq!(grown.levels(), 2);
+        assert_eq!(grown.origin, map.origin - IVec2::new(3, 2));
+        assert_eq!(grown.world_anchor(16.0), map.world_anchor(16.0) - Vec2::new(3.0 * 16.0, 0.0), "the top edge stays put");
+        assert_eq!(grown.get_base(0, 3, 2), TileId(2));
+        assert_eq!(grown.get_overlay(1, 6, 3), Some(TileId(3)));
+        assert_eq!(grown.get_planned(0, 5, 5, TileLayer::Base), Some(TileId(4)));
+        assert_eq!(grown.get_planned(1, 4, 4, TileLayer::Overlay), Some(TileId(5)));
+        assert_eq!(grown.get_structure(1, 3, 5), Some(part));
+        assert_eq!(grown.get_base(0, 0, 0), TileId::EMPTY, "new cells are empty");
+        assert_eq!(grown.tiles_in_use(), map.tiles_in_use());
+    }
+
+    #[test]
+    fn grown_map_is_fully_dirty() {
+        let mut grown = MapState::new(MapSize { w: 2, h: 2 }, 1).expanded(GROW_LEFT_DOWN);
+        assert_eq!(grown.take_dirty_cells(0).len(), 5 * 4);
+    }
+
+    #[test]
+    fn edge_growth_asks_for_the_near_sides_only() {
+        let map = MapState::new(MapSize { w: 20, h: 20 }, 1);
+        assert_eq!(map.edge_growth(10, 10), None);
+        assert_eq!(map.edge_growth(1, 0), Some(MapGrowth { left: EXPAND_STEP, bottom: EXPAND_STEP, ..default() }));
+        assert_eq!(map.edge_growth(18, 10), Some(MapGrowth { right: EXPAND_STEP, ..default() }));
+    }
+}
@@ -2,21 +2,63 @@ pub mod grid;
 pub mod map;
 pub mod tile;
 pub mod catalog;
+pub mod chunk;
+pub mod events;
+pub mod pipes;
+pub mod edit;
+pub mod history;
+pub mod inventory;
+pub mod structure;
+pub mod networks;
+pub mod fluids;
+pub mod atmosphere;
+#[cfg(test)]
+pub(crate) mod test_support;
 
 use bevy::prelude::*;
-use map::{MapSize, MapState};
+use map::{MapSize, MapState, MapSet, DEFAULT_LEVELS, apply_map_growth};
 use tile::Tileset;
 use catalog::CoreTilesPlugin;
 use grid::GridConfig;
+use pipes::PipeMap;
+use edit::{apply_tile_edits, grow_near_edits};
+use inventory::Inventory;
+use fluids::{FluidState, rebase_fluids, step_fluids};
+use atmosphere::{Atmosphere, rebase_atmosphere, step_atmosphere};
+use networks::{PipeNetworks, rebase_pipe_networks, update_pipe_networks};
+use history::{EditHistory, rebase_edit_history, track_edit_strokes, undo_redo_edits};
+use events::{ConstructTile, EditRejected, EditStroke, GrowMap, HistoryCommand, MapResized, TileChanged, TileEdit};
 
 pub struct CorePlugin;
 
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
         // Default map and tileset resources
+        let size = MapSize { w: 32, h: 20 };
         app.add_plugins(CoreTilesPlugin)
             .insert_resource::<GridConfig>(Default::default())
             .init_resource::<Tileset>()
-            .insert_resource(MapState::new(MapSize { w: 32, h: 20 }));
+            .insert_resource(MapState::new(size, DEFAULT_LEVELS))
+            .insert_resource(PipeMap::new((size.w, size.h), DEFAULT_LEVELS))
+            .add_message::<TileEdit>()
+            .add_message::<ConstructTile>()
+            .add_message::<TileChanged>()
+            .add_message::<EditRejected>()
+            .add_message::<EditStroke>()
+            .add_message::<HistoryCommand>()
+            .init_resource::<EditHistory>()
+            .init_resource::<Inventory>()
+            .init_resource::<PipeNetworks>()
+            .init_resource::<FluidState>()
+            .init_resource::<Atmosphere>()
+            .add_message::<GrowMap>()
+            .add_message::<MapResized>()
+            .configure_sets(PostUpdate, (MapSet::Edit, MapSet::Resize, MapSet::Rebuild, MapSet::Sync).chain())
+            .add_systems(PostUpdate, (
+                (track_edit_strokes, apply_tile_edits, undo_redo_edits, grow_near_edits, update_pipe_networks).chain().in_set(MapSet::Edit),
+                apply_map_growth.in_set(MapSet::Resize),
+                (rebase_edit_history, rebase_pipe_networks, rebase_fluids, rebase_atmosphere).in_set(MapSet::Rebuild),
+            ))
+            .add_systems(FixedUpdate, (step_fluids, step_atmosphere).chain());
     }
 }
@@ -0,0 +1,255 @@
+/**
+ * Pipe networks: connected components of each utility kind's layer across decks (risers join decks).
+ * Every utility cell belongs to exactly one network, and a network never mixes kinds. Ids are stable while a network exists: placing a pipe that joins
+ * networks keeps the id of the largest one, and erasing one that splits a network keeps its id for the
+ * largest part while the others get fresh ids. A load renumbers all networks in storage order.
+ */
+use bevy::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use crate::core::events::{CellLayer, MapResized, TileChanged};
+use crate::core::pipes::{PipeCell, PipeMap, UtilityKind};
+
+/** Identifies a pipe network while it exists; never reused within a session. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct NetworkId(pub u32);
+
+/**
+ * Network membership of every pipe cell. Cells are kept ordered so iteration is deterministic.
+ * Kept in step with `PipeMap` by `update_pipe_networks` and `rebase_pipe_networks`.
+ */
+#[derive(Resource, Default)]
+pub struct PipeNetworks {
+    of_cell: HashMap<PipeCell, NetworkId>,
+    networks: BTreeMap<NetworkId, BTreeSet<PipeCell>>,
+    next_id: u32,
+}
+
+impl PipeNetworks {
+    /** Network the pipe at `cell` belongs to, if there is a pipe. */
+    pub fn network_of(&self, cell: PipeCell) -> Option<NetworkId> { self.of_cell.get(&cell).copied() }
+
+    /** Every network, in id order. */
+    pub fn networks(&self) -> impl Iterator<Item = NetworkId> + '_ { self.networks.keys().copied() }
+
+    /** Cells of network `id` in `PipeCell` order; empty for an unknown id. */
+    pub fn cells(&self, id: NetworkId) -> impl Iterator<Item = PipeCell> + '_ {
+        self.networks.get(&id).into_iter().flatten().copied()
+    }
+
+    /** Cells of network `id` with at most one connection: pipe ends (and lone segments). */
+    pub fn endpoints(&self, id: NetworkId, pipes: &PipeMap) -> Vec<PipeCell> {
+        self.cells(id).filter(|&cell| pipes.connected(cell).count() <= 1).collect()
+    }
+
+    fn fresh_id(&mut self) -> NetworkId {
+        let id = NetworkId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn assign(&mut self, cell: PipeCell, id: NetworkId) {
+        if let Some(old) = self.of_cell.insert(cell, id) && old != id && let Some(cells) = self.networks.get_mut(&old) {
+            cells.remove(&cell);
+        }
+        self.networks.entry(id).or_default().insert(cell);
+    }
+
+    /**
+     * Joins `cell` (a new pipe, or a pipe that gained a riser) with every network it now connects to.
+     * The largest network absorbs the others; returns the absorbed ids.
+     */
+    fn join(&mut self, pipes: &PipeMap, cell: PipeCell) -> Vec<NetworkId> {
+        if !pipes.is_node(cell) { return Vec::new() }
+        let mut touching: Vec<NetworkId> = pipes.connected(cell).chain([cell]).filter_map(|c| self.network_of(c)).collect();
+        touching.sort_unstable();
+        touching.dedup();
+        let Some(&keep) = touching.iter().max_by_key(|&&id| (self.networks[&id].len(), std::cmp::Reverse(id))) else {
+            let id = self.fresh_id();
+            self.assign(cell, id);
+            return Vec::new();
+        };
+        let absorbed: Vec<NetworkId> = touching.into_iter().filter(|&id| id != keep).collect();
+        for id in &absorbed {
+            for moved in self.networks.remove(id).into_iter().flatten() {
+                self.of_cell.insert(moved, keep);
+                self.networks.entry(keep).or_default().insert(moved);
+            }
+        }
+        self.assign(cell, keep);
+        absorbed
+    }
+
+    /** Drops `cell` from its network (the pipe was erased); returns the network, which may now be split. */
+    fn leave(&mut self, cell: PipeCell) -> Option<NetworkId> {
+        let id = self.of_cell.remove(&cell)?;
+        let cells = self.networks.get_mut(&id)?;
+        cells.remove(&cell);
+        if cells.is_empty() { self.networks.remove(&id); }
+        Some(id)
+    }
+
+    /** Re-partitions network `id` into its connected parts; the largest keeps `id`. */
+    fn split(&mut self, pipes: &PipeMap, id: NetworkId) {
+        let Some(mut remaining) = self.networks.remove(&id) else { return };
+        let mut parts: Vec<BTreeSet<PipeCell>> = Vec::new();
+        while let Some(start) = remaining.pop_first() {
+            let mut part = BTreeSet::from([start]);
+            let mut queue = VecDeque::from([start]);
+            while let Some(cell) = queue.pop_front() {
+                for next in pipes.connected(cell) {
+                    if remaining.remove(&next) { part.insert(next); queue.push_back(next); }
+                }
+            }
+            parts.push(part);
+        }
+        // Stable sort: among equally large parts the one holding the lowest cell keeps the id.
+        parts.sort_by_key(|part| std::cmp::Reverse(part.len()));
+        for (i, part) in parts.into_iter().enumerate() {
+            let part_id = if i == 0 { id } else { self.fresh_id() };
+            for &cell in &part { self.of_cell.insert(cell, part_id); }
+            self.networks.insert(part_id, part);
+        }
+    }
+
+    /** Rebuilds every network from scratch, numbering them from zero in storage order. */
+    pub fn rebuild(&mut self, pipes: &PipeMap) {
+        *self = Self::default();
+        let (w, h) = pipes.size();
+        for kind in UtilityKind::ALL { for level in 0..pipes.levels() {
+            for y in 0..h { for x in 0..w {
+                let cell = PipeCell::new(kind, level, x, y);
+                if !pipes.is_node(cell) || self.of_cell.contains_key(&cell) { continue }
+                let id = self.fresh_id();
+                self.assign(cell, id);
+                let mut queue = VecDeque::from([cell]);
+                while let Some(cell) = queue.pop_front() {
+                    for next in pipes.connected(cell) {
+                        if self.of_cell.contains_key(&next) { continue }
+                        self.assign(next, id);
+                        queue.push_back(next);
+                    }
+                }
+            }}
+        }}
+    }
+
+    /** Moves every cell by a map growth shift. */
+    fn shift(&mut self, shift: UVec2) {
+        let moved = |c: PipeCell| PipeCell { x: c.x + shift.x, y: c.y + shift.y, ..c };
+        self.of_cell = self.of_cell.drain().map(|(cell, id)| (moved(cell), id)).collect();
+        for cells in self.networks.values_mut() { *cells = cells.iter().map(|&c| moved(c)).collect(); }
+    }
+}
+
+/**
+ * Applies this frame's pipe and riser changes to the networks: new connections merge networks right away,
+ * lost connections re-partition the affected networks once all changes are in. A component change can do
+ * both (inline parts connect on two sides only, crossings keep their two runs apart), so the cell and its
+ * neighbours are re-joined and its old network is re-partitioned. Runs last in `MapSet::Edit`, while
+ * `TileChanged` coordinates still match the map.
+ *
+ * @param changed - applied edits, including undo/redo restores
+ * @param pipes - pipe layer after the edits
+ * @param networks - membership to update
+ */
+pub fn update_pipe_networks(mut changed: MessageReader<TileChanged>, pipes: Res<PipeMap>, mut networks: ResMut<PipeNetworks>) {
+    let mut maybe_split: BTreeSet<NetworkId> = BTreeSet::new();
+    let join = |networks: &mut PipeNetworks, maybe_split: &mut BTreeSet<NetworkId>, cell: PipeCell| {
+        let absorbed = networks.join(&pipes, cell);
+        if absorbed.iter().any(|id| maybe_split.contains(id)) && let Some(id) = networks.network_of(cell) {
+            maybe_split.insert(id);
+        }
+    };
+    for c in changed.read() {
+        let (kind, layer) = match c.layer {
+            CellLayer::Pipe(kind) | CellLayer::Riser(kind) | CellLayer::Component(kind) => (kind, c.layer),
+            _ => continue,
+        };
+        let cell = PipeCell::new(kind, c.level, c.x, c.y);
+        match (layer, c.after.is_some()) {
+            (CellLayer::Component(_), _) => {
+                maybe_split.extend(networks.leave(cell));
+                join(&mut networks, &mut maybe_split, cell);
+                for (x, y) in [(c.x, c.y.wrapping_sub(1)), (c.x + 1, c.y), (c.x, c.y + 1), (c.x.wrapping_sub(1), c.y)] {
+                    join(&mut networks, &mut maybe_split, PipeCell::new(kind, c.level, x, y));
+                }
+            }
+            (_, true) => join(&mut networks, &mut maybe_split, cell),
+            (CellLayer::Pipe(_), false) => { maybe_split.extend(networks.leave(cell)); }
+            _ => { maybe_split.extend(networks.network_of(cell)); }
+        }
+    }
+    for id in maybe_split { networks.split(&pipes, id); }
+}
+
+/** Follows map growth, and rebuilds all networks when the map was replaced by a load (zero growth). */
+pub fn rebase_pipe_networks(mut resized: MessageReader<MapResized>, pipes: Res<PipeMap>, mut networks: ResMut<PipeNetworks>) {
+    for ev in resized.read() {
+        if ev.growth.is_zero() { networks.rebuild(&pipes); } else { networks.shift(ev.growth.shift()); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAS: UtilityKind = UtilityKind::Gas;
+
+    fn cell(x: u32, y: u32) -> PipeCell { PipeCell::new(GAS, 0, x, y) }
+
+    /** Lays a pipe and joins it like `update_pipe_networks` does. */
+    fn place(pipes: &mut PipeMap, networks: &mut PipeNetworks, x: u32, y: u32) {
+        pipes.set(GAS, 0, x, y, true);
+        networks.join(pipes, cell(x, y));
+    }
+
+    /** Erases a pipe and re-partitions its network like `update_pipe_networks` does. */
+    fn erase(pipes: &mut PipeMap, networks: &mut PipeNetworks, x: u32, y: u32) {
+        pipes.set(GAS, 0, x, y, false);
+        if let Some(id) = networks.leave(cell(x, y)) { networks.split(pipes, id); }
+    }
+
+    #[test]
+    fn run_is_one_network_with_two_ends() {
+        let (mut pipes, mut networks) = (PipeMap::new((5, 1), 1), PipeNetworks::default());
+        for x in 0..3 { place(&mut pipes, &mut networks, x, 0); }
+        let ids: Vec<NetworkId> = networks.networks().collect();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(networks.cells(ids[0]).collect::<Vec<_>>(), [cell(0, 0), cell(1, 0), cell(2, 0)]);
+        assert_eq!(networks.endpoints(ids[0], &pipes), [cell(0, 0), cell(2, 0)]);
+    }
+
+    #[test]
+    fn merge_keeps_the_larger_id_and_split_keeps_it_for_the_larger_part() {
+        let (mut pipes, mut networks) = (PipeMap::new((6, 1), 1), PipeNetworks::default());
+        place(&mut pipes, &mut networks, 0, 0);
+        for x in 2..5 { place(&mut pipes, &mut networks, x, 0); }
+        let small = networks.network_of(cell(0, 0)).unwrap();
+        let large = networks.network_of(cell(2, 0)).unwrap();
+        assert_ne!(small, large);
+
+        place(&mut pipes, &mut networks, 1, 0);
+        assert_eq!(networks.networks().collect::<Vec<_>>(), [large]);
+        assert_eq!(networks.cells(large).count(), 5);
+
+        erase(&mut pipes, &mut networks, 1, 0);
+        assert_eq!(networks.network_of(cell(3, 0)), Some(large));
+        let split_off = networks.network_of(cell(0, 0)).unwrap();
+        assert!(split_off != large && split_off != small, "split parts get fresh ids");
+        assert_eq!(networks.networks().count(), 2);
+    }
+
+    #[test]
+    fn kinds_never_share_a_network() {
+        let (mut pipes, mut networks) = (PipeMap::new((2, 1), 1), PipeNetworks::default());
+        pipes.set(GAS, 0, 0, 0, true);
+        pipes.set(UtilityKind::Data, 0, 1, 0, true);
+        pipes.set(GAS, 0, 1, 0, true);
+        networks.rebuild(&pipes);
+        let gas = networks.network_of(cell(1, 0)).unwrap();
+        let data = networks.network_of(PipeCell::new(UtilityKind::Data, 0, 1, 0)).unwrap();
+        assert_ne!(gas, data);
+        assert_eq!(networks.cells(gas).count(), 2);
+        assert_eq!(networks.endpoints(data, &pipes), [PipeCell::new(UtilityKind::Data, 0, 1, 0)]);
+    }
+}
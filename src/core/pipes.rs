@@ -0,0 +1,482 @@
+/**
+ * Utility layers of the map (gas and liquid pipes, power wires, data cables): occupancy, risers and
+ * connectivity masks per kind, deck and cell.
+ * Lives in core next to `MapState` so the edit pipeline can apply pipe edits; the pipe tools and
+ * connectivity rendering are in `gameplay::piping`.
+ */
+use bevy::prelude::*;
+use crate::core::chunk::{ChunkedLayer, DirtyCells};
+use crate::core::fluids::Fluid;
+use crate::core::map::MapGrowth;
+use crate::core::structure::Rotation;
+
+/** Connectivity mask bits: NESW neighbours on the same deck, plus risers to the decks above and below. */
+pub const MASK_N: u8 = 1;
+pub const MASK_E: u8 = 2;
+pub const MASK_S: u8 = 4;
+pub const MASK_W: u8 = 8;
+pub const MASK_UP: u8 = 16;
+pub const MASK_DOWN: u8 = 32;
+
+/** All four same-deck sides. */
+pub const SIDES_ALL: u8 = MASK_N | MASK_E | MASK_S | MASK_W;
+
+/** The side a rotation points at: `R0` is N, turning clockwise through E, S and W. */
+pub fn side_of(rotation: Rotation) -> u8 { [MASK_N, MASK_E, MASK_S, MASK_W][rotation.quarter_turns() as usize] }
+
+/** The side across the cell from `side` (NESW bits only). */
+pub fn opposite_side(side: u8) -> u8 { ((side << 2) | (side >> 2)) & SIDES_ALL }
+
+/**
+ * Independent utility networks. Every kind has its own occupancy, risers and connectivity; segments of
+ * different kinds never connect, even when they share a cell. Ordered by `ALL`.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum UtilityKind { Gas, Liquid, Power, Data }
+
+impl UtilityKind {
+    pub const ALL: [UtilityKind; 4] = [UtilityKind::Gas, UtilityKind::Liquid, UtilityKind::Power, UtilityKind::Data];
+
+    pub fn index(self) -> usize { self as usize }
+
+    /** Stable lowercase name, used in saves and messages. */
+    pub fn name(self) -> &'static str {
+        match self {
+            UtilityKind::Gas => "gas",
+            UtilityKind::Liquid => "liquid",
+            UtilityKind::Power => "power",
+            UtilityKind::Data => "data",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|kind| kind.name() == name) }
+
+    /** What one segment of this kind is called, for player-facing text. */
+    pub fn segment_name(self) -> &'static str {
+        match self {
+            UtilityKind::Gas => "gas pipe",
+            UtilityKind::Liquid => "liquid pipe",
+            UtilityKind::Power => "power wire",
+            UtilityKind::Data => "data cable",
+        }
+    }
+
+    /** Whether segments of this kind are pipes that carry fluid (see `core::fluids`), not wires or cables. */
+    pub fn carries_fluid(self) -> bool { matches!(self, UtilityKind::Gas | UtilityKind::Liquid) }
+}
+
+/** A utility cell: storage cell (x,y) on deck `level` of one utility `kind`. Ordered by kind, deck, row, then column. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct PipeCell { pub kind: UtilityKind, pub level: u32, pub y: u32, pub x: u32 }
+
+impl PipeCell {
+    pub fn new(kind: UtilityKind, level: u32, x: u32, y: u32) -> Self { Self { kind, level, y, x } }
+}
+
+/**
+ * An inline part fitted into a utility segment. Valves, pumps and filters sit inline and connect only on
+ * the two sides along their `rotation` (`R0`/`R180` join N and S, `R90`/`R270` join E and W). A closed valve
+ * stops flow, a pump moves fluid one way toward the side its rotation points at, and a filter passes only
+ * `passes`. Junctions connect all four sides like a plain segment, while a crossing carries N-S and E-W
+ * straight through without joining them: it belongs to no network and holds no fluid itself. Vents and
+ * scrubbers connect all four sides and exchange gas with the cell's atmosphere (see `core::atmosphere`):
+ * a vent releases its network's gas into the room, a scrubber pulls carbon dioxide out of it.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PipeComponent {
+    Valve { rotation: Rotation, open: bool },
+    Pump { rotation: Rotation },
+    Filter { rotation: Rotation, passes: Fluid },
+    Junction,
+    Crossing,
+    Vent,
+    Scrubber,
+}
+
+impl PipeComponent {
+    /** Same-deck sides the component connects on. */
+    pub fn sides(self) -> u8 {
+        match self {
+            PipeComponent::Valve { rotation, .. } | PipeComponent::Pump { rotation } | PipeComponent::Filter { rotation, .. } => {
+                let side = side_of(rotation);
+                side | opposite_side(side)
+            }
+            PipeComponent::Junction | PipeComponent::Crossing | PipeComponent::Vent | PipeComponent::Scrubber => SIDES_ALL,
+        }
+    }
+
+    /** Whether the component only makes sense in a segment that carries fluid. */
+    pub fn needs_fluid(self) -> bool { matches!(self, PipeComponent::Pump { .. } | PipeComponent::Filter { .. }) }
+
+    /** Whether the component only makes sense in a gas pipe. */
+    pub fn needs_gas(self) -> bool { matches!(self, PipeComponent::Vent | PipeComponent::Scrubber) }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PipeComponent::Valve { .. } => "valve",
+            PipeComponent::Pump { .. } => "pump",
+            PipeComponent::Filter { .. } => "filter",
+            PipeComponent::Junction => "junction",
+            PipeComponent::Crossing => "crossing",
+            PipeComponent::Vent => "vent",
+            PipeComponent::Scrubber => "scrubber",
+        }
+    }
+}
+
+/**
+ * Pipe occupancy, risers, inline components and connectivity mask for one deck of one utility kind,
+ * stored in chunks. `riser` marks a vertical segment joining this cell to the same cell on the deck above.
+ */
+struct PipeLevel {
+    present: ChunkedLayer<bool>,
+    riser: ChunkedLayer<bool>,
+    component: ChunkedLayer<Option<PipeComponent>>,
+    mask: ChunkedLayer<u8>,
+    dirty: DirtyCells,
+}
+
+impl PipeLevel {
+    fn new(w: u32, h: u32) -> Self {
+        Self {
+            present: ChunkedLayer::new(w, h, false),
+            riser: ChunkedLayer::new(w, h, false),
+            component: ChunkedLayer::new(w, h, None),
+            mask: ChunkedLayer::new(w, h, 0),
+            dirty: DirtyCells::new(w, h),
+        }
+    }
+}
+
+/**
+ * Occupancy and connectivity mask per utility kind, deck and cell (same dimensions and deck count as the
+ * map). Occupancy, riser and component writes mark their cell dirty; `apply_connectivity_and_tiles`
+ * drains the dirty sets and refreshes those cells and their neighbours.
+ */
+/**
+ * Pipe tiles to repaint after `PipeMap::refresh_masks`, row by row: the mask of each cell (`None` where it
+ * has no segment) and the component of each cell that changed itself.
+ */
+#[derive(Default, PartialEq, Debug)]
+pub struct MaskRefresh { pub masks: Vec<(u32, u32, Option<u8>)>, pub components: Vec<(u32, u32, Option<PipeComponent>)> }
+
+#[derive(Resource)]
+pub struct PipeMap { kinds: Vec<Vec<PipeLevel>>, size: (u32, u32) }
+
+impl PipeMap {
+    pub fn new(size: (u32, u32), levels: u32) -> Self {
+        let (w, h) = size;
+        let kinds = UtilityKind::ALL.iter().map(|_| (0..levels.max(1)).map(|_| PipeLevel::new(w, h)).collect()).collect();
+        Self { kinds, size }
+    }
+
+    fn level(&self, kind: UtilityKind, level: u32) -> &PipeLevel { &self.kinds[kind.index()][level as usize] }
+    fn level_mut(&mut self, kind: UtilityKind, level: u32) -> &mut PipeLevel { &mut self.kinds[kind.index()][level as usize] }
+}
+
+impl PipeMap {
+    pub fn size(&self) -> (u32, u32) { self.size }
+    pub fn levels(&self) -> u32 { self.kinds[0].len() as u32 }
+    pub fn has(&self, kind: UtilityKind, level: u32, x: u32, y: u32) -> bool { self.level(kind, level).present.get(x, y) }
+
+    /** Utility kinds with a segment in cell (x,y) of deck `level`, in `UtilityKind::ALL` order. */
+    pub fn kinds_at(&self, level: u32, x: u32, y: u32) -> impl Iterator<Item = UtilityKind> + '_ {
+        UtilityKind::ALL.into_iter().filter(move |&kind| self.has(kind, level, x, y))
+    }
+
+    /** Whether `cell` is inside the map and holds a segment that is a network node (anything but a crossing). */
+    pub fn is_node(&self, cell: PipeCell) -> bool {
+        let PipeCell { kind, level, x, y } = cell;
+        let (w, h) = self.size;
+        level < self.levels() && x < w && y < h && self.has(kind, level, x, y) && self.component(kind, level, x, y) != Some(PipeComponent::Crossing)
+    }
+
+    /**
+     * Sets segment presence. Removing a segment also removes its component and any riser of its kind into
+     * or out of the cell.
+     */
+    pub fn set(&mut self, kind: UtilityKind, level: u32, x: u32, y: u32, val: bool) {
+        let l = self.level_mut(kind, level);
+        if l.present.set(x, y, val) { l.dirty.mark_cell(x, y); }
+        if !val {
+            self.set_component(kind, level, x, y, None);
+            self.set_riser(kind, level, x, y, false);
+            if level > 0 { self.set_riser(kind, level - 1, x, y, false); }
+        }
+    }
+
+    /** Whether a riser of `kind` joins (level, x, y) to the same cell on the deck above. */
+    pub fn riser(&self, kind: UtilityKind, level: u32, x: u32, y: u32) -> bool { self.level(kind, level).riser.get(x, y) }
+
+    /** Sets a riser on `level`; both decks it joins are marked dirty since both masks change. */
+    pub fn set_riser(&mut self, kind: UtilityKind, level: u32, x: u32, y: u32, val: bool) {
+        if !self.level_mut(kind, level).riser.set(x, y, val) { return }
+        for l in [level, level + 1] {
+            if let Some(l) = self.kinds[kind.index()].get_mut(l as usize) { l.dirty.mark_cell(x, y); }
+        }
+    }
+
+    /** Inline component fitted at (level, x, y) of `kind`, if any. */
+    pub fn component(&self, kind: UtilityKind, level: u32, x: u32, y: u32) -> Option<PipeComponent> {
+        self.level(kind, level).component.get(x, y)
+    }
+
+    /** Every component fitted on `level` of `kind` as `(x, y, component)`, in storage order. */
+    pub fn components_on(&self, kind: UtilityKind, level: u32) -> impl Iterator<Item = (u32, u32, PipeComponent)> + '_ {
+        self.level(kind, level).component.non_default().filter_map(|(x, y, c)| Some((x, y, c?)))
+    }
+
+    /** Fits or removes a component; unvalidated, the edit pipeline checks there is a segment to fit it in. */
+    pub fn set_component(&mut self, kind: UtilityKind, level: u32, x: u32, y: u32, component: Option<PipeComponent>) {
+        let l = self.level_mut(kind, level);
+        if l.component.set(x, y, component) { l.dirty.mark_cell(x, y); }
+    }
+
+    pub fn set_mask(&mut self, kind: UtilityKind, level: u32, x: u32, y: u32, m: u8) { self.level_mut(kind, level).mask.set(x, y, m); }
+    pub fn get_mask(&self, kind: UtilityKind, level: u32, x: u32, y: u32) -> u8 { self.level(kind, level).mask.get(x, y) }
+
+    /** Marks every cell of every kind for a connectivity/tile refresh, e.g. after a load. */
+    pub fn mark_all_dirty(&mut self) {
+        for l in self.kinds.iter_mut().flatten() { l.dirty.mark_all(); }
+    }
+
+    /** Drains the cells of `kind` on `level` changed since the last call. Single consumer: pipe connectivity. */
+    pub fn take_dirty_cells(&mut self, kind: UtilityKind, level: u32) -> Vec<UVec2> { self.level_mut(kind, level).dirty.take() }
+
+    /** Returns a copy grown to match a `MapState` growth, fully dirty so every pipe tile is rebuilt. */
+    pub fn expanded(&self, growth: MapGrowth) -> Self {
+        let (w, h) = self.size;
+        let shift = growth.shift();
+        let mut out = PipeMap::new((w + growth.left + growth.right, h + growth.top + growth.bottom), self.levels());
+        for kind in UtilityKind::ALL {
+            for level in 0..self.levels() {
+                for y in 0..h { for x in 0..w {
+                    let (nx, ny) = (x + shift.x, y + shift.y);
+                    out.set(kind, level, nx, ny, self.has(kind, level, x, y));
+                    out.set_riser(kind, level, nx, ny, self.riser(kind, level, x, y));
+                    out.set_component(kind, level, nx, ny, self.component(kind, level, x, y));
+                }}
+            }
+        }
+        out.mark_all_dirty();
+        out
+    }
+
+    /**
+     * Computes the connectivity mask of an occupied cell: NESW neighbours of the same kind on the same
+     * deck where both cells connect on the shared side (components limit their sides), plus
+     * `MASK_UP`/`MASK_DOWN` where a riser joins a segment on the adjacent deck.
+     */
+    pub fn compute_mask(&self, kind: UtilityKind, level: u32, x: u32, y: u32) -> u8 {
+        let (w, h) = self.size;
+        let sides = |x: u32, y: u32| self.component(kind, level, x, y).map_or(SIDES_ALL, PipeComponent::sides);
+        let own = sides(x, y);
+        let joins = |x: i32, y: i32, back: u8| {
+            x >= 0 && y >= 0 && (x as u32) < w && (y as u32) < h
+                && self.has(kind, level, x as u32, y as u32) && sides(x as u32, y as u32) & back != 0
+        };
+        let (xi, yi) = (x as i32, y as i32);
+        let mut mask = 0;
+        if own & MASK_N != 0 && joins(xi, yi - 1, MASK_S) { mask |= MASK_N; }
+        if own & MASK_E != 0 && joins(xi + 1, yi, MASK_W) { mask |= MASK_E; }
+        if own & MASK_S != 0 && joins(xi, yi + 1, MASK_N) { mask |= MASK_S; }
+        if own & MASK_W != 0 && joins(xi - 1, yi, MASK_E) { mask |= MASK_W; }
+        if level + 1 < self.levels() && self.riser(kind, level, x, y) && self.has(kind, level + 1, x, y) { mask |= MASK_UP; }
+        if level > 0 && self.riser(kind, level - 1, x, y) && self.has(kind, level - 1, x, y) { mask |= MASK_DOWN; }
+        mask
+    }
+
+    /**
+     * Recomputes and stores the masks of the `dirty` cells of `kind` on `level` and of their in-bounds
+     * neighbours, whose masks depend on them (`rebuild` skips the neighbours when `dirty` lists every cell).
+     * Returns the tiles to repaint: every dirty cell, and a neighbour only when its mask changed.
+     */
+    pub fn refresh_masks(&mut self, kind: UtilityKind, level: u32, dirty: &[UVec2], rebuild: bool) -> MaskRefresh {
+        let (w, h) = self.size;
+        // (x, y, changed itself) for every dirty cell and neighbour; a cell listed both ways counts as changed.
+        let mut cells: Vec<(u32, u32, bool)> = Vec::with_capacity(dirty.len() * 5);
+        for &UVec2 { x, y } in dirty {
+            cells.push((x, y, true));
+            if rebuild { continue }
+            if y > 0 { cells.push((x, y - 1, false)); }
+            if x + 1 < w { cells.push((x + 1, y, false)); }
+            if y + 1 < h { cells.push((x, y + 1, false)); }
+            if x > 0 { cells.push((x - 1, y, false)); }
+        }
+        cells.sort_unstable_by_key(|&(x, y, changed)| (y, x, !changed));
+        cells.dedup_by_key(|&mut (x, y, _)| (x, y));
+
+        let mut out = MaskRefresh { masks: Vec::with_capacity(cells.len()), components: Vec::with_capacity(dirty.len()) };
+        for (x, y, changed) in cells {
+            let before = self.get_mask(kind, level, x, y);
+            let mask = self.has(kind, level, x, y).then(|| self.compute_mask(kind, level, x, y));
+            self.set_mask(kind, level, x, y, mask.unwrap_or(0));
+            if changed || mask.is_some_and(|mask| mask != before) { out.masks.push((x, y, mask)); }
+            if changed { out.components.push((x, y, self.component(kind, level, x, y))); }
+        }
+        out
+    }
+
+    /**
+     * Network nodes connected to `cell`, each with the mask bit of the side it is reached through. Follows
+     * the same rules as `compute_mask`, and carries straight on through crossings to the node beyond.
+     * None if `cell` is not a node.
+     */
+    pub fn connected_sides(&self, cell: PipeCell) -> impl Iterator<Item = (u8, PipeCell)> + use<> {
+        let mut out = Vec::new();
+        if self.is_node(cell) {
+            let mask = self.compute_mask(cell.kind, cell.level, cell.x, cell.y);
+            for bit in [MASK_N, MASK_E, MASK_S, MASK_W, MASK_UP, MASK_DOWN] {
+                if mask & bit == 0 { continue }
+                let mut next = step(cell, bit);
+                while bit & SIDES_ALL != 0 && self.component(next.kind, next.level, next.x, next.y) == Some(PipeComponent::Crossing) {
+                    if self.compute_mask(next.kind, next.level, next.x, next.y) & bit == 0 { break }
+                    next = step(next, bit);
+                }
+                if self.is_node(next) { out.push((bit, next)); }
+            }
+        }
+        out.into_iter()
+    }
+
+    /** Network nodes connected to `cell`; see `connected_sides`. */
+    pub fn connected(&self, cell: PipeCell) -> impl Iterator<Item = PipeCell> + use<> {
+        self.connected_sides(cell).map(|(_, neighbour)| neighbour)
+    }
+}
+
+/** The cell one step from `cell` through the side or riser `bit`. */
+fn step(cell: PipeCell, bit: u8) -> PipeCell {
+    let PipeCell { level, x, y, .. } = cell;
+    match bit {
+        MASK_N => PipeCell { y: y.wrapping_sub(1), ..cell },
+        MASK_E => PipeCell { x: x + 1, ..cell },
+        MASK_S => PipeCell { y: y + 1, ..cell },
+        MASK_W => PipeCell { x: x.wrapping_sub(1), ..cell },
+        MASK_UP => PipeCell { level: level + 1, ..cell },
+        _ => PipeCell { level: level.wrapping_sub(1), ..cell },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAS: UtilityKind = UtilityKind::Gas;
+
+    /** Masks of every occupied cell of every kind, as `(kind, level, x, y, mask)`. */
+    fn masks(pipes: &PipeMap) -> Vec<(UtilityKind, u32, u32, u32, u8)> {
+        let (w, h) = pipes.size();
+        let mut out = Vec::new();
+        for kind in UtilityKind::ALL { for level in 0..pipes.levels() { for y in 0..h { for x in 0..w {
+            if pipes.has(kind, level, x, y) { out.push((kind, level, x, y, pipes.compute_mask(kind, level, x, y))); }
+        }}}}
+        out
+    }
+
+    #[test]
+    fn growing_left_and_down_keeps_connectivity() {
+        let mut pipes = PipeMap::new((4, 4), 2);
+        for (x, y) in [(0, 0), (1, 0), (2, 0), (2, 1)] { pipes.set(GAS, 0, x, y, true); }
+        pipes.set_component(GAS, 0, 1, 0, Some(PipeComponent::Valve { rotation: Rotation::R90, open: true }));
+        pipes.set(GAS, 1, 2, 1, true);
+        pipes.set_riser(GAS, 0, 2, 1, true);
+        pipes.set(UtilityKind::Liquid, 0, 3, 3, true);
+
+        let growth = MapGrowth { left: 5, bottom: 1, ..default() };
+        let mut grown = pipes.expanded(growth);
+        assert_eq!((grown.size(), grown.levels()), ((9, 5), 2));
+        let shifted: Vec<_> = masks(&pipes).into_iter().map(|(kind, level, x, y, mask)| (kind, level, x + 5, y + 1, mask)).collect();
+        assert_eq!(masks(&grown), shifted);
+        assert_eq!(grown.compute_mask(GAS, 0, 7, 2), MASK_N | MASK_UP);
+        assert_eq!(grown.component(GAS, 0, 6, 1), Some(PipeComponent::Valve { rotation: Rotation::R90, open: true }));
+        assert_eq!(grown.take_dirty_cells(GAS, 1).len(), 9 * 5, "every cell is refreshed after growth");
+    }
+
+    #[test]
+    fn risers_join_two_decks_only_when_both_ends_have_a_segment() {
+        let mut pipes = PipeMap::new((3, 3), 2);
+        pipes.set(GAS, 0, 1, 1, true);
+        pipes.set_riser(GAS, 0, 1, 1, true);
+        assert_eq!(pipes.compute_mask(GAS, 0, 1, 1), 0, "no segment above yet");
+        pipes.set(GAS, 1, 1, 1, true);
+        assert_eq!(pipes.compute_mask(GAS, 0, 1, 1), MASK_UP);
+        assert_eq!(pipes.compute_mask(GAS, 1, 1, 1), MASK_DOWN);
+        assert_eq!(pipes.compute_mask(UtilityKind::Liquid, 1, 1, 1), 0, "risers only join their own kind");
+
+        pipes.take_dirty_cells(GAS, 0);
+        pipes.set(GAS, 1, 1, 1, false);
+        assert!(!pipes.riser(GAS, 0, 1, 1), "removing the upper segment removes the riser into it");
+        assert_eq!(pipes.take_dirty_cells(GAS, 0), [UVec2::new(1, 1)], "the lower deck is refreshed too");
+        assert_eq!(pipes.compute_mask(GAS, 0, 1, 1), 0);
+    }
+
+    #[test]
+    fn stacked_risers_join_every_deck() {
+        let mut pipes = PipeMap::new((2, 2), 3);
+        for level in 0..3 { pipes.set(GAS, level, 0, 0, true); }
+        for level in 0..2 { pipes.set_riser(GAS, level, 0, 0, true); }
+        pipes.set(GAS, 1, 1, 0, true);
+        assert_eq!(pipes.compute_mask(GAS, 0, 0, 0), MASK_UP);
+        assert_eq!(pipes.compute_mask(GAS, 1, 0, 0), MASK_UP | MASK_DOWN | MASK_E);
+        assert_eq!(pipes.compute_mask(GAS, 2, 0, 0), MASK_DOWN);
+        let up: Vec<_> = pipes.connected(PipeCell::new(GAS, 1, 0, 0)).collect();
+        assert!(up.contains(&PipeCell::new(GAS, 0, 0, 0)) && up.contains(&PipeCell::new(GAS, 2, 0, 0)));
+    }
+
+    /** Full recompute of every mask of `kind` on `level`, as `refresh_masks` would store it. */
+    fn full_masks(pipes: &PipeMap, kind: UtilityKind, level: u32) -> Vec<u8> {
+        let (w, h) = pipes.size();
+        (0..h).flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| if pipes.has(kind, level, x, y) { pipes.compute_mask(kind, level, x, y) } else { 0 })
+            .collect()
+    }
+
+    fn stored_masks(pipes: &PipeMap, kind: UtilityKind, level: u32) -> Vec<u8> {
+        let (w, h) = pipes.size();
+        (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| pipes.get_mask(kind, level, x, y)).collect()
+    }
+
+    /** Refreshes whatever is dirty for gas on deck 0, as render sync does. */
+    fn refresh(pipes: &mut PipeMap) -> MaskRefresh {
+        let dirty = pipes.take_dirty_cells(GAS, 0);
+        pipes.refresh_masks(GAS, 0, &dirty, false)
+    }
+
+    #[test]
+    fn refresh_matches_a_full_recompute_and_repaints_only_changed_neighbours() {
+        let mut pipes = PipeMap::new((4, 4), 1);
+        for (x, y) in [(1, 0), (1, 1), (2, 2)] { pipes.set(GAS, 0, x, y, true); }
+        // A valve joining only east and west: a segment laid south of it leaves its mask as it was.
+        pipes.set_component(GAS, 0, 1, 1, Some(PipeComponent::Valve { rotation: Rotation::R90, open: true }));
+        refresh(&mut pipes);
+        assert_eq!(stored_masks(&pipes, GAS, 0), full_masks(&pipes, GAS, 0));
+
+        pipes.set(GAS, 0, 1, 2, true);
+        let refreshed = refresh(&mut pipes);
+        assert_eq!(stored_masks(&pipes, GAS, 0), full_masks(&pipes, GAS, 0));
+        assert_eq!(refreshed.masks, [(1, 2, Some(MASK_E)), (2, 2, Some(MASK_W))], "the valve is not repainted");
+        assert_eq!(refreshed.components, [(1, 2, None)], "only the edited cell's component is repainted");
+
+        pipes.set(GAS, 0, 3, 3, true);
+        let refreshed = refresh(&mut pipes);
+        assert_eq!(refreshed.masks, [(3, 3, Some(0))], "unconnected neighbours keep their masks and tiles");
+    }
+
+    #[test]
+    fn a_dirty_cell_that_is_also_a_neighbour_stays_changed() {
+        let mut pipes = PipeMap::new((4, 4), 1);
+        pipes.set(GAS, 0, 1, 1, true);
+        pipes.set(GAS, 0, 2, 1, true);
+        pipes.set_component(GAS, 0, 2, 1, Some(PipeComponent::Junction));
+        let refreshed = refresh(&mut pipes);
+        assert_eq!(refreshed.components, [(1, 1, None), (2, 1, Some(PipeComponent::Junction))]);
+        assert!(refreshed.masks.contains(&(1, 1, Some(MASK_E))) && refreshed.masks.contains(&(2, 1, Some(MASK_W))));
+
+        pipes.set(GAS, 0, 1, 1, false);
+        pipes.set_component(GAS, 0, 2, 1, None);
+        let refreshed = refresh(&mut pipes);
+        assert_eq!(refreshed.components, [(1, 1, None), (2, 1, None)]);
+        assert!(refreshed.masks.contains(&(1, 1, None)), "a cleared cell is repainted even though its stored mask was reset");
+    }
+}
@@ -0,0 +1,174 @@
+/**
+ * Multi-cell structures (machines, tanks, beds). A catalog structure declares a rectangular footprint,
+ * optionally masked, and is placed in one of four rotations. Every covered cell of a deck's structure
+ * layer holds a `StructurePart` naming the structure, its rotation and the cell's offset from the
+ * placement anchor, so any cell leads back to the whole structure without a separate instance table.
+ */
+use bevy::prelude::*;
+use crate::core::tile::{PlacementRule, TileProperties};
+
+/**
+ * Compact structure identifier interned from the catalog's string ids, like `TileId`.
+ * Only valid for the `Tileset` that produced it; persist `StructureDef::name` instead.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct StructureId(pub u16);
+
+impl StructureId {
+    pub fn index(self) -> usize { self.0 as usize }
+}
+
+/** Clockwise quarter turns applied to a structure's footprint and sprite. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Rotation { #[default] R0, R90, R180, R270 }
+
+impl Rotation {
+    pub const ALL: [Rotation; 4] = [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270];
+
+    pub fn quarter_turns(self) -> u8 { self as u8 }
+
+    pub fn from_quarter_turns(turns: u8) -> Self { Self::ALL[(turns % 4) as usize] }
+
+    /** The next rotation clockwise. */
+    pub fn next(self) -> Self { Self::from_quarter_turns(self.quarter_turns() + 1) }
+}
+
+/** One covered cell of a placed structure; the anchor is the cell's position minus `offset`. */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StructurePart {
+    pub structure: StructureId,
+    pub rotation: Rotation,
+    pub offset: UVec2,
+}
+
+/**
+ * Runtime definition of a structure. `cells` are the covered offsets of the unrotated footprint
+ * within `size` (y up, like storage cells); the first one is the primary cell, which carries the
+ * structure's cost and sprite.
+ */
+pub struct StructureDef {
+    pub name: String,
+    pub size: UVec2,
+    pub cells: Vec<UVec2>,
+    pub color: Color,
+    pub sprite: Option<Handle<Image>>,
+    pub props: TileProperties,
+    pub rules: Vec<PlacementRule>,
+}
+
+impl StructureDef {
+    /** Footprint bounds after `rotation`. */
+    pub fn rotated_size(&self, rotation: Rotation) -> UVec2 {
+        if rotation.quarter_turns() % 2 == 1 { self.size.yx() } else { self.size }
+    }
+
+    /** Covered offsets from the anchor after `rotation`, primary cell first. */
+    pub fn footprint(&self, rotation: Rotation) -> impl Iterator<Item = UVec2> + '_ {
+        self.cells.iter().map(move |&cell| self.rotate(cell, rotation))
+    }
+
+    /** Offset of the primary cell after `rotation`. */
+    pub fn primary(&self, rotation: Rotation) -> UVec2 { self.rotate(self.cells[0], rotation) }
+
+    fn rotate(&self, cell: UVec2, rotation: Rotation) -> UVec2 {
+        let (mut cell, mut size) = (cell, self.size);
+        for _ in 0..rotation.quarter_turns() {
+            // Clockwise with y up: the left edge becomes the top edge.
+            cell = UVec2::new(cell.y, size.x - 1 - cell.x);
+            size = size.yx();
+        }
+        cell
+    }
+}
+
+/**
+ * Parses a catalog footprint mask: one string per row, top row first, `#` for a covered cell and `.` for
+ * a free one. Returns the covered cells (y up) or why the mask is malformed.
+ *
+ * @param size - footprint bounds the mask must match
+ * @param mask - rows as written in the catalog
+ */
+pub fn parse_mask(size: UVec2, mask: &[String]) -> Result<Vec<UVec2>, String> {
+    if mask.len() != size.y as usize { return Err(format!("mask has {} rows, expected {}", mask.len(), size.y)) }
+    let mut cells = Vec::new();
+    for (row, line) in mask.iter().enumerate() {
+        if line.chars().count() != size.x as usize { return Err(format!("mask row {row} is not {} cells wide", size.x)) }
+        let y = size.y - 1 - row as u32;
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                '#' => cells.push(UVec2::new(x as u32, y)),
+                '.' => {}
+                other => return Err(format!("mask row {row} has '{other}', expected '#' or '.'")),
+            }
+        }
+    }
+    if cells.is_empty() { return Err("mask covers no cells".to_string()) }
+    cells.sort_unstable_by_key(|c| (c.y, c.x));
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(mask: &[&str]) -> Vec<String> { mask.iter().map(|row| row.to_string()).collect() }
+
+    /** The L-shaped 2x2 `Machine` mask: the top-right cell is free. */
+    fn machine() -> StructureDef {
+        let size = UVec2::new(2, 2);
+        StructureDef {
+            name: "Machine".into(),
+            size,
+            cells: parse_mask(size, &rows(&["#.", "##"])).unwrap(),
+            color: Color::WHITE,
+            sprite: None,
+            props: TileProperties::default(),
+            rules: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn mask_rows_run_top_to_bottom() {
+        let cells = parse_mask(UVec2::new(2, 2), &rows(&["#.", "##"])).unwrap();
+        assert_eq!(cells, [UVec2::new(0, 0), UVec2::new(1, 0), UVec2::new(0, 1)]);
+        let cells = parse_mask(UVec2::new(3, 1), &rows(&[".##"])).unwrap();
+        assert_eq!(cells, [UVec2::new(1, 0), UVec2::new(2, 0)]);
+    }
+
+    #[test]
+    fn malformed_masks_are_rejected() {
+        let size = UVec2::new(2, 2);
+        assert!(parse_mask(size, &rows(&["##"])).is_err(), "too few rows");
+        assert!(parse_mask(size, &rows(&["##", "#"])).is_err(), "short row");
+        assert!(parse_mask(size, &rows(&["#x", "##"])).is_err(), "unknown cell");
+        assert!(parse_mask(size, &rows(&["..", ".."])).is_err(), "no cells");
+    }
+
+    #[test]
+    fn rotation_turns_clockwise_and_wraps() {
+        assert_eq!(Rotation::R270.next(), Rotation::R0);
+        assert_eq!(Rotation::from_quarter_turns(5), Rotation::R90);
+        let tall = StructureDef { size: UVec2::new(1, 2), cells: vec![UVec2::ZERO, UVec2::Y], ..machine() };
+        assert_eq!(tall.rotated_size(Rotation::R90), UVec2::new(2, 1));
+        assert_eq!(tall.rotated_size(Rotation::R180), UVec2::new(1, 2));
+        // Clockwise with y up: the bottom cell of a standing bed ends up on the left.
+        assert_eq!(tall.footprint(Rotation::R90).collect::<Vec<_>>(), [UVec2::new(0, 0), UVec2::new(1, 0)]);
+        assert_eq!(tall.primary(Rotation::R180), UVec2::new(0, 1));
+    }
+
+    #[test]
+    fn rotated_footprint_stays_in_bounds_and_keeps_its_shape() {
+        let def = machine();
+        for rotation in Rotation::ALL {
+            let size = def.rotated_size(rotation);
+            let cells: Vec<UVec2> = def.footprint(rotation).collect();
+            assert_eq!(cells.len(), def.cells.len());
+            assert!(cells.iter().all(|c| c.x < size.x && c.y < size.y), "{rotation:?}: {cells:?}");
+            assert_eq!(cells[0], def.primary(rotation));
+        }
+        // The free corner moves clockwise: top-right, bottom-right, bottom-left, top-left.
+        let free = |rotation| (0..2).flat_map(|y| (0..2).map(move |x| UVec2::new(x, y)))
+            .find(|c| !def.footprint(rotation).any(|f| f == *c)).unwrap();
+        assert_eq!(Rotation::ALL.map(free), [UVec2::new(1, 1), UVec2::new(1, 0), UVec2::new(0, 0), UVec2::new(0, 1)]);
+    }
+}
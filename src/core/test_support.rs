@@ -0,0 +1,57 @@
+/**
+ * Scaffolding shared by unit tests that drive core state directly, without an app. Test modules add their
+ * own helpers to `Fixture` in an `impl Fixture` block next to their tests.
+ */
+use crate::core::atmosphere::Atmosphere;
+use crate::core::edit::MapEditor;
+use crate::core::fluids::FluidState;
+use crate::core::inventory::Inventory;
+use crate::core::map::{MapSize, MapState};
+use crate::core::pipes::PipeMap;
+use crate::core::tile::{TileId, Tileset};
+
+/** One of each map-sized resource over a shared footprint, with the stock the tileset's materials start with. */
+pub(crate) struct Fixture {
+    pub map: MapState,
+    pub pipes: PipeMap,
+    pub tileset: Tileset,
+    pub inventory: Inventory,
+    pub fluids: FluidState,
+    pub air: Atmosphere,
+}
+
+impl Fixture {
+    /** An empty `w` x `h` map with `levels` decks over `tileset`. */
+    pub fn new(tileset: Tileset, w: u32, h: u32, levels: u32) -> Self {
+        let mut inventory = Inventory::default();
+        inventory.stock_materials(&tileset.materials);
+        Self {
+            map: MapState::new(MapSize { w, h }, levels),
+            pipes: PipeMap::new((w, h), levels),
+            tileset,
+            inventory,
+            fluids: FluidState::default(),
+            air: Atmosphere::default(),
+        }
+    }
+
+    /**
+     * A single deck drawn row by row from `rows` (the first row is y = 0): each character is the base tile
+     * `legend` names for it, any other character leaves the cell `Empty`.
+     */
+    pub fn drawn(tileset: Tileset, rows: &[&str], legend: &[(char, &str)]) -> Self {
+        let mut fx = Self::new(tileset, rows[0].len() as u32, rows.len() as u32, 1);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let Some(&(_, name)) = legend.iter().find(|(key, _)| *key == c) else { continue };
+                let id = fx.tile(name);
+                fx.map.set_base(0, x as u32, y as u32, id);
+            }
+        }
+        fx
+    }
+
+    pub fn tile(&self, name: &str) -> TileId { self.tileset.id(name).unwrap() }
+
+    pub fn editor(&mut self) -> MapEditor<'_> { MapEditor::new(&mut self.map, &mut self.pipes, &mut self.fluids, &self.tileset, &mut self.inventory) }
+}
@@ -1,36 +1,213 @@
 use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use crate::core::catalog::TileCatalog;
+use crate::core::inventory::MaterialCost;
+use crate::core::structure::{StructureDef, StructureId};
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum TileId { Empty, Dirt, Marker }
+/**
+ * Compact numeric tile identifier interned from the catalog's string ids.
+ * Only valid for the `Tileset` that produced it; persist `TileDef::name` instead.
+ * Index 0 is always the built-in `Empty` tile.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TileId(pub u16);
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+impl TileId {
+    /** The always-present empty tile. */
+    pub const EMPTY: TileId = TileId(0);
+
+    pub fn index(self) -> usize { self.0 as usize }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum TileLayer { Base, Overlay }
 
+impl TileLayer {
+    /** Parses the catalog's `layer` string ("Base" / "Overlay"). */
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "Base" => Some(TileLayer::Base),
+            "Overlay" => Some(TileLayer::Overlay),
+            _ => None,
+        }
+    }
+}
+
 pub struct TileDef {
     pub id: TileId,
+    pub name: String,
     pub layer: TileLayer,
     pub color: Color,
+    pub sprite: Option<TileSprite>,
+    pub props: TileProperties,
+    pub rules: Vec<PlacementRule>,
+}
+
+/**
+ * Placement rules a tile declares under `rules` in the catalog (snake_case names); evaluated by
+ * `gameplay::rules` before core applies a placement.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementRule {
+    /** The base tile of the cell must be tagged "floor". */
+    RequiresFloor,
+    /** The base tile of the cell must not be `Empty`. */
+    RequiresBase,
+    /** The target layer of the cell must be empty; replacing a different tile needs a removal first. */
+    NoOverlap,
+}
+
+/**
+ * Gameplay properties of a tile, declared under `properties` in the catalog. Every key is optional;
+ * omitted keys take the defaults below, which describe inert, non-blocking empty space.
+ */
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TileProperties {
+    /** Crew can stand on / move through this tile. */
+    pub walkable: bool,
+    /** Blocks gas flow between neighboring cells. */
+    pub airtight: bool,
+    /** Supports tiles built on or against it. */
+    pub structural: bool,
+    /** Material units consumed to build one tile, by material id; empty means free. */
+    pub build_cost: MaterialCost,
+    /** Seconds of work needed to build one tile. */
+    pub build_time: f32,
+    /** Damage the tile can take before it is destroyed; 0 means indestructible. */
+    pub max_integrity: u32,
+    /** Relative heat transfer rate to neighboring cells. */
+    pub thermal_conductivity: f32,
+    /** Free-form labels for rules and queries, e.g. "floor" or "wall". */
+    pub tags: Vec<String>,
+}
+
+/** A building material declared by the catalog, and how much of it a new station starts with. */
+#[derive(Clone, Debug)]
+pub struct MaterialDef {
+    pub name: String,
+    pub starting: u32,
+}
+
+/** Atlas art for a tile. `frames[0]` is the primary atlas cell, the rest are variants. */
+#[derive(Clone)]
+pub struct TileSprite {
+    pub atlas: Handle<Image>,
+    pub frames: Vec<u32>,
 }
 
+/**
+ * Runtime tile definitions built from the loaded `TileCatalog`.
+ * `defs` is indexed by `TileId`; `by_name` interns the catalog's string ids.
+ * `materials` are the catalog's building materials that `build_cost`s refer to; `structures` are indexed
+ * by `StructureId`.
+ */
 #[derive(Resource)]
 pub struct Tileset {
     pub defs: Vec<TileDef>,
+    pub materials: Vec<MaterialDef>,
+    pub structures: Vec<StructureDef>,
+    by_name: HashMap<String, TileId>,
+    structure_by_name: HashMap<String, StructureId>,
 }
 
 impl Default for Tileset {
+    /** Empty-only tileset used until the catalog finishes loading. */
     fn default() -> Self {
-        Self {
-            defs: vec![
-                TileDef { id: TileId::Empty, layer: TileLayer::Base, color: Color::NONE },
-                TileDef { id: TileId::Dirt, layer: TileLayer::Base, color: Color::srgb(0.55, 0.42, 0.35) },
-                TileDef { id: TileId::Marker, layer: TileLayer::Overlay, color: Color::srgb(1.0, 1.0, 0.0) },
-            ],
-        }
+        let mut tileset = Self {
+            defs: Vec::new(),
+            materials: Vec::new(),
+            structures: Vec::new(),
+            by_name: HashMap::new(),
+            structure_by_name: HashMap::new(),
+        };
+        tileset.push("Empty", TileLayer::Base, Color::NONE);
+        tileset
     }
 }
 
 impl Tileset {
+    /**
+     * Builds a tileset from catalog entries, assigning TileIds in catalog order after `Empty`.
+     * An `Empty` entry in the catalog overrides the built-in empty tile's color and properties (its sprite is ignored).
+     *
+     * @param catalog - loaded catalog asset
+     */
+    pub fn from_catalog(catalog: &TileCatalog) -> anyhow::Result<Self> {
+        let materials = catalog.materials.iter().map(|m| MaterialDef { name: m.id.clone(), starting: m.starting }).collect();
+        let mut tileset = Self { materials, ..Self::default() };
+        for entry in &catalog.defs {
+            let Some(layer) = TileLayer::parse(&entry.layer) else {
+                anyhow::bail!("tile '{}' has unknown layer '{}'", entry.id, entry.layer);
+            };
+            let [r, g, b, a] = entry.color;
+            let color = Color::srgba(r, g, b, a);
+            if entry.id == "Empty" {
+                tileset.defs[0].color = color;
+                tileset.defs[0].props = entry.properties.known.clone();
+                continue;
+            }
+            if tileset.by_name.contains_key(&entry.id) {
+                anyhow::bail!("duplicate tile id '{}'", entry.id);
+            }
+            let id = tileset.push(&entry.id, layer, color);
+            let def = &mut tileset.defs[id.index()];
+            def.props = entry.properties.known.clone();
+            def.rules = entry.rules.clone();
+            def.sprite = entry.sprite.as_ref().map(|s| TileSprite {
+                atlas: s.handle.clone(),
+                frames: std::iter::once(s.index).chain(s.variants.iter().copied()).collect(),
+            });
+        }
+        for entry in &catalog.structures {
+            let cells = entry.cells().map_err(|err| anyhow::anyhow!("structure '{}': {err}", entry.id))?;
+            let id = StructureId(tileset.structures.len() as u16);
+            if tileset.structure_by_name.insert(entry.id.clone(), id).is_some() {
+                anyhow::bail!("duplicate structure id '{}'", entry.id);
+            }
+            let [r, g, b, a] = entry.color;
+            tileset.structures.push(StructureDef {
+                name: entry.id.clone(),
+                size: UVec2::from_array(entry.size),
+                cells,
+                color: Color::srgba(r, g, b, a),
+                sprite: entry.sprite_handle.clone(),
+                props: entry.properties.known.clone(),
+                rules: entry.rules.clone(),
+            });
+        }
+        Ok(tileset)
+    }
+
+    fn push(&mut self, name: &str, layer: TileLayer, color: Color) -> TileId {
+        let id = TileId(self.defs.len() as u16);
+        self.defs.push(TileDef { id, name: name.to_string(), layer, color, sprite: None, props: TileProperties::default(), rules: Vec::new() });
+        self.by_name.insert(name.to_string(), id);
+        id
+    }
+
     pub fn def(&self, id: TileId) -> &TileDef {
-        self.defs.iter().find(|def| def.id == id).unwrap()
+        &self.defs[id.index()]
+    }
+
+    /** Looks up the interned id for a catalog string id. */
+    pub fn id(&self, name: &str) -> Option<TileId> {
+        self.by_name.get(name).copied()
     }
-}
\ No newline at end of file
+
+    pub fn structure(&self, id: StructureId) -> &StructureDef {
+        &self.structures[id.index()]
+    }
+
+    /** Looks up the interned id for a catalog structure id. */
+    pub fn structure_id(&self, name: &str) -> Option<StructureId> {
+        self.structure_by_name.get(name).copied()
+    }
+
+    /** Gameplay properties of a tile. */
+    pub fn props(&self, id: TileId) -> &TileProperties { &self.def(id).props }
+
+    pub fn has_tag(&self, id: TileId, tag: &str) -> bool { self.props(id).tags.iter().any(|t| t == tag) }
+}
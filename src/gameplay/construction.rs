@@ -0,0 +1,113 @@
+/**
+ * Construction of blueprints. Tools place tiles as `CellContent::Planned`; every blueprint is a job that
+ * accumulates work and, once it reaches the tile's `build_time`, is swapped for the built tile with a
+ * `ConstructTile`, which the edit pipeline applies without recording it for undo. Until workers exist the
+ * clock does the work; a blueprint whose rules are not met yet (e.g. a ladder on a floor that is itself
+ * still planned) waits. Cancelling is removing the blueprint.
+ */
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::core::events::{CellContent, CellLayer, ConstructTile, MapResized, PlaceTile, TileChanged};
+use crate::core::map::{MapSet, MapState};
+use crate::core::pipes::PipeMap;
+use crate::core::tile::{TileLayer, Tileset};
+use crate::gameplay::rules::check_placement;
+
+/** Identifies a job: the blueprint on `layer` of cell (x,y) on deck `level`. */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct JobKey { pub level: u32, pub x: u32, pub y: u32, pub layer: TileLayer }
+
+/** Work done so far (in seconds) on every blueprint of the map. Kept in step with the planned layers. */
+#[derive(Resource, Default)]
+pub struct ConstructionJobs { work: HashMap<JobKey, f32> }
+
+pub struct ConstructionPlugin;
+
+impl Plugin for ConstructionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConstructionJobs>()
+            .add_systems(Update, advance_construction)
+            .add_systems(PostUpdate, track_construction_jobs.in_set(MapSet::Rebuild));
+    }
+}
+
+/** The tile layer of a blueprint cell layer. */
+fn planned_layer(layer: CellLayer) -> Option<TileLayer> {
+    match layer {
+        CellLayer::PlannedBase => Some(TileLayer::Base),
+        CellLayer::PlannedOverlay => Some(TileLayer::Overlay),
+        _ => None,
+    }
+}
+
+/**
+ * Opens a job for every new blueprint and drops it when the blueprint is built, cancelled or replaced.
+ * Edits are reported in pre-growth coordinates, so they are applied before jobs follow a map growth; a
+ * load (zero growth) replaced the map, so jobs are re-read from it with no work done.
+ *
+ * @param changed - applied edits, including undo/redo restores
+ * @param resized - map growth or replacement
+ * @param map - planned layers to re-read after a load
+ * @param jobs - jobs to keep in step
+ */
+fn track_construction_jobs(
+    mut changed: MessageReader<TileChanged>,
+    mut resized: MessageReader<MapResized>,
+    map: Res<MapState>,
+    mut jobs: ResMut<ConstructionJobs>,
+) {
+    for c in changed.read() {
+        let Some(layer) = planned_layer(c.layer) else { continue };
+        let key = JobKey { level: c.level, x: c.x, y: c.y, layer };
+        match c.after {
+            Some(_) => { jobs.work.insert(key, 0.0); }
+            None => { jobs.work.remove(&key); }
+        }
+    }
+    for ev in resized.read() {
+        if ev.growth.is_zero() {
+            jobs.work = (0..map.levels())
+                .flat_map(|level| map.planned_cells(level).into_iter().map(move |(x, y, layer, _)| JobKey { level, x, y, layer }))
+                .map(|key| (key, 0.0))
+                .collect();
+        } else {
+            let shift = ev.growth.shift();
+            jobs.work = jobs.work.drain().map(|(k, w)| (JobKey { x: k.x + shift.x, y: k.y + shift.y, ..k }, w)).collect();
+        }
+    }
+}
+
+/**
+ * Advances every job by the frame time and requests the swap from blueprint to built tile for jobs whose
+ * work reached the tile's `build_time`, if the built tile's placement rules hold now. A requested job
+ * starts over, so one the edit pipeline refuses is retried after another `build_time` rather than every frame.
+ *
+ * @param time - frame time, the work done on each job
+ * @param jobs - work per blueprint
+ * @param map - blueprints and built tiles
+ * @param pipes - pipe layer, for the placement rules
+ * @param tileset - build times and rules
+ * @param construct - blueprint to built tile swaps
+ */
+fn advance_construction(
+    time: Res<Time>,
+    mut jobs: ResMut<ConstructionJobs>,
+    map: Res<MapState>,
+    pipes: Res<PipeMap>,
+    tileset: Res<Tileset>,
+    mut construct: MessageWriter<ConstructTile>,
+) {
+    if jobs.work.is_empty() { return }
+    let dt = time.delta_secs();
+    for (key, work) in jobs.work.iter_mut() {
+        *work += dt;
+        let JobKey { level, x, y, layer } = *key;
+        if level >= map.levels() { continue }
+        let Some(id) = map.get_planned(level, x, y, layer) else { continue };
+        if *work < tileset.props(id).build_time { continue }
+        let built = PlaceTile { level, x, y, content: CellContent::Tile(id) };
+        if check_placement(&map, &pipes, &tileset, &built).is_err() { continue }
+        *work = 0.0;
+        construct.write(ConstructTile { level, x, y, layer });
+    }
+}
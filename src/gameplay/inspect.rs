@@ -0,0 +1,75 @@
+/**
+ * Cell inspection for debugging the simulations. Until there is an info panel, the inspect keybind logs
+ * what the cell under the cursor holds on the current deck: its air, then one line per utility network running
+ * through it, with the fluid in the pipe and its network for the kinds that carry fluid.
+ */
+use bevy::prelude::*;
+use crate::core::atmosphere::{AirCell, Atmosphere};
+use crate::core::fluids::{Fluid, FluidMix, FluidState};
+use crate::core::grid::GridConfig;
+use crate::core::map::MapState;
+use crate::core::networks::PipeNetworks;
+use crate::core::pipes::{PipeCell, PipeMap, UtilityKind};
+use crate::gameplay::placement::cursor_cell;
+use crate::input::GameplayInputState;
+
+pub struct InspectPlugin;
+
+impl Plugin for InspectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, inspect_cell_from_input);
+    }
+}
+
+/**
+ * Logs the cell under the cursor when the inspect keybind was pressed: the pressure and gases of its air,
+ * then for every utility kind with a network node there, the network's id, how many networks of that kind
+ * exist, its size and its number of ends; for pipes also how full the cell is, what it holds, its port and
+ * the fluid in the whole network.
+ *
+ * @param gi - inspect flag (reset here), cursor and current deck
+ * @param map - map geometry, to find the cell under the cursor
+ * @param grid - tile size, to find the cell under the cursor
+ * @param pipes - utility layers, to find network ends
+ * @param networks - network membership
+ * @param fluids - pipe contents and ports
+ * @param atmosphere - gas in the cell
+ */
+fn inspect_cell_from_input(
+    mut gi: ResMut<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    pipes: Res<PipeMap>,
+    networks: Res<PipeNetworks>,
+    fluids: Res<FluidState>,
+    atmosphere: Res<Atmosphere>,
+) {
+    if !gi.inspect_requested { return }
+    gi.inspect_requested = false;
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    info!("cell ({},{}) on deck {level}", tp.x, tp.y);
+    let air = AirCell::new(level, tp.x, tp.y);
+    info!("  air {:.2} atm ({:.1}) [{}]", atmosphere.pressure(air), atmosphere.amount(air), held(atmosphere.mix(air)));
+    for kind in UtilityKind::ALL {
+        let cell = PipeCell::new(kind, level, tp.x, tp.y);
+        let Some(id) = networks.network_of(cell) else { continue };
+        let of_kind = networks.networks().filter(|&other| networks.cells(other).next().is_some_and(|c| c.kind == kind)).count();
+        let cells = networks.cells(id).count();
+        let ends = networks.endpoints(id, &pipes).len();
+        info!("  {} network {} of {of_kind}: {cells} cells, {ends} ends", kind.name(), id.0);
+        if !kind.carries_fluid() { continue }
+        let port = match fluids.ports().find(|&(at, _)| at == cell) {
+            Some((_, port)) if port.rate > 0.0 => format!(", {} source", port.fluid.name()),
+            Some(_) => ", sink".to_string(),
+            None => String::new(),
+        };
+        info!("    {:.0}% full [{}]{port}; network holds {:.1}", fluids.pressure(cell) * 100.0, held(fluids.mix(cell)), fluids.network_amount(&networks, id));
+    }
+}
+
+/** Lists the fluids `mix` holds, e.g. `oxygen 21.0, nitrogen 78.0`. */
+fn held(mix: FluidMix) -> String {
+    let held: Vec<String> = Fluid::ALL.into_iter().filter(|&f| mix.get(f) > 0.0).map(|f| format!("{} {:.1}", f.name(), mix.get(f))).collect();
+    held.join(", ")
+}
@@ -1,17 +1,24 @@
+pub mod construction;
+pub mod inspect;
 pub mod placement;
 pub mod rules;
 pub mod piping;
+pub mod save;
 
 use bevy::prelude::*;
 use crate::render::sync::TileSyncPlugin;
 use bevy_ecs_tilemap::TilemapPlugin;
+use construction::ConstructionPlugin;
+use inspect::InspectPlugin;
 use placement::PlacementPlugin;
 use piping::PipePlugin;
+use rules::RulesPlugin;
+use save::SavePlugin;
 
 pub struct GameplayPlugin;
 
 impl Plugin for GameplayPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((TilemapPlugin, TileSyncPlugin, PlacementPlugin, PipePlugin));
+        app.add_plugins((TilemapPlugin, TileSyncPlugin, PlacementPlugin, ConstructionPlugin, PipePlugin, RulesPlugin, SavePlugin, InspectPlugin));
     }
 }
@@ -1,152 +1,363 @@
 /**
- * Piping gameplay systems: tool selection, drag-to-build/erase, and connectivity-based rendering.
- * Option A: two tilemaps for pipes (normal and engineering), with visibility toggled via input.
+ * Piping gameplay systems for every utility kind: drag-to-build/erase and risers.
+ * Tools only write `TileEdit`s for the kind of the selected tool; core applies them to `PipeMap`,
+ * and `render::sync` draws whatever `PipeMap` holds. A drag is previewed as ghost tiles on the deck's
+ * `pipe_preview` tilemap and only turned into edits when the button is released.
  */
 use bevy::prelude::*;
-use bevy_ecs_tilemap::prelude::*;
-use crate::core::map::MapState;
-use crate::render::tilemaps::TilemapLayers;
-use crate::render::sync::{set_tile_with_index, remove_tile_in_tilemap};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::core::edit::PlacementCheck;
+use crate::core::map::{MapSet, MapState};
+use crate::core::events::{CellContent, CellLayer, EditStroke, MapResized, PlaceTile, RemoveTile, TileEdit};
+use crate::core::pipes::{PipeMap, UtilityKind, MASK_E, MASK_N, MASK_S, MASK_W};
+use crate::core::tile::Tileset;
+use crate::gameplay::placement::cursor_cell;
 use crate::input::{GameplayInputState, Tool as InputTool};
 use crate::core::grid::GridConfig;
+use crate::render::sync::{engineering_color, TileWriter};
+use crate::render::tilemaps::TilemapLayers;
 
-/** Tracks whether the player is dragging a pipe path and the last visited tile. */
+/**
+ * The pipe drag in progress and the drag options. While dragging, `path` runs from the cell the drag
+ * started on to the cell under the cursor; it is only previewed until the button is released.
+ * `vertical_first` and `auto_route` persist between drags.
+ */
 #[derive(Resource, Default)]
-pub struct PipeDragState { pub dragging: bool, pub last: Option<TilePos> }
-
-/** Pipe occupancy and connectivity mask per cell (same dimensions as the map). */
-#[derive(Resource)]
-pub struct PipeMap { pub present: Vec<bool>, pub mask: Vec<u8> }
+pub struct PipeDragState {
+    pub dragging: bool,
+    /** Deck, utility kind and mode (place or erase) the drag started with; changing tool cancels it. */
+    pub level: u32,
+    pub kind: Option<UtilityKind>,
+    pub placing: bool,
+    /** Cell the drag started on, once the cursor was over the map. */
+    pub start: Option<UVec2>,
+    /** Cell under the cursor the path was last computed for. */
+    pub end: Option<UVec2>,
+    /** Cells of the path in order, each with whether the placement rules reject it (placing only). */
+    pub path: Vec<(UVec2, bool)>,
+    /** Turn the L vertical-first instead of horizontal-first (F). */
+    pub vertical_first: bool,
+    /** Route placements around cells the placement rules reject, using A* when no L fits (T). */
+    pub auto_route: bool,
+}
 
-impl PipeMap {
-    pub fn new(size: (u32, u32)) -> Self {
-        let (w, h) = size; let n = (w * h) as usize; Self { present: vec![false; n], mask: vec![0; n] }
+impl PipeDragState {
+    /** Ends the drag without editing anything. */
+    fn cancel(&mut self) {
+        self.dragging = false;
+        self.start = None;
+        self.end = None;
+        self.path.clear();
     }
 }
 
-impl PipeMap {
-    fn idx(&self, map: &MapState, x: u32, y: u32) -> usize { map.idx(x, y) }
-    fn has(&self, map: &MapState, x: u32, y: u32) -> bool { self.present[self.idx(map, x, y)] }
-    fn set(&mut self, map: &MapState, x: u32, y: u32, val: bool) { let i = self.idx(map, x, y); self.present[i] = val; }
-    fn set_mask(&mut self, map: &MapState, x: u32, y: u32, m: u8) { let i = self.idx(map, x, y); self.mask[i] = m; }
-    fn get_mask(&self, map: &MapState, x: u32, y: u32) -> u8 { self.mask[self.idx(map, x, y)] }
-}
+/** Extra cost of a turn in an auto-routed path, so routes prefer long straight runs over staircases. */
+const ROUTE_TURN_COST: u32 = 2;
+
+/** Cells an auto-routed path may stray outside the box spanned by its ends; bounds the search. */
+const ROUTE_MARGIN: u32 = 8;
+
+/** Alpha of the preview tiles. */
+const PREVIEW_ALPHA: f32 = 0.5;
+
+/** Tint of preview cells the rules reject, and of every cell of an erase drag. */
+const PREVIEW_BLOCKED_TINT: Color = Color::srgba(1.0, 0.3, 0.3, 0.6);
 
 pub struct PipePlugin;
 
 impl Plugin for PipePlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<PipeDragState>()
-            .add_systems(Startup, init_pipemap)
-            .add_systems(Update, (drag_from_input, apply_connectivity_and_tiles));
+            .add_systems(Update, ((commit_drag_on_release, update_drag_from_input).chain(), place_riser_on_click))
+            .add_systems(PostUpdate, (
+                shift_drag_on_resize.in_set(MapSet::Rebuild),
+                draw_drag_preview.in_set(MapSet::Sync),
+            ));
     }
 }
 
-/** Initializes the PipeMap resource to match the current map size. */
-fn init_pipemap(mut commands: Commands, map: Res<MapState>) {
-    commands.insert_resource(PipeMap::new((map.size.w, map.size.h)));
+/** Shifts the in-progress drag with the map after it grew, so its path keeps covering the same cells. */
+fn shift_drag_on_resize(mut resized: MessageReader<MapResized>, mut drag: ResMut<PipeDragState>) {
+    for ev in resized.read() {
+        let shift = ev.growth.shift();
+        if shift == UVec2::ZERO { continue }
+        if let Some(start) = drag.start.as_mut() { *start += shift; }
+        if let Some(end) = drag.end.as_mut() { *end += shift; }
+        for (cell, _) in &mut drag.path { *cell += shift; }
+    }
 }
 
 /**
- * Uses collected input state to start/stop drags and write pipe presence along the drag path.
- * Supports straight lines and simple L-turns.
+ * Turns the dragged path into edits when the left button is released: one `PlaceTile` or `RemoveTile` of
+ * the drag's utility kind per cell, wrapped in one edit stroke so the drag undoes as a single step.
  */
-fn drag_from_input(
+fn commit_drag_on_release(
     gi: Res<GameplayInputState>,
     mut drag: ResMut<PipeDragState>,
-    mut pipemap: ResMut<PipeMap>,
+    mut edits: MessageWriter<TileEdit>,
+    mut stroke: MessageWriter<EditStroke>,
+) {
+    if !gi.left_just_released || !drag.dragging { return }
+    if let Some(kind) = drag.kind && !drag.path.is_empty() {
+        let level = drag.level;
+        stroke.write(EditStroke::Begin);
+        if drag.placing {
+            edits.write_batch(drag.path.iter().map(|&(c, _)| PlaceTile { level, x: c.x, y: c.y, content: CellContent::Pipe(kind) }.into()));
+        } else {
+            edits.write_batch(drag.path.iter().map(|&(c, _)| RemoveTile { level, x: c.x, y: c.y, layer: CellLayer::Pipe(kind) }.into()));
+        }
+        stroke.write(EditStroke::End);
+    }
+    drag.cancel();
+}
+
+/**
+ * Starts, cancels and steers the pipe drag from input. Left press starts a drag with the selected pipe
+ * tool; right click, Escape (which deselects the tool) or switching tools cancels it. While dragging, the
+ * path to the cursor is an L (horizontal-first unless flipped); with auto-routing on, placements take the
+ * other L when the preferred one crosses a rejected cell, and an A* route around rejected cells when both do.
+ *
+ * @param gi - pointer state, selected tool and the one-shot flip / auto-route toggles (reset here)
+ * @param drag - drag to update; only touched when something changed, so the preview redraws on change
+ * @param pipes - pipe layers the placement rules check against
+ * @param check - installed placement rules; without them no cell counts as blocked
+ */
+fn update_drag_from_input(
+    mut gi: ResMut<GameplayInputState>,
+    mut drag: ResMut<PipeDragState>,
     map: Res<MapState>,
+    pipes: Res<PipeMap>,
+    tileset: Res<Tileset>,
+    check: Option<Res<PlacementCheck>>,
     grid: Res<GridConfig>,
 ) {
-    let placing_mode = matches!(gi.selected_tool, InputTool::PipePlace | InputTool::PipeErase);
-    if !placing_mode { drag.dragging = false; drag.last = None; return }
-
-    if gi.left_just_pressed { drag.dragging = true; drag.last = None; }
-    if gi.left_just_released { drag.dragging = false; drag.last = None; }
-    if !drag.dragging || !gi.left_pressed { return }
-
-    let Some(world) = gi.world_cursor else { return };
-    let local = Vec2::new(world.x, world.y);
-    let map_size = TilemapSize { x: map.size.w, y: map.size.h };
-    let grid_size = TilemapGridSize { x: grid.tile_size, y: grid.tile_size };
-    let tile_size = TilemapTileSize { x: grid.tile_size, y: grid.tile_size };
-    let Some(tp) = TilePos::from_world_pos(&local, &map_size, &grid_size, &tile_size, &TilemapType::Square, &TilemapAnchor::TopLeft) else { return };
-    let Some(last) = drag.last else { drag.last = Some(tp); return };
-    if last == tp { return }
-
-    let placing = matches!(gi.selected_tool, InputTool::PipePlace);
-    let mut fill_straight = |from: TilePos, to: TilePos, val: bool| {
-        if from.x == to.x {
-            let x = from.x; let (a, b) = if from.y <= to.y { (from.y, to.y) } else { (to.y, from.y) };
-            for y in a..=b { pipemap.set(&map, x, y, val); }
-        } else if from.y == to.y {
-            let y = from.y; let (a, b) = if from.x <= to.x { (from.x, to.x) } else { (to.x, from.x) };
-            for x in a..=b { pipemap.set(&map, x, y, val); }
-        }
+    let mut reroute = false;
+    if gi.flip_route_requested { drag.vertical_first = !drag.vertical_first; gi.flip_route_requested = false; reroute = true; }
+    if gi.toggle_auto_route_requested { drag.auto_route = !drag.auto_route; gi.toggle_auto_route_requested = false; reroute = true; }
+
+    let (placing, kind) = match gi.selected_tool {
+        InputTool::PipePlace(kind) => (true, kind),
+        InputTool::PipeErase(kind) => (false, kind),
+        _ => { if drag.dragging { drag.cancel(); } return }
     };
+    if drag.dragging && (drag.kind != Some(kind) || drag.placing != placing) { drag.cancel(); }
+    if gi.left_just_pressed {
+        drag.cancel();
+        drag.dragging = true;
+        drag.level = gi.current_level.min(map.levels() - 1);
+        drag.kind = Some(kind);
+        drag.placing = placing;
+    }
+    if !drag.dragging { return }
+    if gi.right_just_pressed { drag.cancel(); return }
 
-    if last.x == tp.x || last.y == tp.y {
-        fill_straight(last, tp, placing);
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let tile = UVec2::new(tp.x, tp.y);
+    let start = match drag.start {
+        Some(start) => start,
+        None => { drag.start = Some(tile); tile }
+    };
+    if drag.end == Some(tile) && !reroute { return }
+
+    let level = drag.level;
+    let blocked = |c: UVec2| {
+        let edit = PlaceTile { level, x: c.x, y: c.y, content: CellContent::Pipe(kind) };
+        placing && check.as_ref().is_some_and(|check| (check.0)(&map, &pipes, &tileset, &edit).is_err())
+    };
+    let preferred = l_path(start, tile, drag.vertical_first);
+    let path = if !placing || !drag.auto_route || !preferred.iter().any(|&c| blocked(c)) {
+        preferred
     } else {
-        let mid = TilePos { x: tp.x, y: last.y };
-        fill_straight(last, mid, placing);
-        fill_straight(mid, tp, placing);
+        let other = l_path(start, tile, !drag.vertical_first);
+        if !other.iter().any(|&c| blocked(c)) { other }
+        else { route(start, tile, map.size.w, map.size.h, &blocked).unwrap_or(preferred) }
+    };
+    drag.path = path.into_iter().map(|c| (c, blocked(c))).collect();
+    drag.end = Some(tile);
+}
+
+/** Cells from `from` to `to` along an L: horizontal leg first, or vertical first when `vertical_first`. */
+fn l_path(from: UVec2, to: UVec2, vertical_first: bool) -> Vec<UVec2> {
+    let corner = if vertical_first { UVec2::new(from.x, to.y) } else { UVec2::new(to.x, from.y) };
+    let mut path = vec![from];
+    for target in [corner, to] {
+        let mut c = *path.last().unwrap();
+        while c != target {
+            if c.x != target.x { c.x = if c.x < target.x { c.x + 1 } else { c.x - 1 }; }
+            else { c.y = if c.y < target.y { c.y + 1 } else { c.y - 1 }; }
+            path.push(c);
+        }
     }
-    drag.last = Some(tp);
+    path
 }
 
 /**
- * Recomputes NESW connectivity for all cells and updates both pipe tilemaps.
- * For now, the connectivity mask is used directly as the tile texture index.
+ * Shortest path from `from` to `to` over the four-connected grid avoiding `blocked` cells (the ends are
+ * always allowed), where each turn costs `ROUTE_TURN_COST` extra steps. A* with the Manhattan distance,
+ * searching only within `ROUTE_MARGIN` cells of the box spanned by the ends. Ties are broken by cell
+ * order, so the same map and ends always give the same route. `None` when no route exists in that area.
+ *
+ * @param w - map width in cells
+ * @param h - map height in cells
  */
-fn apply_connectivity_and_tiles(
-    mut commands: Commands,
-    layers: Res<TilemapLayers>,
-    mut q_sets: ParamSet<(Query<&mut TileStorage>, Query<&mut TileStorage>)>,
-    pipemap: ResMut<PipeMap>,
-    map: Res<MapState>,
-) {
-    if !pipemap.is_changed() { return }
-    let w = map.size.w; let h = map.size.h;
-    let inside = |x: i32, y: i32| x >= 0 && y >= 0 && (x as u32) < w && (y as u32) < h;
-    let has = |x: i32, y: i32| inside(x, y) && pipemap.present[map.idx(x as u32, y as u32)];
-
-    let mut ops: Vec<(u32, u32, Option<u32>)> = Vec::with_capacity((w * h) as usize);
-    for y in 0..h { for x in 0..w {
-        let present = pipemap.present[map.idx(x, y)];
-        if !present {
-            ops.push((x, y, None));
-        } else {
-            let n = if has(x as i32, y as i32 - 1) { 1 } else { 0 };
-            let e = if has(x as i32 + 1, y as i32) { 2 } else { 0 };
-            let s = if has(x as i32, y as i32 + 1) { 4 } else { 0 };
-            let wv = if has(x as i32 - 1, y as i32) { 8 } else { 0 };
-            let mask = (n | e | s | wv) as u32;
-            ops.push((x, y, Some(mask)));
+fn route(from: UVec2, to: UVec2, w: u32, h: u32, blocked: &dyn Fn(UVec2) -> bool) -> Option<Vec<UVec2>> {
+    let min = from.min(to).saturating_sub(UVec2::splat(ROUTE_MARGIN));
+    let max = (from.max(to) + UVec2::splat(ROUTE_MARGIN)).min(UVec2::new(w - 1, h - 1));
+    let bw = max.x - min.x + 1;
+    // Search nodes are a cell plus the direction it was entered in (N, E, S, W; 4 for the start), so turns can cost extra.
+    let node = |c: UVec2, dir: usize| ((c.y - min.y) * bw + (c.x - min.x)) as usize * 5 + dir;
+    let cell_of = |n: usize| { let i = (n / 5) as u32; UVec2::new(min.x + i % bw, min.y + i / bw) };
+    let heuristic = |c: UVec2| c.x.abs_diff(to.x) + c.y.abs_diff(to.y);
+    let nodes = (bw * (max.y - min.y + 1)) as usize * 5;
+    let (mut cost, mut parent) = (vec![u32::MAX; nodes], vec![usize::MAX; nodes]);
+    cost[node(from, 4)] = 0;
+    let mut open = BinaryHeap::from([Reverse((heuristic(from), node(from, 4)))]);
+    while let Some(Reverse((estimate, n))) = open.pop() {
+        let (c, dir) = (cell_of(n), n % 5);
+        if estimate > cost[n] + heuristic(c) { continue }
+        if c == to {
+            let mut path = vec![c];
+            let mut n = n;
+            while parent[n] != usize::MAX { n = parent[n]; path.push(cell_of(n)); }
+            path.reverse();
+            return Some(path);
         }
-    }}
-
-    {
-        let mut q = q_sets.p0();
-        let mut storage = q.get_mut(layers.pipes).unwrap();
-        for (x, y, mask_opt) in ops.iter().copied() {
-            match mask_opt {
-                Some(mask) => set_tile_with_index(&mut commands, &mut storage, layers.pipes, mask, Color::WHITE, x, y),
-                None => remove_tile_in_tilemap(&mut commands, &mut storage, x, y),
-            }
+        let steps = [
+            c.y.checked_sub(1).map(|y| UVec2::new(c.x, y)),
+            Some(UVec2::new(c.x + 1, c.y)),
+            Some(UVec2::new(c.x, c.y + 1)),
+            c.x.checked_sub(1).map(|x| UVec2::new(x, c.y)),
+        ];
+        for (d, next) in steps.into_iter().enumerate() {
+            let Some(next) = next.filter(|next| next.cmpge(min).all() && next.cmple(max).all()) else { continue };
+            if next != to && blocked(next) { continue }
+            let g = cost[n] + 1 + if dir != 4 && dir != d { ROUTE_TURN_COST } else { 0 };
+            let m = node(next, d);
+            if g < cost[m] { cost[m] = g; parent[m] = n; open.push(Reverse((g + heuristic(next), m))); }
         }
     }
+    None
+}
 
-    {
-        let mut q = q_sets.p1();
-        let mut storage_eng = q.get_mut(layers.pipes_eng).unwrap();
-        for (x, y, mask_opt) in ops.into_iter() {
-            match mask_opt {
-                Some(mask) => set_tile_with_index(&mut commands, &mut storage_eng, layers.pipes_eng, mask, Color::srgb(0.6, 0.9, 1.0), x, y),
-                None => remove_tile_in_tilemap(&mut commands, &mut storage_eng, x, y),
-            }
-        }
-    }
+/**
+ * Draws the dragged path as ghost pipe tiles on its deck's preview tilemap, replacing the previous preview.
+ * Each cell joins its neighbours along the path and, when placing, the existing segments of the kind
+ * around it; cells the rules reject (and every cell of an erase drag) are tinted red.
+ *
+ * @param drag - drag to preview; redrawn only when it changed
+ * @param layers - preview tilemap of each deck
+ * @param pipes - existing segments the preview connects to
+ * @param shown - preview tilemap and cells drawn last time, cleared before drawing
+ */
+fn draw_drag_preview(
+    drag: Res<PipeDragState>,
+    layers: Option<Res<TilemapLayers>>,
+    pipes: Res<PipeMap>,
+    mut tiles: TileWriter,
+    mut shown: Local<Option<(Entity, Vec<UVec2>)>>,
+) {
+    let Some(layers) = layers else { return };
+    if !drag.is_changed() && !layers.is_changed() { return }
+    if let Some((tilemap, cells)) = shown.take() { tiles.apply(tilemap, cells.into_iter().map(|c| (c.x, c.y, None))); }
+    let Some(kind) = drag.kind.filter(|_| !drag.path.is_empty()) else { return };
+    let Some(tilemap) = layers.levels.get(drag.level as usize).map(|l| l.pipe_preview) else { return };
+
+    let (w, h) = pipes.size();
+    let tint = engineering_color(kind);
+    let tint = tint.with_alpha(tint.alpha() * PREVIEW_ALPHA);
+    let cells: Vec<UVec2> = drag.path.iter().map(|&(c, _)| c).collect();
+    let existing = |n: UVec2| drag.placing && n.x < w && n.y < h && pipes.has(kind, drag.level, n.x, n.y);
+    let joins = |next: Option<UVec2>| next.is_some_and(|n| cells.contains(&n) || existing(n));
+    let updates = drag.path.iter().map(|&(c, blocked)| {
+        let mut mask = 0;
+        let sides = [
+            (MASK_N, c.y.checked_sub(1).map(|y| UVec2::new(c.x, y))),
+            (MASK_E, Some(UVec2::new(c.x + 1, c.y))),
+            (MASK_S, Some(UVec2::new(c.x, c.y + 1))),
+            (MASK_W, c.x.checked_sub(1).map(|x| UVec2::new(x, c.y))),
+        ];
+        for (bit, next) in sides { if joins(next) { mask |= bit; } }
+        let color = if blocked || !drag.placing { PREVIEW_BLOCKED_TINT } else { tint };
+        (c.x, c.y, Some((mask as u32, color)))
+    });
+    tiles.apply(tilemap, updates);
+    *shown = Some((tilemap, cells));
+}
+
+/**
+ * Places a riser on left-click with the riser tool: a vertical segment of the tool's utility kind joining
+ * the active deck to the deck above, creating a segment on both ends so the networks connect.
+ */
+fn place_riser_on_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    let InputTool::PipeRiser(kind) = gi.selected_tool else { return };
+    if !gi.left_just_pressed { return }
+    let level = gi.current_level.min(map.levels() - 1);
+    if level + 1 >= map.levels() { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let (x, y) = (tp.x, tp.y);
+    edits.write_batch([
+        PlaceTile { level, x, y, content: CellContent::Pipe(kind) }.into(),
+        PlaceTile { level: level + 1, x, y, content: CellContent::Pipe(kind) }.into(),
+        PlaceTile { level, x, y, content: CellContent::Riser(kind) }.into(),
+    ]);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(points: &[(u32, u32)]) -> Vec<UVec2> { points.iter().map(|&(x, y)| UVec2::new(x, y)).collect() }
+
+    fn turns(path: &[UVec2]) -> usize {
+        path.windows(3).filter(|w| (w[1].x == w[0].x) != (w[2].x == w[1].x)).count()
+    }
+
+    fn is_connected(path: &[UVec2]) -> bool {
+        path.windows(2).all(|w| w[0].x.abs_diff(w[1].x) + w[0].y.abs_diff(w[1].y) == 1)
+    }
 
+    #[test]
+    fn l_path_takes_the_requested_leg_first() {
+        let (from, to) = (UVec2::new(0, 0), UVec2::new(2, 1));
+        assert_eq!(l_path(from, to, false), cells(&[(0, 0), (1, 0), (2, 0), (2, 1)]));
+        assert_eq!(l_path(from, to, true), cells(&[(0, 0), (0, 1), (1, 1), (2, 1)]));
+        assert_eq!(l_path(to, from, false), cells(&[(2, 1), (1, 1), (0, 1), (0, 0)]));
+        assert_eq!(l_path(from, from, true), cells(&[(0, 0)]));
+    }
+
+    #[test]
+    fn route_on_an_open_grid_is_a_shortest_l() {
+        let (from, to) = (UVec2::new(1, 1), UVec2::new(6, 4));
+        let path = route(from, to, 10, 10, &|_| false).unwrap();
+        assert_eq!((path[0], *path.last().unwrap()), (from, to));
+        assert_eq!(path.len(), 9);
+        assert!(is_connected(&path));
+        assert_eq!(turns(&path), 1);
+    }
+
+    #[test]
+    fn route_goes_around_blocked_cells() {
+        // A wall at x = 3 with a gap at y = 5.
+        let wall = |c: UVec2| c.x == 3 && c.y != 5;
+        let (from, to) = (UVec2::new(0, 0), UVec2::new(6, 0));
+        let path = route(from, to, 10, 10, &wall).unwrap();
+        assert!(is_connected(&path));
+        assert!(path.iter().all(|&c| !wall(c)));
+        assert!(path.contains(&UVec2::new(3, 5)));
+        assert_eq!(path, route(from, to, 10, 10, &wall).unwrap(), "routes are deterministic");
+    }
+
+    #[test]
+    fn route_may_end_on_a_blocked_cell_but_not_pass_one() {
+        let to = UVec2::new(4, 0);
+        let path = route(UVec2::ZERO, to, 5, 1, &|c| c == to).unwrap();
+        assert_eq!(path.len(), 5);
+        assert_eq!(route(UVec2::ZERO, to, 5, 1, &|c| c.x == 2), None);
+    }
+}
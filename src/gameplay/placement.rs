@@ -1,75 +1,325 @@
 use bevy::prelude::*;
-use crate::core::map::MapState;
-use crate::core::tile::{TileId, Tileset};
+use crate::core::map::{MapGrowth, MapState, EXPAND_STEP};
+use crate::core::events::{CellContent, CellLayer, GrowMap, HistoryCommand, PlaceStructure, PlaceTile, RemoveTile, TileEdit};
+use crate::core::fluids::{Fluid, FluidPort};
+use crate::core::pipes::{PipeComponent, PipeMap};
+use crate::core::structure::{Rotation, StructureId};
+use crate::core::tile::Tileset;
 use bevy_ecs_tilemap::prelude::*;
-use crate::render::tilemaps::TilemapLayers;
-use crate::render::sync::set_tile_in_tilemap;
 use crate::input::{GameplayInputState, Tool as InputTool};
 use crate::core::grid::GridConfig;
 
+/** Catalog ids placed by the left/right click tools. */
+const BASE_BRUSH: &str = "Dirt";
+const OVERLAY_BRUSH: &str = "Marker";
+const LADDER: &str = "Ladder";
+
+/** Structure placed by the structure tool, and its rotation. Cycled and rotated from the keybinds. */
+#[derive(Resource, Default)]
+pub struct StructureBrush { pub index: usize, pub rotation: Rotation }
+
+/** Component fitted by the component tool, as an index into `COMPONENT_BRUSHES`, and its rotation. */
+#[derive(Resource, Default)]
+pub struct ComponentBrush { pub index: usize, pub rotation: Rotation }
+
+/** Components the component tool cycles through; valves start open and filters come one per fluid. */
+const COMPONENT_BRUSHES: usize = 6 + Fluid::ALL.len();
+
+impl ComponentBrush {
+    pub fn component(&self) -> PipeComponent {
+        let rotation = self.rotation;
+        match self.index % COMPONENT_BRUSHES {
+            0 => PipeComponent::Valve { rotation, open: true },
+            1 => PipeComponent::Pump { rotation },
+            2 => PipeComponent::Junction,
+            3 => PipeComponent::Crossing,
+            4 => PipeComponent::Vent,
+            5 => PipeComponent::Scrubber,
+            i => PipeComponent::Filter { rotation, passes: Fluid::ALL[i - 6] },
+        }
+    }
+}
+
+/** Port attached by the port tool, as an index into the port brushes: a source of each fluid, then a sink. */
+#[derive(Resource, Default)]
+pub struct PortBrush { pub index: usize }
+
+/** Ports the port tool cycles through: one source per fluid, then a sink. */
+const PORT_BRUSHES: usize = Fluid::ALL.len() + 1;
+
+/** Amount per tick a port placed by the port tool adds or removes. */
+const PORT_RATE: f32 = 1.0;
+
+impl PortBrush {
+    /** The brush port; a sink drains every fluid, so its `fluid` is only a placeholder. */
+    pub fn port(&self) -> FluidPort {
+        match Fluid::ALL.get(self.index % PORT_BRUSHES) {
+            Some(&fluid) => FluidPort { rate: PORT_RATE, fluid },
+            None => FluidPort { rate: -PORT_RATE, fluid: Fluid::ALL[0] },
+        }
+    }
+}
+
 pub struct PlacementPlugin;
 
 impl Plugin for PlacementPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (place_base_on_left_click, place_overlay_on_right_click));
+        app.init_resource::<StructureBrush>()
+            .init_resource::<ComponentBrush>()
+            .init_resource::<PortBrush>()
+            .add_systems(Update, (
+            clamp_current_level,
+            place_base_on_left_click,
+            place_overlay_on_right_click,
+            place_ladder_on_left_click,
+            cancel_blueprint_on_left_click,
+            (update_component_brush, update_structure_brush, place_structure_on_left_click, remove_structure_on_right_click).chain(),
+            (place_component_on_left_click, remove_component_on_right_click),
+            (update_port_brush, attach_port_on_left_click, detach_port_on_right_click).chain(),
+            grow_map_from_input,
+            history_from_input,
+        ));
     }
 }
 
 
+/** Storage cell under the cursor, if the cursor is over the map. */
+pub(crate) fn cursor_cell(gi: &GameplayInputState, map: &MapState, grid: &GridConfig) -> Option<TilePos> {
+    let local = gi.world_cursor? - map.world_anchor(grid.tile_size);
+    let map_size = TilemapSize { x: map.size.w, y: map.size.h };
+    let grid_size = TilemapGridSize { x: grid.tile_size, y: grid.tile_size };
+    let tile_size = TilemapTileSize { x: grid.tile_size, y: grid.tile_size };
+    TilePos::from_world_pos(&local, &map_size, &grid_size, &tile_size, &TilemapType::Square, &TilemapAnchor::TopLeft)
+}
+
+/** Keeps the selected deck within the map's deck count. */
+fn clamp_current_level(mut gi: ResMut<GameplayInputState>, map: Res<MapState>) {
+    let max = map.levels() - 1;
+    if gi.current_level > max { gi.current_level = max; }
+}
+
 /**
- * Places a base tile on left-click unless a pipe tool is active (pipe tools own left-drag).
- * Consumes high-level gameplay input instead of raw inputs.
+ * Requests a base tile blueprint on the active deck on left-click unless a tool is active (tools own
+ * left-click). Consumes high-level gameplay input instead of raw inputs; core applies the edit and
+ * construction later builds it.
  */
 fn place_base_on_left_click(
     gi: Res<GameplayInputState>,
-    mut map: ResMut<MapState>,
+    map: Res<MapState>,
     tileset: Res<Tileset>,
-    mut commands: Commands,
-    mut q_base: Query<&mut TileStorage>,
-    layers: Res<TilemapLayers>,
     grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
 ) {
-    if matches!(gi.selected_tool, InputTool::PipePlace | InputTool::PipeErase) { return }
+    if gi.selected_tool != InputTool::None { return }
     if !gi.left_just_pressed { return }
-    if let Some(world) = gi.world_cursor {
-        let local = Vec2::new(world.x, world.y);
-        let map_size = TilemapSize { x: map.size.w, y: map.size.h };
-        let grid_size = TilemapGridSize { x: grid.tile_size, y: grid.tile_size };
-        let tile_size = TilemapTileSize { x: grid.tile_size, y: grid.tile_size };
-        let tp = TilePos::from_world_pos(&local, &map_size, &grid_size, &tile_size, &TilemapType::Square, &TilemapAnchor::TopLeft);
-        let Some(tp) = tp else { return };
-        map.set_base(tp.x, tp.y, TileId::Dirt);
-        let mut storage = q_base.get_mut(layers.base).unwrap();
-        let color = tileset.def(TileId::Dirt).color;
-        set_tile_in_tilemap(&mut commands, &mut storage, layers.base, color, tp.x, tp.y);
-    }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let Some(id) = tileset.id(BASE_BRUSH) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(PlaceTile { level, x: tp.x, y: tp.y, content: CellContent::Planned(id) }.into());
 }
 
 /**
- * Places an overlay marker on right-click unless a pipe tool is active.
- * Consumes high-level gameplay input instead of raw inputs.
+ * Requests an overlay marker blueprint on the active deck on right-click unless a tool is active.
+ * Consumes high-level gameplay input instead of raw inputs; core applies the edit.
  */
 fn place_overlay_on_right_click(
     gi: Res<GameplayInputState>,
-    mut map: ResMut<MapState>,
+    map: Res<MapState>,
     tileset: Res<Tileset>,
-    mut commands: Commands,
-    mut q_overlay: Query<&mut TileStorage>,
-    layers: Res<TilemapLayers>,
     grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    if gi.selected_tool != InputTool::None { return }
+    if !gi.right_just_pressed { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let Some(id) = tileset.id(OVERLAY_BRUSH) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(PlaceTile { level, x: tp.x, y: tp.y, content: CellContent::Planned(id) }.into());
+}
+
+/**
+ * Plans a ladder with the ladder tool: a vertical connector between the active deck and the one above,
+ * drawn as a "Ladder" overlay blueprint on both decks.
+ */
+fn place_ladder_on_left_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    tileset: Res<Tileset>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    if gi.selected_tool != InputTool::Ladder || !gi.left_just_pressed { return }
+    let level = gi.current_level.min(map.levels() - 1);
+    if level + 1 >= map.levels() { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let Some(id) = tileset.id(LADDER) else { return };
+    let (x, y, content) = (tp.x, tp.y, CellContent::Planned(id));
+    edits.write_batch([PlaceTile { level, x, y, content }.into(), PlaceTile { level: level + 1, x, y, content }.into()]);
+}
+
+/** Cancels the blueprints (both layers) in the clicked cell of the active deck with the cancel tool. */
+fn cancel_blueprint_on_left_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    if gi.selected_tool != InputTool::Cancel || !gi.left_just_pressed { return }
+    let level = gi.current_level.min(map.levels() - 1);
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let (x, y) = (tp.x, tp.y);
+    edits.write_batch([
+        RemoveTile { level, x, y, layer: CellLayer::PlannedOverlay }.into(),
+        RemoveTile { level, x, y, layer: CellLayer::PlannedBase }.into(),
+    ]);
+}
+
+/** Applies the cycle and rotate keybinds to the component brush while the component tool is active. */
+fn update_component_brush(mut gi: ResMut<GameplayInputState>, mut brush: ResMut<ComponentBrush>) {
+    if !matches!(gi.selected_tool, InputTool::PipeComponent(_)) { return }
+    if gi.next_component_requested { brush.index = (brush.index + 1) % COMPONENT_BRUSHES; }
+    if gi.rotate_requested { brush.rotation = brush.rotation.next(); }
+    gi.next_component_requested = false;
+    gi.rotate_requested = false;
+}
+
+/**
+ * Fits the brush component into the clicked segment of the tool's utility kind with the component tool.
+ * Clicking a valve opens or closes it instead.
+ */
+fn place_component_on_left_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    pipes: Res<PipeMap>,
+    grid: Res<GridConfig>,
+    brush: Res<ComponentBrush>,
+    mut edits: MessageWriter<TileEdit>,
 ) {
-    if matches!(gi.selected_tool, InputTool::PipePlace | InputTool::PipeErase) { return }
+    let InputTool::PipeComponent(kind) = gi.selected_tool else { return };
+    if !gi.left_just_pressed { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    let component = match pipes.component(kind, level, tp.x, tp.y) {
+        Some(PipeComponent::Valve { rotation, open }) => PipeComponent::Valve { rotation, open: !open },
+        _ => brush.component(),
+    };
+    edits.write(PlaceTile { level, x: tp.x, y: tp.y, content: CellContent::Component(kind, component) }.into());
+}
+
+/** Removes the component from the right-clicked segment of the tool's utility kind with the component tool. */
+fn remove_component_on_right_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    let InputTool::PipeComponent(kind) = gi.selected_tool else { return };
     if !gi.right_just_pressed { return }
-    if let Some(world) = gi.world_cursor {
-        let local = Vec2::new(world.x, world.y);
-        let map_size = TilemapSize { x: map.size.w, y: map.size.h };
-        let grid_size = TilemapGridSize { x: grid.tile_size, y: grid.tile_size };
-        let tile_size = TilemapTileSize { x: grid.tile_size, y: grid.tile_size };
-        let tp = TilePos::from_world_pos(&local, &map_size, &grid_size, &tile_size, &TilemapType::Square, &TilemapAnchor::TopLeft);
-        let Some(tp) = tp else { return };
-        map.set_overlay(tp.x, tp.y, Some(TileId::Marker));
-        let mut storage = q_overlay.get_mut(layers.overlay).unwrap();
-        let color = tileset.def(TileId::Marker).color;
-        set_tile_in_tilemap(&mut commands, &mut storage, layers.overlay, color, tp.x, tp.y);
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(RemoveTile { level, x: tp.x, y: tp.y, layer: CellLayer::Component(kind) }.into());
+}
+
+/** Applies the cycle keybind to the port brush while the port tool is active. */
+fn update_port_brush(mut gi: ResMut<GameplayInputState>, mut brush: ResMut<PortBrush>) {
+    if !matches!(gi.selected_tool, InputTool::Port(_)) { return }
+    if gi.next_port_requested {
+        brush.index = (brush.index + 1) % PORT_BRUSHES;
+        let port = brush.port();
+        if port.rate > 0.0 { info!("port brush: {} source", port.fluid.name()); } else { info!("port brush: sink"); }
     }
+    gi.next_port_requested = false;
+}
+
+/**
+ * Attaches the brush port to the clicked pipe of the tool's utility kind with the port tool. Core checks
+ * there is a gas or liquid pipe to attach it to; like any edit it can be undone.
+ */
+fn attach_port_on_left_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    brush: Res<PortBrush>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    let InputTool::Port(kind) = gi.selected_tool else { return };
+    if !gi.left_just_pressed { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(PlaceTile { level, x: tp.x, y: tp.y, content: CellContent::Port(kind, brush.port()) }.into());
+}
+
+/** Detaches the port from the right-clicked cell of the tool's utility kind with the port tool. */
+fn detach_port_on_right_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    let InputTool::Port(kind) = gi.selected_tool else { return };
+    if !gi.right_just_pressed { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(RemoveTile { level, x: tp.x, y: tp.y, layer: CellLayer::Port(kind) }.into());
+}
+
+/** Applies the cycle and rotate keybinds to the structure brush, wrapping around the catalog's structures. */
+fn update_structure_brush(mut gi: ResMut<GameplayInputState>, tileset: Res<Tileset>, mut brush: ResMut<StructureBrush>) {
+    let count = tileset.structures.len().max(1);
+    if gi.next_structure_requested { brush.index = (brush.index + 1) % count; }
+    if gi.rotate_requested { brush.rotation = brush.rotation.next(); }
+    if brush.index >= count { brush.index = 0; }
+    gi.next_structure_requested = false;
+    gi.rotate_requested = false;
+}
+
+/**
+ * Places the brush structure with the structure tool, with its rotated footprint centered on the clicked
+ * cell (rounded toward the bottom-left). Core places all of its cells or rejects it.
+ */
+fn place_structure_on_left_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    tileset: Res<Tileset>,
+    grid: Res<GridConfig>,
+    brush: Res<StructureBrush>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    if gi.selected_tool != InputTool::Structure || !gi.left_just_pressed { return }
+    let Some(def) = tileset.structures.get(brush.index) else { return };
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let half = (def.rotated_size(brush.rotation) - UVec2::ONE) / 2;
+    let anchor = UVec2::new(tp.x, tp.y).saturating_sub(half);
+    let level = gi.current_level.min(map.levels() - 1);
+    let structure = StructureId(brush.index as u16);
+    edits.write(PlaceStructure { level, x: anchor.x, y: anchor.y, structure, rotation: brush.rotation }.into());
+}
+
+/** Deconstructs the structure covering the right-clicked cell with the structure tool. */
+fn remove_structure_on_right_click(
+    gi: Res<GameplayInputState>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut edits: MessageWriter<TileEdit>,
+) {
+    if gi.selected_tool != InputTool::Structure || !gi.right_just_pressed { return }
+    let Some(tp) = cursor_cell(&gi, &map, &grid) else { return };
+    let level = gi.current_level.min(map.levels() - 1);
+    edits.write(RemoveTile { level, x: tp.x, y: tp.y, layer: CellLayer::Structure }.into());
+}
+
+/** Turns the explicit resize keybind into a one-step growth on every side of the map. */
+fn grow_map_from_input(mut gi: ResMut<GameplayInputState>, mut grow: MessageWriter<GrowMap>) {
+    if !gi.grow_map_requested { return }
+    gi.grow_map_requested = false;
+    let step = EXPAND_STEP;
+    grow.write(GrowMap { growth: MapGrowth { left: step, right: step, top: step, bottom: step } });
+}
+
+/** Turns the undo/redo keybinds into history commands for core. */
+fn history_from_input(mut gi: ResMut<GameplayInputState>, mut history: MessageWriter<HistoryCommand>) {
+    if gi.undo_requested { history.write(HistoryCommand::Undo); }
+    if gi.redo_requested { history.write(HistoryCommand::Redo); }
+    gi.undo_requested = false;
+    gi.redo_requested = false;
 }
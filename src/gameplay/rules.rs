@@ -1 +1,239 @@
-// Placeholder for future placement rules
+/**
+ * Placement rules engine. Catalog tiles declare their `PlacementRule`s; utility segments, which have no
+ * catalog entry, use `PIPE_RULES` and may only share a cell with kinds `utilities_can_share` allows. `check_placement` evaluates the rules of a `PlaceTile` against the current
+ * map and names the first one broken. `RulesPlugin` installs it into core's edit pipeline, so every
+ * producer of edits is held to the same rules; undo/redo restores are not re-checked.
+ */
+use bevy::prelude::*;
+use std::fmt;
+use crate::core::edit::{cell_content, content_layer, PlacementCheck};
+use crate::core::events::{CellContent, EditRejected, PlaceTile};
+use crate::core::map::MapState;
+use crate::core::pipes::{PipeMap, UtilityKind};
+use crate::core::tile::{PlacementRule, TileId, TileLayer, Tileset};
+
+/** Tag the base tile of a cell needs to count as floor for `RequiresFloor`. */
+pub const FLOOR_TAG: &str = "floor";
+
+/** Rules for pipe segments. Risers are only checked structurally by core (pipe on both ends). */
+const PIPE_RULES: &[PlacementRule] = &[PlacementRule::RequiresFloor];
+
+/**
+ * Whether utility segments of kinds `a` and `b` may share a cell: at most one pipe (gas or liquid) per
+ * cell, and power wires stay out of cells with liquid pipes. Wires and cables share freely otherwise.
+ */
+pub fn utilities_can_share(a: UtilityKind, b: UtilityKind) -> bool {
+    match (a, b) {
+        _ if a == b => true,
+        (UtilityKind::Gas | UtilityKind::Liquid, UtilityKind::Gas | UtilityKind::Liquid) => false,
+        (UtilityKind::Liquid, UtilityKind::Power) | (UtilityKind::Power, UtilityKind::Liquid) => false,
+        _ => true,
+    }
+}
+
+/** Why a placement was refused. The `Display` text is meant for the player. */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleViolation {
+    NoFloor,
+    NoBase,
+    Occupied { by: String },
+}
+
+impl fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleViolation::NoFloor => write!(f, "needs a floor underneath"),
+            RuleViolation::NoBase => write!(f, "can't be placed on empty space"),
+            RuleViolation::Occupied { by } => write!(f, "cell is occupied by {by}"),
+        }
+    }
+}
+
+pub struct RulesPlugin;
+
+impl Plugin for RulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PlacementCheck(|map, pipes, tileset, edit| {
+            check_placement(map, pipes, tileset, edit).map_err(|violation| violation.to_string())
+        }))
+        .add_systems(PostUpdate, report_rejected_edits);
+    }
+}
+
+/**
+ * Evaluates the placement rules of `edit.content` at its cell; core asks once per cell of a structure. Assumes core already checked bounds and
+ * the tile id. Blueprints are held to the rules of the tile they plan; a planned floor counts as the
+ * cell's base, so a blueprint may rest on another blueprint, and `NoOverlap` also sees blueprints.
+ *
+ * @param map - map state before the edit
+ * @param pipes - pipe layer before the edit
+ * @param tileset - tile rules and tags
+ * @param edit - requested placement
+ */
+pub fn check_placement(map: &MapState, pipes: &PipeMap, tileset: &Tileset, edit: &PlaceTile) -> Result<(), RuleViolation> {
+    let PlaceTile { level, x, y, content } = *edit;
+    let rules = match content {
+        CellContent::Tile(id) | CellContent::Planned(id) => tileset.def(id).rules.as_slice(),
+        CellContent::Structure(part) => tileset.structure(part.structure).rules.as_slice(),
+        CellContent::Pipe(_) => PIPE_RULES,
+        CellContent::Riser(_) | CellContent::Component(..) | CellContent::Port(..) => &[],
+    };
+    if let CellContent::Pipe(kind) = content
+        && let Some(other) = pipes.kinds_at(level, x, y).find(|&other| !utilities_can_share(kind, other)) {
+        return Err(RuleViolation::Occupied { by: format!("a {}", other.segment_name()) });
+    }
+    let base = match content {
+        CellContent::Planned(_) => map.get_planned(level, x, y, TileLayer::Base).unwrap_or(map.get_base(level, x, y)),
+        _ => map.get_base(level, x, y),
+    };
+    for rule in rules {
+        match rule {
+            PlacementRule::RequiresFloor if !tileset.has_tag(base, FLOOR_TAG) => return Err(RuleViolation::NoFloor),
+            PlacementRule::RequiresBase if base == TileId::EMPTY => return Err(RuleViolation::NoBase),
+            PlacementRule::NoOverlap => {
+                if let Some(existing) = occupant(map, pipes, tileset, edit) {
+                    let by = match existing {
+                        CellContent::Tile(id) => tileset.def(id).name.clone(),
+                        CellContent::Planned(id) => format!("a planned {}", tileset.def(id).name),
+                        CellContent::Structure(part) => format!("a {}", tileset.structure(part.structure).name),
+                        CellContent::Pipe(kind) => format!("a {}", kind.segment_name()),
+                        CellContent::Riser(kind) => format!("a {} riser", kind.name()),
+                        CellContent::Component(kind, component) => format!("a {} {}", kind.name(), component.name()),
+                        CellContent::Port(kind, _) => format!("a {} port", kind.name()),
+                    };
+                    return Err(RuleViolation::Occupied { by });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/** What else already holds `edit`'s cell layer, built or planned. Re-placing the same content is not an overlap. */
+fn occupant(map: &MapState, pipes: &PipeMap, tileset: &Tileset, edit: &PlaceTile) -> Option<CellContent> {
+    let PlaceTile { level, x, y, content } = *edit;
+    let (built, planned) = match content {
+        CellContent::Tile(id) | CellContent::Planned(id) => (CellContent::Tile(id), CellContent::Planned(id)),
+        _ => (content, content),
+    };
+    let layers = [content_layer(tileset, built), content_layer(tileset, planned)];
+    layers.into_iter()
+        .filter_map(|layer| cell_content(map, pipes, level, x, y, layer))
+        .find(|&existing| existing != built && existing != planned)
+}
+
+/**
+ * Tells the player why edits were refused. Until there is on-screen feedback this logs each distinct reason
+ * of the frame once, so a drag across many illegal cells produces one line per kind of problem.
+ */
+fn report_rejected_edits(mut rejected: MessageReader<EditRejected>) {
+    for line in rejection_summary(rejected.read()) { info!("{line}"); }
+}
+
+/** One line per distinct reason, in the order first seen, naming its first cell and how many more it refused. */
+fn rejection_summary<'a>(rejected: impl IntoIterator<Item = &'a EditRejected>) -> Vec<String> {
+    let mut groups: Vec<(&EditRejected, usize)> = Vec::new();
+    for edit in rejected {
+        match groups.iter_mut().find(|(first, _)| first.reason == edit.reason) {
+            Some((_, more)) => *more += 1,
+            None => groups.push((edit, 0)),
+        }
+    }
+    groups.into_iter().map(|(first, more)| {
+        let suffix = if more > 0 { format!(" (+{more} more)") } else { String::new() };
+        format!("can't build at ({},{}) on deck {}: {}{suffix}", first.x, first.y, first.level, first.reason)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::catalog::test_tileset;
+    use crate::core::test_support::Fixture;
+
+    const CATALOG: &str = r#"{ "tiles": [
+        { "id": "Empty", "layer": "Base", "color": [0, 0, 0, 0] },
+        { "id": "Dirt", "layer": "Base", "color": [1, 1, 1, 1], "properties": { "tags": ["floor"] } },
+        { "id": "Rock", "layer": "Base", "color": [1, 1, 1, 1] },
+        { "id": "Marker", "layer": "Overlay", "color": [1, 1, 0, 1], "rules": ["requires_base"] },
+        { "id": "Ladder", "layer": "Overlay", "color": [1, 1, 1, 1], "rules": ["requires_floor", "no_overlap"] }
+    ] }"#;
+
+    /** A 3x1 deck: Empty, Dirt, Rock. */
+    fn fixture() -> Fixture { Fixture::drawn(test_tileset(CATALOG), &[".DR"], &[('D', "Dirt"), ('R', "Rock")]) }
+
+    impl Fixture {
+        fn check(&self, x: u32, content: CellContent) -> Result<(), RuleViolation> {
+            check_placement(&self.map, &self.pipes, &self.tileset, &PlaceTile { level: 0, x, y: 0, content })
+        }
+    }
+
+    #[test]
+    fn requires_floor_needs_a_floor_tag() {
+        let fx = fixture();
+        let ladder = CellContent::Tile(fx.tile("Ladder"));
+        assert_eq!(fx.check(0, ladder), Err(RuleViolation::NoFloor));
+        assert_eq!(fx.check(1, ladder), Ok(()));
+        assert_eq!(fx.check(2, ladder), Err(RuleViolation::NoFloor));
+    }
+
+    #[test]
+    fn requires_base_accepts_any_base() {
+        let fx = fixture();
+        let marker = CellContent::Tile(fx.tile("Marker"));
+        assert_eq!(fx.check(0, marker), Err(RuleViolation::NoBase));
+        assert_eq!(fx.check(2, marker), Ok(()));
+    }
+
+    #[test]
+    fn no_overlap_sees_built_and_planned_tiles() {
+        let mut fx = fixture();
+        let (ladder, marker) = (fx.tile("Ladder"), fx.tile("Marker"));
+        fx.map.set_overlay(0, 1, 0, Some(marker));
+        assert_eq!(fx.check(1, CellContent::Tile(ladder)), Err(RuleViolation::Occupied { by: "Marker".into() }));
+        fx.map.set_overlay(0, 1, 0, None);
+        fx.map.set_planned(0, 1, 0, TileLayer::Overlay, Some(marker));
+        assert_eq!(fx.check(1, CellContent::Tile(ladder)), Err(RuleViolation::Occupied { by: "a planned Marker".into() }));
+        fx.map.set_planned(0, 1, 0, TileLayer::Overlay, Some(ladder));
+        assert_eq!(fx.check(1, CellContent::Tile(ladder)), Ok(()), "building a blueprint is not an overlap");
+    }
+
+    #[test]
+    fn planned_floor_counts_as_base_for_blueprints() {
+        let mut fx = fixture();
+        fx.map.set_planned(0, 0, 0, TileLayer::Base, Some(fx.tile("Dirt")));
+        assert_eq!(fx.check(0, CellContent::Planned(fx.tile("Ladder"))), Ok(()));
+        assert_eq!(fx.check(0, CellContent::Tile(fx.tile("Ladder"))), Err(RuleViolation::NoFloor));
+    }
+
+    #[test]
+    fn pipes_need_a_floor_and_a_compatible_cell() {
+        let mut fx = fixture();
+        assert_eq!(fx.check(0, CellContent::Pipe(UtilityKind::Gas)), Err(RuleViolation::NoFloor));
+        fx.pipes.set(UtilityKind::Gas, 0, 1, 0, true);
+        assert_eq!(fx.check(1, CellContent::Pipe(UtilityKind::Gas)), Ok(()));
+        assert_eq!(fx.check(1, CellContent::Pipe(UtilityKind::Data)), Ok(()));
+        assert!(matches!(fx.check(1, CellContent::Pipe(UtilityKind::Liquid)), Err(RuleViolation::Occupied { .. })));
+    }
+
+    #[test]
+    fn utility_sharing_is_symmetric() {
+        for a in UtilityKind::ALL { for b in UtilityKind::ALL {
+            assert_eq!(utilities_can_share(a, b), utilities_can_share(b, a), "{a:?} / {b:?}");
+        }}
+        assert!(!utilities_can_share(UtilityKind::Liquid, UtilityKind::Power));
+        assert!(utilities_can_share(UtilityKind::Gas, UtilityKind::Power));
+    }
+
+    #[test]
+    fn rejections_are_reported_once_per_reason() {
+        let rejected = |x, reason: &str| EditRejected { level: 0, x, y: 0, reason: reason.into() };
+        let frame = [rejected(0, "no floor"), rejected(1, "occupied"), rejected(2, "no floor"), rejected(3, "no floor")];
+        assert_eq!(rejection_summary(&frame), [
+            "can't build at (0,0) on deck 0: no floor (+2 more)",
+            "can't build at (1,0) on deck 0: occupied",
+        ]);
+        assert!(rejection_summary(&[]).is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+/**
+ * Save format migrations. Each step upgrades a raw JSON payload by exactly one version, so an old
+ * save walks the chain up to `SAVE_VERSION` before it is deserialized into `SaveFile`.
+ * When the schema changes: bump `SAVE_VERSION` and append one step here.
+ */
+use serde_json::{Map, Value, json};
+use crate::core::fluids::Fluid;
+use crate::core::pipes::UtilityKind;
+use super::{SAVE_FORMAT, SAVE_VERSION};
+
+type Migration = fn(&mut Map<String, Value>) -> anyhow::Result<()>;
+
+/** `MIGRATIONS[i]` upgrades a payload from version `i + 1` to version `i + 2`. */
+const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8, v8_to_v9, v9_to_v10, v10_to_v11];
+
+const _: () = assert!(MIGRATIONS.len() as u32 + 1 == SAVE_VERSION, "one migration per version bump");
+
+/**
+ * Reads the format version of a raw payload: `header.version` since v2, top-level `version` in v1.
+ *
+ * @param root - parsed save file JSON
+ */
+pub fn payload_version(root: &Value) -> anyhow::Result<u32> {
+    let version = root.get("header").and_then(|h| h.get("version"))
+        .or_else(|| root.get("version"))
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow::anyhow!("missing save format version header"))?;
+    u32::try_from(version).map_err(|_| anyhow::anyhow!("save format version {version} out of range"))
+}
+
+/**
+ * Upgrades a raw payload to `SAVE_VERSION`. Saves from a newer build are rejected rather than guessed at.
+ *
+ * @param root - parsed save file JSON of any known version
+ */
+pub fn migrate(mut root: Value) -> anyhow::Result<Value> {
+    let version = payload_version(&root)?;
+    if version == 0 {
+        anyhow::bail!("invalid save format version 0");
+    }
+    if version > SAVE_VERSION {
+        anyhow::bail!("save format version {version} is newer than this build supports ({SAVE_VERSION})");
+    }
+    let Some(obj) = root.as_object_mut() else { anyhow::bail!("save payload is not a JSON object") };
+    for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        let from = step as u32 + 1;
+        migration(obj).map_err(|err| anyhow::anyhow!("migrating save from v{from} to v{}: {err}", from + 1))?;
+    }
+    Ok(root)
+}
+
+/** v1 -> v2: the bare top-level `version` moves into a `header` that also names the format. */
+fn v1_to_v2(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    obj.remove("version");
+    obj.insert("header".into(), json!({ "format": SAVE_FORMAT, "version": 2 }));
+    Ok(())
+}
+
+/**
+ * v2 -> v3: maps gained an `origin` so they can grow in negative directions.
+ * Older maps were always anchored with their top-left corner at the world origin.
+ */
+fn v2_to_v3(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let height = obj.get("height").and_then(Value::as_i64).ok_or_else(|| anyhow::anyhow!("missing height"))?;
+    obj.insert("origin".into(), json!([0, -height]));
+    set_version(obj, 3);
+    Ok(())
+}
+
+/**
+ * v3 -> v4: maps gained decks. The single-plane layers become deck 0, and the new per-deck
+ * `risers` layer defaults to empty.
+ */
+fn v3_to_v4(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let mut take = |key: &str| obj.remove(key).ok_or_else(|| anyhow::anyhow!("missing {key} layer"));
+    let (base, overlay, pipes) = (take("base")?, take("overlay")?, take("pipes")?);
+    let cells = base.as_array().map_or(0, Vec::len);
+    obj.insert("levels".into(), json!([{ "base": base, "overlay": overlay, "pipes": pipes, "risers": vec![false; cells] }]));
+    set_version(obj, 4);
+    Ok(())
+}
+
+/** v4 -> v5: decks gained blueprint layers (`planned_base`, `planned_overlay`); older maps had none. */
+fn v4_to_v5(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let Some(level) = level.as_object_mut() else { anyhow::bail!("deck is not a JSON object") };
+        let cells = level.get("base").and_then(Value::as_array).map_or(0, Vec::len);
+        level.insert("planned_base".into(), json!(vec![Value::Null; cells]));
+        level.insert("planned_overlay".into(), json!(vec![Value::Null; cells]));
+    }
+    set_version(obj, 5);
+    Ok(())
+}
+
+/**
+ * v5 -> v6: saves gained the station `inventory`. Older saves record no stock, so every catalog
+ * material starts at its starting amount on load.
+ */
+fn v5_to_v6(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    obj.insert("inventory".into(), json!({}));
+    set_version(obj, 6);
+    Ok(())
+}
+
+/** v6 -> v7: maps gained multi-cell structures, with their own name table and a per-deck layer. */
+fn v6_to_v7(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    obj.insert("structures".into(), json!([]));
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let Some(level) = level.as_object_mut() else { anyhow::bail!("deck is not a JSON object") };
+        let cells = level.get("base").and_then(Value::as_array).map_or(0, Vec::len);
+        level.insert("structures".into(), json!(vec![Value::Null; cells]));
+    }
+    set_version(obj, 7);
+    Ok(())
+}
+
+/** v7 -> v8: pipe cells gained the fluid they hold and the ports attached to them; older saves had empty pipes and no ports. */
+fn v7_to_v8(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let Some(level) = level.as_object_mut() else { anyhow::bail!("deck is not a JSON object") };
+        let cells = level.get("pipes").and_then(Value::as_array).map_or(0, Vec::len);
+        level.insert("fluids".into(), json!(vec![Value::Null; cells]));
+        level.insert("ports".into(), json!(vec![Value::Null; cells]));
+    }
+    set_version(obj, 8);
+    Ok(())
+}
+
+/**
+ * v8 -> v9: utilities gained kinds, each with its own layers under `utilities`. The single pipe kind of
+ * older saves becomes gas.
+ */
+fn v8_to_v9(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let Some(level) = level.as_object_mut() else { anyhow::bail!("deck is not a JSON object") };
+        let mut gas = Map::new();
+        for key in ["pipes", "risers", "fluids", "ports"] {
+            let layer = level.remove(key).ok_or_else(|| anyhow::anyhow!("missing {key} layer"))?;
+            gas.insert(key.into(), layer);
+        }
+        level.insert("utilities".into(), json!({ "gas": gas }));
+    }
+    set_version(obj, 9);
+    Ok(())
+}
+
+/**
+ * v9 -> v10: utility segments gained inline components, and pipes gained named fluids. Older saves have no
+ * components, and the one unnamed fluid they carried becomes oxygen in gas pipes and water in liquid pipes,
+ * both in the pipes and at the sources.
+ */
+fn v9_to_v10(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let utilities = level.get_mut("utilities").and_then(Value::as_object_mut).ok_or_else(|| anyhow::anyhow!("missing utilities"))?;
+        for (name, layers) in utilities.iter_mut() {
+            let Some(layers) = layers.as_object_mut() else { anyhow::bail!("utility layers are not a JSON object") };
+            let cells = layers.get("pipes").and_then(Value::as_array).map_or(0, Vec::len);
+            layers.insert("components".into(), json!(vec![Value::Null; cells]));
+            let fluid = if name == UtilityKind::Liquid.name() { Fluid::Water } else { Fluid::Oxygen }.name();
+            for held in layers.get_mut("fluids").and_then(Value::as_array_mut).into_iter().flatten() {
+                if !held.is_null() { *held = json!({ fluid: held.take() }); }
+            }
+            for port in layers.get_mut("ports").and_then(Value::as_array_mut).into_iter().flatten() {
+                if let Some(port) = port.as_object_mut() { port.insert("fluid".into(), json!(fluid)); }
+            }
+        }
+    }
+    set_version(obj, 10);
+    Ok(())
+}
+
+/** v10 -> v11: decks gained the gas held in each cell. Older saves had no atmosphere, so every room starts empty. */
+fn v10_to_v11(obj: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let levels = obj.get_mut("levels").and_then(Value::as_array_mut).ok_or_else(|| anyhow::anyhow!("missing levels"))?;
+    for level in levels {
+        let Some(level) = level.as_object_mut() else { anyhow::bail!("deck is not a JSON object") };
+        let cells = level.get("base").and_then(Value::as_array).map_or(0, Vec::len);
+        level.insert("air".into(), json!(vec![Value::Null; cells]));
+    }
+    set_version(obj, 11);
+    Ok(())
+}
+
+fn set_version(obj: &mut Map<String, Value>, version: u32) {
+    if let Some(header) = obj.get_mut("header").and_then(Value::as_object_mut) {
+        header.insert("version".into(), json!(version));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::atmosphere::AirCell;
+    use crate::core::pipes::PipeCell;
+    use crate::core::tile::{TileId, Tileset};
+    use crate::gameplay::save::SaveFile;
+
+    /** A 2x1 map of empty space with a pipe in its right cell, as the first save format wrote it. */
+    fn v1_save() -> Value {
+        json!({ "version": 1, "width": 2, "height": 1, "tiles": ["Empty"], "base": [0, 0], "overlay": [null, null], "pipes": [false, true] })
+    }
+
+    #[test]
+    fn v1_save_migrates_to_current_version() {
+        let migrated = migrate(v1_save()).unwrap();
+        assert_eq!(payload_version(&migrated).unwrap(), SAVE_VERSION);
+        let save: SaveFile = serde_json::from_value(migrated).unwrap();
+        assert_eq!(save.header.format, SAVE_FORMAT);
+        assert_eq!(save.origin, [0, -1]);
+
+        let (map, pipes, fluids, atmosphere, inventory) = save.restore(&Tileset::default()).unwrap();
+        assert_eq!((map.size.w, map.size.h, map.levels()), (2, 1, 1));
+        assert_eq!(map.get_base(0, 1, 0), TileId::EMPTY);
+        assert!(!pipes.has(UtilityKind::Gas, 0, 0, 0));
+        assert!(pipes.has(UtilityKind::Gas, 0, 1, 0));
+        assert!(!pipes.riser(UtilityKind::Gas, 0, 1, 0));
+        assert!(pipes.component(UtilityKind::Gas, 0, 1, 0).is_none());
+        assert_eq!(fluids.amount(PipeCell::new(UtilityKind::Gas, 0, 1, 0)), 0.0);
+        assert_eq!(fluids.ports().count(), 0);
+        assert_eq!(atmosphere.amount(AirCell::new(0, 0, 0)), 0.0);
+        assert!(inventory.stock().is_empty());
+    }
+
+    #[test]
+    fn unnamed_pipe_fluid_becomes_oxygen_or_water() {
+        let layers = json!({ "pipes": [true], "risers": [false], "fluids": [5.0], "ports": [{ "rate": 1.0 }] });
+        let mut v9 = json!({ "header": { "format": SAVE_FORMAT, "version": 9 }, "levels": [{ "utilities": { "gas": layers, "liquid": layers } }] });
+        v9_to_v10(v9.as_object_mut().unwrap()).unwrap();
+        let utilities = &v9["levels"][0]["utilities"];
+        assert_eq!(utilities["gas"]["fluids"], json!([{ "oxygen": 5.0 }]));
+        assert_eq!(utilities["liquid"]["ports"], json!([{ "rate": 1.0, "fluid": "water" }]));
+        assert_eq!(utilities["liquid"]["components"], json!([null]));
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let mut current = v1_save();
+        for migration in MIGRATIONS { migration(current.as_object_mut().unwrap()).unwrap(); }
+        assert_eq!(migrate(current.clone()).unwrap(), current);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let future = json!({ "header": { "format": SAVE_FORMAT, "version": SAVE_VERSION + 1 } });
+        assert!(migrate(future).is_err());
+        assert!(migrate(json!({ "version": 0 })).is_err());
+        assert!(migrate(json!({ "width": 2 })).is_err());
+    }
+}
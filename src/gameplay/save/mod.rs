@@ -0,0 +1,448 @@
+/**
+ * Map persistence: writes `MapState` (built and planned base + overlay, structures), `PipeMap` occupancy per utility kind, the fluid and
+ * ports of every pipe (`FluidState`), the gas in every room (`Atmosphere`) and the station `Inventory` to a versioned JSON file and
+ * rebuilds those resources on load; render sync and pipe connectivity then repaint the tilemaps.
+ * Cells reference tiles and structures through per-file name tables, so saves survive catalog reordering.
+ * Older files are upgraded through `migrate` before deserialization.
+ */
+pub mod migrate;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::core::map::{MapGrowth, MapSize, MapState};
+use crate::core::events::MapResized;
+use crate::core::inventory::{Inventory, MaterialCost};
+use crate::core::structure::{Rotation, StructureId, StructurePart};
+use crate::core::tile::{TileId, TileLayer, Tileset};
+use crate::core::atmosphere::{AirCell, Atmosphere};
+use crate::core::fluids::{Fluid, FluidMix, FluidPort, FluidState};
+use crate::core::pipes::{PipeCell, PipeComponent, PipeMap, UtilityKind};
+use crate::input::GameplayInputState;
+
+/** Current on-disk save format version. */
+pub const SAVE_VERSION: u32 = 11;
+
+/** Format name written to every save header. */
+pub const SAVE_FORMAT: &str = "bsg-map";
+
+/** Destination of the quick save/load keybinds. */
+pub const QUICKSAVE_PATH: &str = "saves/quicksave.json";
+
+/** Identifies the file as a map save and records the schema version it was written with. */
+#[derive(Serialize, Deserialize)]
+pub struct SaveHeader {
+    pub format: String,
+    pub version: u32,
+}
+
+/**
+ * Layers of one deck. Vectors are row-major `width * height`; tile cells store indices into
+ * `SaveFile::tiles`. `planned_*` hold blueprints not built yet (construction progress is not saved and
+ * restarts on load). `structures` holds the structure part covering each cell. `air` holds the gas in each
+ * cell by `Fluid::name`. `utilities` holds the layers of each utility kind by `UtilityKind::name`; kinds
+ * with no segments on the deck are left out.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct SaveLevel {
+    pub base: Vec<u16>,
+    pub overlay: Vec<Option<u16>>,
+    pub planned_base: Vec<Option<u16>>,
+    pub planned_overlay: Vec<Option<u16>>,
+    pub structures: Vec<Option<SavePart>>,
+    pub air: Vec<Option<BTreeMap<String, f32>>>,
+    pub utilities: BTreeMap<String, SaveUtility>,
+}
+
+/**
+ * One utility kind's layers on a deck: segment occupancy, `risers` joining a cell to the deck above, the
+ * inline component fitted into each cell, the fluid each cell holds by `Fluid::name` and the port attached
+ * to it. Kinds that carry no fluid leave the last two empty.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct SaveUtility {
+    pub pipes: Vec<bool>,
+    pub risers: Vec<bool>,
+    pub components: Vec<Option<SaveComponent>>,
+    pub fluids: Vec<Option<BTreeMap<String, f32>>>,
+    pub ports: Vec<Option<SavePort>>,
+}
+
+/** A port attached to a pipe cell: `rate` per tick (negative for a sink) of the fluid named `fluid`. */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavePort {
+    pub rate: f32,
+    pub fluid: String,
+}
+
+/** An inline pipe component; `rotation` counts clockwise quarter turns and `passes` is a fluid name. */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SaveComponent {
+    Valve { rotation: u8, open: bool },
+    Pump { rotation: u8 },
+    Filter { rotation: u8, passes: String },
+    Junction,
+    Crossing,
+    Vent,
+    Scrubber,
+}
+
+impl SaveComponent {
+    fn capture(component: PipeComponent) -> Self {
+        match component {
+            PipeComponent::Valve { rotation, open } => SaveComponent::Valve { rotation: rotation.quarter_turns(), open },
+            PipeComponent::Pump { rotation } => SaveComponent::Pump { rotation: rotation.quarter_turns() },
+            PipeComponent::Filter { rotation, passes } => {
+                SaveComponent::Filter { rotation: rotation.quarter_turns(), passes: passes.name().to_string() }
+            }
+            PipeComponent::Junction => SaveComponent::Junction,
+            PipeComponent::Crossing => SaveComponent::Crossing,
+            PipeComponent::Vent => SaveComponent::Vent,
+            PipeComponent::Scrubber => SaveComponent::Scrubber,
+        }
+    }
+
+    fn restore(&self) -> anyhow::Result<PipeComponent> {
+        let turn = |rotation: u8| Rotation::from_quarter_turns(rotation);
+        Ok(match self {
+            SaveComponent::Valve { rotation, open } => PipeComponent::Valve { rotation: turn(*rotation), open: *open },
+            SaveComponent::Pump { rotation } => PipeComponent::Pump { rotation: turn(*rotation) },
+            SaveComponent::Filter { rotation, passes } => {
+                let passes = Fluid::from_name(passes).ok_or_else(|| anyhow::anyhow!("unknown fluid '{passes}'"))?;
+                PipeComponent::Filter { rotation: turn(*rotation), passes }
+            }
+            SaveComponent::Junction => PipeComponent::Junction,
+            SaveComponent::Crossing => PipeComponent::Crossing,
+            SaveComponent::Vent => PipeComponent::Vent,
+            SaveComponent::Scrubber => PipeComponent::Scrubber,
+        })
+    }
+}
+
+/**
+ * One covered cell of a structure: `structure` indexes `SaveFile::structures`, `rotation` counts clockwise
+ * quarter turns and `offset` is the cell's offset from the structure's anchor.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SavePart {
+    pub structure: u16,
+    pub rotation: u8,
+    pub offset: [u32; 2],
+}
+
+/**
+ * On-disk snapshot of the map: shared geometry, the tile and structure name tables (stable catalog names),
+ * one `SaveLevel` per deck, bottom deck first, and the material stock by catalog material id.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub header: SaveHeader,
+    pub width: u32,
+    pub height: u32,
+    /** Logical cell of storage (0,0); see `MapState::origin`. */
+    pub origin: [i32; 2],
+    pub tiles: Vec<String>,
+    pub structures: Vec<String>,
+    pub levels: Vec<SaveLevel>,
+    pub inventory: MaterialCost,
+}
+
+impl SaveFile {
+    /**
+     * Snapshots the map and pipe layers, the fluid in the pipes, the atmosphere and the stock, interning every
+     * referenced tile into the name table.
+     *
+     * @param map - map state to capture
+     * @param pipes - pipe occupancy to capture
+     * @param fluids - pipe contents and ports to capture
+     * @param atmosphere - gas in the rooms to capture
+     * @param inventory - material stock to capture
+     * @param tileset - tileset used to resolve TileIds to catalog names
+     */
+    pub fn capture(
+        map: &MapState,
+        pipes: &PipeMap,
+        fluids: &FluidState,
+        atmosphere: &Atmosphere,
+        inventory: &Inventory,
+        tileset: &Tileset,
+    ) -> Self {
+        let mut tiles: Vec<String> = Vec::new();
+        let mut lookup: HashMap<TileId, u16> = HashMap::new();
+        let mut intern = |id: TileId| -> u16 {
+            *lookup.entry(id).or_insert_with(|| {
+                tiles.push(tileset.def(id).name.clone());
+                (tiles.len() - 1) as u16
+            })
+        };
+        let mut structures: Vec<String> = Vec::new();
+        let mut structure_lookup: HashMap<StructureId, u16> = HashMap::new();
+        let mut intern_part = |part: StructurePart| -> SavePart {
+            let structure = *structure_lookup.entry(part.structure).or_insert_with(|| {
+                structures.push(tileset.structure(part.structure).name.clone());
+                (structures.len() - 1) as u16
+            });
+            SavePart { structure, rotation: part.rotation.quarter_turns(), offset: part.offset.to_array() }
+        };
+
+        let (w, h) = (map.size.w, map.size.h);
+        let n = (w * h) as usize;
+        let ports: HashMap<PipeCell, FluidPort> = fluids.ports().collect();
+        let mut levels = Vec::with_capacity(map.levels() as usize);
+        for level in 0..map.levels() {
+            let mut out = SaveLevel {
+                base: Vec::with_capacity(n),
+                overlay: Vec::with_capacity(n),
+                planned_base: Vec::with_capacity(n),
+                planned_overlay: Vec::with_capacity(n),
+                structures: Vec::with_capacity(n),
+                air: Vec::with_capacity(n),
+                utilities: BTreeMap::new(),
+            };
+            for y in 0..h { for x in 0..w {
+                out.base.push(intern(map.get_base(level, x, y)));
+                out.overlay.push(map.get_overlay(level, x, y).map(&mut intern));
+                out.planned_base.push(map.get_planned(level, x, y, TileLayer::Base).map(&mut intern));
+                out.planned_overlay.push(map.get_planned(level, x, y, TileLayer::Overlay).map(&mut intern));
+                out.structures.push(map.get_structure(level, x, y).map(&mut intern_part));
+                out.air.push(named(atmosphere.mix(AirCell::new(level, x, y))));
+            }}
+            for kind in UtilityKind::ALL {
+                let mut layers = SaveUtility {
+                    pipes: Vec::with_capacity(n),
+                    risers: Vec::with_capacity(n),
+                    components: Vec::with_capacity(n),
+                    fluids: Vec::new(),
+                    ports: Vec::new(),
+                };
+                for y in 0..h { for x in 0..w {
+                    layers.pipes.push(pipes.has(kind, level, x, y));
+                    layers.risers.push(pipes.riser(kind, level, x, y));
+                    layers.components.push(pipes.component(kind, level, x, y).map(SaveComponent::capture));
+                    if !kind.carries_fluid() { continue }
+                    let cell = PipeCell::new(kind, level, x, y);
+                    layers.fluids.push(named(fluids.mix(cell)));
+                    layers.ports.push(ports.get(&cell).map(|port| SavePort { rate: port.rate, fluid: port.fluid.name().to_string() }));
+                }}
+                if layers.pipes.contains(&true) { out.utilities.insert(kind.name().to_string(), layers); }
+            }
+            levels.push(out);
+        }
+
+        let header = SaveHeader { format: SAVE_FORMAT.to_string(), version: SAVE_VERSION };
+        let inventory = inventory.stock().clone();
+        Self { header, width: w, height: h, origin: map.origin.to_array(), tiles, structures, levels, inventory }
+    }
+
+    /**
+     * Rebuilds map, pipe, fluid, atmosphere and inventory resources from the snapshot, resolving tile names against the live
+     * tileset. Map and pipes come back fully dirty so render sync rebuilds every tile;
+     * catalog materials the save does not mention start at their starting amount.
+     *
+     * @param tileset - live tileset used to intern tile names
+     */
+    pub fn restore(&self, tileset: &Tileset) -> anyhow::Result<(MapState, PipeMap, FluidState, Atmosphere, Inventory)> {
+        let n = (self.width * self.height) as usize;
+        if self.levels.is_empty() {
+            anyhow::bail!("save has no decks");
+        }
+        for (i, l) in self.levels.iter().enumerate() {
+            let lens = [l.base.len(), l.overlay.len(), l.planned_base.len(), l.planned_overlay.len(), l.structures.len(), l.air.len()];
+            let utility_lens = l.utilities.values().flat_map(|u| [u.pipes.len(), u.risers.len(), u.components.len()]);
+            if lens.into_iter().chain(utility_lens).any(|len| len != n) {
+                anyhow::bail!("deck {i} layer lengths do not match map size {}x{}", self.width, self.height);
+            }
+            for (name, u) in &l.utilities {
+                let Some(kind) = UtilityKind::from_name(name) else { anyhow::bail!("deck {i} has unknown utility kind '{name}'") };
+                let fluid_len = if kind.carries_fluid() { n } else { 0 };
+                if u.fluids.len() != fluid_len || u.ports.len() != fluid_len {
+                    anyhow::bail!("deck {i} {name} fluid layer lengths do not match map size {}x{}", self.width, self.height);
+                }
+            }
+        }
+        let ids = self.tiles.iter()
+            .map(|name| tileset.id(name).ok_or_else(|| anyhow::anyhow!("unknown tile id '{name}'")))
+            .collect::<anyhow::Result<Vec<TileId>>>()?;
+        let resolve = |i: u16| ids.get(i as usize).copied()
+            .ok_or_else(|| anyhow::anyhow!("tile index {i} out of range"));
+        let structure_ids = self.structures.iter()
+            .map(|name| tileset.structure_id(name).ok_or_else(|| anyhow::anyhow!("unknown structure id '{name}'")))
+            .collect::<anyhow::Result<Vec<StructureId>>>()?;
+        let resolve_part = |p: SavePart| -> anyhow::Result<StructurePart> {
+            let structure = structure_ids.get(p.structure as usize).copied()
+                .ok_or_else(|| anyhow::anyhow!("structure index {} out of range", p.structure))?;
+            Ok(StructurePart { structure, rotation: Rotation::from_quarter_turns(p.rotation), offset: UVec2::from_array(p.offset) })
+        };
+
+        let levels = self.levels.len() as u32;
+        let mut map = MapState::new(MapSize { w: self.width, h: self.height }, levels);
+        map.origin = IVec2::from_array(self.origin);
+        let mut pipes = PipeMap::new((self.width, self.height), levels);
+        let mut fluids = FluidState::default();
+        let mut atmosphere = Atmosphere::default();
+        let fluid = |name: &str| Fluid::from_name(name).ok_or_else(|| anyhow::anyhow!("unknown fluid '{name}'"));
+        for (level, l) in (0..levels).zip(&self.levels) {
+            for y in 0..self.height { for x in 0..self.width {
+                let i = map.idx(x, y);
+                map.set_base(level, x, y, resolve(l.base[i])?);
+                map.set_overlay(level, x, y, l.overlay[i].map(resolve).transpose()?);
+                map.set_planned(level, x, y, TileLayer::Base, l.planned_base[i].map(resolve).transpose()?);
+                map.set_planned(level, x, y, TileLayer::Overlay, l.planned_overlay[i].map(resolve).transpose()?);
+                map.set_structure(level, x, y, l.structures[i].map(resolve_part).transpose()?);
+                for (name, layers) in &l.utilities {
+                    let Some(kind) = UtilityKind::from_name(name) else { continue };
+                    pipes.set(kind, level, x, y, layers.pipes[i]);
+                    pipes.set_riser(kind, level, x, y, layers.risers[i]);
+                    pipes.set_component(kind, level, x, y, layers.components[i].as_ref().map(SaveComponent::restore).transpose()?);
+                }
+            }}
+            // Gas only stays in open cells, so it is restored once every tile of the deck is in.
+            for (i, held) in l.air.iter().enumerate() {
+                let cell = AirCell::new(level, i as u32 % self.width, i as u32 / self.width);
+                for (name, &amount) in held.iter().flatten() { atmosphere.set_amount(&map, tileset, cell, fluid(name)?, amount); }
+            }
+            // Fluid only stays in pipe nodes, so it is restored once every segment and component of the deck is in.
+            for (name, layers) in &l.utilities {
+                let Some(kind) = UtilityKind::from_name(name) else { continue };
+                for (i, (held, port)) in layers.fluids.iter().zip(&layers.ports).enumerate() {
+                    let cell = PipeCell::new(kind, level, i as u32 % self.width, i as u32 / self.width);
+                    for (name, &amount) in held.iter().flatten() { fluids.set_amount(&pipes, cell, fluid(name)?, amount); }
+                    if let Some(port) = port { fluids.attach_port(cell, FluidPort { rate: port.rate, fluid: fluid(&port.fluid)? }); }
+                }
+            }
+        }
+        map.mark_all_dirty();
+        pipes.mark_all_dirty();
+        let mut inventory = Inventory::new(self.inventory.clone());
+        inventory.stock_materials(&tileset.materials);
+        Ok((map, pipes, fluids, atmosphere, inventory))
+    }
+}
+
+/** Names the fluids `mix` holds by `Fluid::name`; `None` when it holds nothing. */
+fn named(mix: FluidMix) -> Option<BTreeMap<String, f32>> {
+    let held = Fluid::ALL.into_iter().filter(|&f| mix.get(f) > 0.0).map(|f| (f.name().to_string(), mix.get(f)));
+    Some(held.collect::<BTreeMap<_, _>>()).filter(|held| !held.is_empty())
+}
+
+/** Serializes a save file to `path`, creating parent directories as needed. */
+pub fn write_save(path: &Path, save: &SaveFile) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    std::fs::write(path, serde_json::to_vec(save)?)?;
+    Ok(())
+}
+
+/** Reads a save file from `path`, migrating older versions and rejecting unknown future ones. */
+pub fn read_save(path: &Path) -> anyhow::Result<SaveFile> {
+    let raw: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    let save: SaveFile = serde_json::from_value(migrate::migrate(raw)?)?;
+    if save.header.format != SAVE_FORMAT {
+        anyhow::bail!("not a map save (format '{}')", save.header.format);
+    }
+    Ok(save)
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (quick_save_from_input, quick_load_from_input));
+    }
+}
+
+/** Writes the quick save when the save keybind was pressed this frame. */
+fn quick_save_from_input(
+    mut gi: ResMut<GameplayInputState>,
+    map: Res<MapState>,
+    pipes: Res<PipeMap>,
+    fluids: Res<FluidState>,
+    atmosphere: Res<Atmosphere>,
+    inventory: Res<Inventory>,
+    tileset: Res<Tileset>,
+) {
+    if !gi.save_requested { return }
+    gi.save_requested = false;
+    let save = SaveFile::capture(&map, &pipes, &fluids, &atmosphere, &inventory, &tileset);
+    match write_save(Path::new(QUICKSAVE_PATH), &save) {
+        Ok(()) => info!("saved map to {QUICKSAVE_PATH}"),
+        Err(err) => error!("failed to save {QUICKSAVE_PATH}: {err}"),
+    }
+}
+
+/**
+ * Loads the quick save when the load keybind was pressed, replacing `MapState`, `PipeMap`, `FluidState`, `Atmosphere` and `Inventory`.
+ * The replacements are fully dirty; `MapResized` lets tilemaps and the debug grid adopt the saved size and
+ * origin, and makes render sync repaint every tilemap.
+ */
+fn quick_load_from_input(
+    mut gi: ResMut<GameplayInputState>,
+    mut commands: Commands,
+    mut resized: MessageWriter<MapResized>,
+    tileset: Res<Tileset>,
+) {
+    if !gi.load_requested { return }
+    gi.load_requested = false;
+    match read_save(Path::new(QUICKSAVE_PATH)).and_then(|save| save.restore(&tileset)) {
+        Ok((new_map, new_pipes, new_fluids, new_atmosphere, new_inventory)) => {
+            commands.insert_resource(new_map);
+            commands.insert_resource(new_pipes);
+            commands.insert_resource(new_fluids);
+            commands.insert_resource(new_atmosphere);
+            commands.insert_resource(new_inventory);
+            resized.write(MapResized { growth: MapGrowth::default() });
+            info!("loaded map from {QUICKSAVE_PATH}");
+        }
+        Err(err) => error!("failed to load {QUICKSAVE_PATH}: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::catalog::test_tileset;
+
+    #[test]
+    fn pipe_fluid_and_ports_survive_a_round_trip() {
+        let tileset = Tileset::default();
+        let map = MapState::new(MapSize { w: 3, h: 2 }, 2);
+        let mut pipes = PipeMap::new((3, 2), 2);
+        for x in 0..3 { pipes.set(UtilityKind::Liquid, 1, x, 1, true); }
+        pipes.set(UtilityKind::Power, 0, 0, 0, true);
+        let cell = |x| PipeCell::new(UtilityKind::Liquid, 1, x, 1);
+        let mut fluids = FluidState::default();
+        fluids.set_amount(&pipes, cell(0), Fluid::Water, 42.5);
+        fluids.set_amount(&pipes, cell(2), Fluid::Oxygen, 3.0);
+        fluids.attach_port(cell(1), FluidPort { rate: -1.5, fluid: Fluid::Water });
+
+        let atmosphere = Atmosphere::default();
+        let json = serde_json::to_value(SaveFile::capture(&map, &pipes, &fluids, &atmosphere, &Inventory::default(), &tileset)).unwrap();
+        let save: SaveFile = serde_json::from_value(migrate::migrate(json).unwrap()).unwrap();
+        let (_, restored_pipes, restored, _, _) = save.restore(&tileset).unwrap();
+        assert!(restored_pipes.has(UtilityKind::Power, 0, 0, 0));
+        for x in 0..3 { assert_eq!(restored.mix(cell(x)), fluids.mix(cell(x))); }
+        assert_eq!(restored.ports().collect::<Vec<_>>(), fluids.ports().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn room_gas_survives_a_round_trip() {
+        let tileset = test_tileset(r#"{ "tiles": [
+            { "id": "Empty", "layer": "Base", "color": [0, 0, 0, 0] },
+            { "id": "Deck", "layer": "Base", "color": [1, 1, 1, 1] }
+        ] }"#);
+        let mut map = MapState::new(MapSize { w: 2, h: 2 }, 1);
+        map.set_base(0, 1, 1, tileset.id("Deck").unwrap());
+        let room = AirCell::new(0, 1, 1);
+        let mut atmosphere = Atmosphere::default();
+        atmosphere.set_amount(&map, &tileset, room, Fluid::Oxygen, 21.0);
+        atmosphere.set_amount(&map, &tileset, room, Fluid::Nitrogen, 78.0);
+
+        let save = SaveFile::capture(&map, &PipeMap::new((2, 2), 1), &FluidState::default(), &atmosphere, &Inventory::default(), &tileset);
+        let json = serde_json::to_value(save).unwrap();
+        let save: SaveFile = serde_json::from_value(migrate::migrate(json).unwrap()).unwrap();
+        let (_, _, _, restored, _) = save.restore(&tileset).unwrap();
+        assert_eq!(restored.mix(room), atmosphere.mix(room));
+        assert_eq!(restored.amount(AirCell::new(0, 0, 0)), 0.0);
+    }
+}
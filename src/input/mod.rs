@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 use bevy::input::mouse::MouseWheel;
+use crate::core::pipes::UtilityKind;
 // Input should not depend on render/tilemaps; emit world cursor instead
 
 #[derive(Resource)]
@@ -8,10 +9,11 @@ pub struct CameraInputState {
     pub pan_delta: Vec2,
     pub toggle_overlay: bool,
     pub toggle_engineering: bool,
+    pub toggle_ghost_below: bool,
 }
 
 impl Default for CameraInputState {
-    fn default() -> Self { Self { zoom_factor: 1.0, pan_delta: Vec2::ZERO, toggle_overlay: false, toggle_engineering: false } }
+    fn default() -> Self { Self { zoom_factor: 1.0, pan_delta: Vec2::ZERO, toggle_overlay: false, toggle_engineering: false, toggle_ghost_below: false } }
 }
 
 pub struct InputPlugin;
@@ -25,8 +27,13 @@ impl Plugin for InputPlugin {
                 collect_wasd_pan,
                 toggle_overlay_on_backspace,
                 toggle_engineering_on_key,
+                toggle_ghost_below_on_key,
                 collect_tool_keys,
                 collect_pointer_actions,
+                collect_save_load_keys,
+                collect_resize_keys,
+                collect_level_keys,
+                collect_history_keys,
             ));
     }
 }
@@ -64,9 +71,38 @@ fn toggle_engineering_on_key(keys: Res<ButtonInput<KeyCode>>, mut state: ResMut<
     if keys.just_pressed(KeyCode::KeyE) { state.toggle_engineering = true; }
 }
 
-/** Player tool modes for gameplay interactions. */
+/** Collects the toggle (KeyG) for ghosting the deck below the active one; render consumes the flag. */
+fn toggle_ghost_below_on_key(keys: Res<ButtonInput<KeyCode>>, mut state: ResMut<CameraInputState>) {
+    if keys.just_pressed(KeyCode::KeyG) { state.toggle_ghost_below = true; }
+}
+
+/** Player tool modes for gameplay interactions. The pipe tools act on one utility kind's layer. */
 #[derive(Clone, Copy, PartialEq, Eq)]
-pub enum Tool { None, PipePlace, PipeErase }
+pub enum Tool {
+    None,
+    PipePlace(UtilityKind),
+    PipeErase(UtilityKind),
+    PipeRiser(UtilityKind),
+    PipeComponent(UtilityKind),
+    Port(UtilityKind),
+    Ladder,
+    Cancel,
+    Structure,
+}
+
+impl Tool {
+    /** The same tool retargeted to `kind`; tools without a utility kind are returned unchanged. */
+    fn with_utility(self, kind: UtilityKind) -> Self {
+        match self {
+            Tool::PipePlace(_) => Tool::PipePlace(kind),
+            Tool::PipeErase(_) => Tool::PipeErase(kind),
+            Tool::PipeRiser(_) => Tool::PipeRiser(kind),
+            Tool::PipeComponent(_) => Tool::PipeComponent(kind),
+            Tool::Port(_) => Tool::Port(kind),
+            other => other,
+        }
+    }
+}
 
 /**
  * Transient gameplay input derived from raw inputs each frame.
@@ -75,6 +111,10 @@ pub enum Tool { None, PipePlace, PipeErase }
 #[derive(Resource)]
 pub struct GameplayInputState {
     pub selected_tool: Tool,
+    /** Utility kind the pipe tools pick up when selected (Digit1-4: gas, liquid, power, data). */
+    pub utility: UtilityKind,
+    /** Deck that tools act on and that is shown; gameplay clamps it to the map's deck count. */
+    pub current_level: u32,
     pub left_just_pressed: bool,
     pub left_pressed: bool,
     pub left_just_released: bool,
@@ -82,12 +122,38 @@ pub struct GameplayInputState {
     pub right_pressed: bool,
     pub right_just_released: bool,
     pub world_cursor: Option<Vec2>,
+    /** One-shot: quick save requested (F5); reset by the save system. */
+    pub save_requested: bool,
+    /** One-shot: quick load requested (F9); reset by the save system. */
+    pub load_requested: bool,
+    /** One-shot: grow the map on every side (PageUp); reset by placement. */
+    pub grow_map_requested: bool,
+    /** One-shot: undo the last edit step (Ctrl+Z); reset by placement. */
+    pub undo_requested: bool,
+    /** One-shot: redo the last undone step (Ctrl+Shift+Z); reset by placement. */
+    pub redo_requested: bool,
+    /** One-shot: select the next structure (B while the structure tool is active); reset by placement. */
+    pub next_structure_requested: bool,
+    /** One-shot: select the next pipe component (C while the component tool is active); reset by placement. */
+    pub next_component_requested: bool,
+    /** One-shot: select the next port (K while the port tool is active); reset by placement. */
+    pub next_port_requested: bool,
+    /** One-shot: rotate the structure or component brush clockwise (R); reset by placement. */
+    pub rotate_requested: bool,
+    /** One-shot: flip the L of the pipe drag between horizontal-first and vertical-first (F); reset by piping. */
+    pub flip_route_requested: bool,
+    /** One-shot: toggle routing pipe drags around blocked cells (T); reset by piping. */
+    pub toggle_auto_route_requested: bool,
+    /** One-shot: log what the cell under the cursor holds (I); reset by inspect. */
+    pub inspect_requested: bool,
 }
 
 impl Default for GameplayInputState {
     fn default() -> Self {
         Self {
             selected_tool: Tool::None,
+            utility: UtilityKind::Gas,
+            current_level: 0,
             left_just_pressed: false,
             left_pressed: false,
             left_just_released: false,
@@ -95,17 +161,81 @@ impl Default for GameplayInputState {
             right_pressed: false,
             right_just_released: false,
             world_cursor: None,
+            save_requested: false,
+            load_requested: false,
+            grow_map_requested: false,
+            undo_requested: false,
+            redo_requested: false,
+            next_structure_requested: false,
+            next_component_requested: false,
+            next_port_requested: false,
+            rotate_requested: false,
+            flip_route_requested: false,
+            toggle_auto_route_requested: false,
+            inspect_requested: false,
         }
     }
 }
 
-/** Handles keybinds for selecting gameplay tools. */
+/** Keys selecting the utility kind of the pipe tools, in `UtilityKind::ALL` order. */
+const UTILITY_KEYS: [KeyCode; 4] = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+
+/**
+ * Handles keybinds for selecting gameplay tools, the utility kind of the pipe tools (Digit1-4, which also
+ * retargets an active pipe tool), cycling structures (B again), components (C again) and ports (K again), rotating them (R),
+ * flipping the L of pipe drags (F), toggling their auto-routing (T) and inspecting the cell under the cursor (I).
+ */
 fn collect_tool_keys(keys: Res<ButtonInput<KeyCode>>, mut gi: ResMut<GameplayInputState>) {
-    if keys.just_pressed(KeyCode::KeyP) { gi.selected_tool = Tool::PipePlace; }
-    if keys.just_pressed(KeyCode::KeyO) { gi.selected_tool = Tool::PipeErase; }
+    for (key, kind) in UTILITY_KEYS.into_iter().zip(UtilityKind::ALL) {
+        if keys.just_pressed(key) { gi.utility = kind; gi.selected_tool = gi.selected_tool.with_utility(kind); }
+    }
+    if keys.just_pressed(KeyCode::KeyP) { gi.selected_tool = Tool::PipePlace(gi.utility); }
+    if keys.just_pressed(KeyCode::KeyO) { gi.selected_tool = Tool::PipeErase(gi.utility); }
+    if keys.just_pressed(KeyCode::KeyV) { gi.selected_tool = Tool::PipeRiser(gi.utility); }
+    if keys.just_pressed(KeyCode::KeyC) {
+        if matches!(gi.selected_tool, Tool::PipeComponent(_)) { gi.next_component_requested = true; }
+        gi.selected_tool = Tool::PipeComponent(gi.utility);
+    }
+    if keys.just_pressed(KeyCode::KeyK) {
+        if matches!(gi.selected_tool, Tool::Port(_)) { gi.next_port_requested = true; }
+        gi.selected_tool = Tool::Port(gi.utility);
+    }
+    if keys.just_pressed(KeyCode::KeyL) { gi.selected_tool = Tool::Ladder; }
+    if keys.just_pressed(KeyCode::KeyX) { gi.selected_tool = Tool::Cancel; }
+    if keys.just_pressed(KeyCode::KeyB) {
+        if gi.selected_tool == Tool::Structure { gi.next_structure_requested = true; }
+        gi.selected_tool = Tool::Structure;
+    }
+    if keys.just_pressed(KeyCode::KeyR) { gi.rotate_requested = true; }
+    if keys.just_pressed(KeyCode::KeyF) { gi.flip_route_requested = true; }
+    if keys.just_pressed(KeyCode::KeyT) { gi.toggle_auto_route_requested = true; }
+    if keys.just_pressed(KeyCode::KeyI) { gi.inspect_requested = true; }
     if keys.just_pressed(KeyCode::Escape) { gi.selected_tool = Tool::None; }
 }
 
+/** Handles quick save (F5) and quick load (F9) keybinds. */
+fn collect_save_load_keys(keys: Res<ButtonInput<KeyCode>>, mut gi: ResMut<GameplayInputState>) {
+    if keys.just_pressed(KeyCode::F5) { gi.save_requested = true; }
+    if keys.just_pressed(KeyCode::F9) { gi.load_requested = true; }
+}
+
+/** Handles the explicit map resize keybind (PageUp). */
+fn collect_resize_keys(keys: Res<ButtonInput<KeyCode>>, mut gi: ResMut<GameplayInputState>) {
+    if keys.just_pressed(KeyCode::PageUp) { gi.grow_map_requested = true; }
+}
+
+/** Handles deck selection: Period moves up a deck, Comma moves down. */
+fn collect_level_keys(keys: Res<ButtonInput<KeyCode>>, mut gi: ResMut<GameplayInputState>) {
+    if keys.just_pressed(KeyCode::Period) { gi.current_level += 1; }
+    if keys.just_pressed(KeyCode::Comma) { gi.current_level = gi.current_level.saturating_sub(1); }
+}
+
+/** Handles undo (Ctrl+Z) and redo (Ctrl+Shift+Z). */
+fn collect_history_keys(keys: Res<ButtonInput<KeyCode>>, mut gi: ResMut<GameplayInputState>) {
+    if !keys.just_pressed(KeyCode::KeyZ) || !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) { return }
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) { gi.redo_requested = true; } else { gi.undo_requested = true; }
+}
+
 /**
  * Produces per-frame pointer actions (left/right pressed/released) and current world cursor position.
  */
@@ -1,7 +1,9 @@
 use bevy::prelude::*;
-use crate::input::CameraInputState;
+use crate::core::map::{MapSet, MapState};
+use crate::input::{CameraInputState, GameplayInputState};
 
 pub mod overlay;
+pub mod structures;
 pub mod sync;
 pub mod tilemaps;
 
@@ -9,9 +11,18 @@ pub struct RenderPlugin;
 
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((overlay::DebugGridPlugin, tilemaps::GameTilemapsPlugin))
+        app.add_plugins((overlay::DebugGridPlugin, tilemaps::GameTilemapsPlugin, structures::StructureSpritesPlugin))
+            .init_resource::<LayerView>()
             .add_systems(Startup, setup_camera)
-            .add_systems(Update, (apply_input_zoom, apply_input_pan, apply_input_toggle_overlay, apply_input_toggle_engineering));
+            .add_systems(Update, (
+                apply_input_zoom,
+                apply_input_pan,
+                apply_input_toggle_overlay,
+                apply_input_toggle_engineering,
+                apply_input_toggle_ghost,
+                follow_active_level,
+            ))
+            .add_systems(PostUpdate, apply_layer_visibility.in_set(MapSet::Sync));
     }
 }
 
@@ -41,44 +52,96 @@ fn apply_input_pan(mut state: ResMut<CameraInputState>, mut q_cam: Query<(&mut T
 }
 
 /**
- * Toggles visibility of the generic overlay tilemap when the input flag is set.
+ * Presentation state for the tilemap sets: which deck is shown and which layers are visible.
+ * Visual only; gameplay never branches on it. `apply_layer_visibility` applies it to `TilemapLayers`.
+ */
+#[derive(Resource)]
+pub struct LayerView {
+    pub active_level: u32,
+    pub overlay_visible: bool,
+    pub engineering: bool,
+    /** Show the deck below the active one as a translucent ghost (base and overlay only). */
+    pub ghost_below: bool,
+}
+
+impl Default for LayerView {
+    fn default() -> Self { Self { active_level: 0, overlay_visible: true, engineering: false, ghost_below: true } }
+}
+
+impl LayerView {
+    /** Deck currently drawn as a ghost, if any. */
+    pub fn ghost_level(&self) -> Option<u32> {
+        if self.ghost_below { self.active_level.checked_sub(1) } else { None }
+    }
+}
+
+/**
+ * Toggles visibility of the generic overlay tilemaps when the input flag is set.
  *
  * @param state - input state containing the one-shot toggle flag
- * @param layers - tilemap entities to modify
- * @param q_vis - query to access and mutate visibility on entities
+ * @param view - layer presentation state to modify
  */
-fn apply_input_toggle_overlay(mut state: ResMut<CameraInputState>, layers: Res<tilemaps::TilemapLayers>, mut q_vis: Query<&mut Visibility>) {
+fn apply_input_toggle_overlay(mut state: ResMut<CameraInputState>, mut view: ResMut<LayerView>) {
     if !state.toggle_overlay { return }
-    let Ok(mut vis) = q_vis.get_mut(layers.overlay) else { return };
-    *vis = match *vis {
-        Visibility::Visible => Visibility::Hidden,
-        Visibility::Hidden => Visibility::Visible,
-        Visibility::Inherited => Visibility::Visible,
-    };
+    view.overlay_visible = !view.overlay_visible;
     state.toggle_overlay = false;
 }
 
 /**
- * Switches between normal and engineering views for pipes by toggling the two pipe tilemaps.
+ * Switches between normal and engineering views for every utility kind.
  *
  * @param state - input state containing the one-shot engineering toggle flag
- * @param layers - tilemap entities for normal and engineering pipe views
- * @param q_vis - query to access and mutate visibility on entities
+ * @param view - layer presentation state to modify
  */
-fn apply_input_toggle_engineering(mut state: ResMut<CameraInputState>, layers: Res<tilemaps::TilemapLayers>, mut q_vis: Query<&mut Visibility>) {
+fn apply_input_toggle_engineering(mut state: ResMut<CameraInputState>, mut view: ResMut<LayerView>) {
     if !state.toggle_engineering { return }
-    // First fetch and compute desired vis
-    let pipes_visible = {
-        if let Ok(vis_pipes) = q_vis.get_mut(layers.pipes) {
-            matches!(*vis_pipes, Visibility::Visible | Visibility::Inherited)
-        } else { return }
+    view.engineering = !view.engineering;
+    state.toggle_engineering = false;
+}
+
+/** Toggles ghosting of the deck below the active one. */
+fn apply_input_toggle_ghost(mut state: ResMut<CameraInputState>, mut view: ResMut<LayerView>) {
+    if !state.toggle_ghost_below { return }
+    view.ghost_below = !view.ghost_below;
+    state.toggle_ghost_below = false;
+}
+
+/** Mirrors the deck selected in gameplay input into the view, clamped to the decks that exist. */
+fn follow_active_level(gi: Res<GameplayInputState>, map: Res<MapState>, mut view: ResMut<LayerView>) {
+    let level = gi.current_level.min(map.levels() - 1);
+    if view.active_level != level { view.active_level = level; }
+}
+
+/**
+ * Applies `LayerView` to every deck: only the active deck (and optionally the ghosted one below) is shown,
+ * overlay and pipe layers follow their toggles, and a ghosted deck hides its pipes, pipe preview, blueprints and structures.
+ * Shown entities use `Inherited` so a hidden deck root hides all of its layers.
+ *
+ * @param view - layer presentation state
+ * @param layers - tilemap entities per deck
+ * @param q_vis - query to access and mutate visibility on entities
+ */
+fn apply_layer_visibility(view: Res<LayerView>, layers: Res<tilemaps::TilemapLayers>, mut q_vis: Query<&mut Visibility>) {
+    if !view.is_changed() && !layers.is_changed() { return }
+    let mut set = |entity: Entity, shown: bool| {
+        if let Ok(mut vis) = q_vis.get_mut(entity) {
+            *vis = if shown { Visibility::Inherited } else { Visibility::Hidden };
+        }
     };
-    // Now apply to both in separate borrows
-    if let Ok(mut vis_pipes) = q_vis.get_mut(layers.pipes) {
-        *vis_pipes = if pipes_visible { Visibility::Hidden } else { Visibility::Visible };
-    }
-    if let Ok(mut vis_eng) = q_vis.get_mut(layers.pipes_eng) {
-        *vis_eng = if pipes_visible { Visibility::Visible } else { Visibility::Hidden };
+    for (i, level) in layers.levels.iter().enumerate() {
+        let i = i as u32;
+        let ghost = view.ghost_level() == Some(i);
+        set(level.root, i == view.active_level || ghost);
+        set(level.base, true);
+        set(level.overlay, view.overlay_visible);
+        set(level.planned_base, !ghost);
+        set(level.planned_overlay, !ghost && view.overlay_visible);
+        for ((pipes, pipes_eng), components) in level.pipes.into_iter().zip(level.pipes_eng).zip(level.components) {
+            set(pipes, !ghost && !view.engineering);
+            set(pipes_eng, !ghost && view.engineering);
+            set(components, !ghost);
+        }
+        set(level.pipe_preview, !ghost);
+        set(level.structures, !ghost);
     }
-    state.toggle_engineering = false;
 }
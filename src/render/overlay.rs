@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use crate::core::grid::{GridConfig, DebugGridConfig};
-use crate::core::map::MapState;
+use crate::core::map::{MapSet, MapState};
+use crate::core::events::MapResized;
 // use crate::render::sync::world_from_grid;
 
 #[derive(Component)]
@@ -12,12 +13,36 @@ impl Plugin for DebugGridPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource::<DebugGridConfig>(Default::default())
             .add_systems(Startup, setup_debug_grid)
-            .add_systems(Update, sync_debug_grid_visibility);
+            .add_systems(Update, sync_debug_grid_visibility)
+            .add_systems(PostUpdate, rebuild_debug_grid.in_set(MapSet::Rebuild));
     }
 }
 
 fn setup_debug_grid(mut commands: Commands, map: Res<MapState>, grid: Res<GridConfig>, dbg: Res<DebugGridConfig>) {
-    let root = commands.spawn((Name::new("DebugGrid"), DebugGridRoot, Visibility::Hidden, Transform::default(), GlobalTransform::default())).id();
+    spawn_debug_grid(&mut commands, &map, &grid, &dbg);
+}
+
+/** Replaces the debug grid after the map geometry changed so lines cover the new bounds. */
+fn rebuild_debug_grid(
+    mut commands: Commands,
+    mut resized: MessageReader<MapResized>,
+    roots: Query<Entity, With<DebugGridRoot>>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    dbg: Res<DebugGridConfig>,
+) {
+    if resized.read().count() == 0 { return }
+    for root in &roots { commands.entity(root).despawn(); }
+    spawn_debug_grid(&mut commands, &map, &grid, &dbg);
+}
+
+/**
+ * Spawns the grid root at the map's top-left world anchor with one line per cell edge as children.
+ */
+fn spawn_debug_grid(commands: &mut Commands, map: &MapState, grid: &GridConfig, dbg: &DebugGridConfig) {
+    let vis = if dbg.enabled { Visibility::Visible } else { Visibility::Hidden };
+    let anchor = Transform::from_translation(map.world_anchor(grid.tile_size).extend(0.0));
+    let root = commands.spawn((Name::new("DebugGrid"), DebugGridRoot, vis, anchor, GlobalTransform::default())).id();
 
     let w = map.size.w;
     let h = map.size.h;
@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+use crate::core::events::{CellContent, CellLayer, MapResized, TileChanged};
+use crate::core::grid::GridConfig;
+use crate::core::map::{MapSet, MapState};
+use crate::core::structure::StructurePart;
+use crate::core::tile::Tileset;
+use crate::render::sync::{world_from_grid_with_tile, GridPos};
+use crate::render::tilemaps::{LevelTilemaps, TilemapLayers};
+
+pub struct StructureSpritesPlugin;
+
+impl Plugin for StructureSpritesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, sync_structure_sprites.in_set(MapSet::Sync));
+    }
+}
+
+/**
+ * Keeps one sprite per placed structure, spanning its rotated footprint. Structure cells changed this
+ * frame respawn the sprite of the structure they belong to; a resize, load or tileset rebuild moves every
+ * anchor, so all sprites are respawned from the map.
+ *
+ * @param changed - applied edits; only the structure layer is read
+ * @param resized - map geometry changes
+ * @param map - placed structure parts
+ * @param tileset - structure footprints, tints and art
+ * @param layers - per-deck sprite parents and the sprites spawned so far
+ */
+fn sync_structure_sprites(
+    mut commands: Commands,
+    mut changed: MessageReader<TileChanged>,
+    mut resized: MessageReader<MapResized>,
+    map: Res<MapState>,
+    tileset: Res<Tileset>,
+    grid: Res<GridConfig>,
+    layers: Option<ResMut<TilemapLayers>>,
+) {
+    let changes: Vec<TileChanged> = changed.read().filter(|c| c.layer == CellLayer::Structure).copied().collect();
+    let rebuild = resized.read().count() > 0 || tileset.is_changed();
+    let Some(mut layers) = layers else { return };
+    if !rebuild && changes.is_empty() { return }
+    let (px, origin) = (grid.tile_size, map.origin);
+
+    if rebuild {
+        for (level, tilemaps) in layers.levels.iter_mut().enumerate().take(map.levels() as usize) {
+            for (_, sprite) in tilemaps.structure_sprites.drain() { commands.entity(sprite).despawn(); }
+            for (anchor, part) in map.structures_on(level as u32, &tileset) {
+                spawn_structure_sprite(&mut commands, tilemaps, &tileset, anchor, part, origin, px);
+            }
+        }
+        return;
+    }
+
+    // Structures whose cells changed, by deck and primary cell (unlike anchors, never shared).
+    let mut touched: HashSet<(u32, UVec2)> = HashSet::new();
+    for c in &changes {
+        for content in [c.before, c.after].into_iter().flatten() {
+            let CellContent::Structure(part) = content else { continue };
+            let primary = UVec2::new(c.x, c.y) - part.offset + tileset.structure(part.structure).primary(part.rotation);
+            touched.insert((c.level, primary));
+        }
+    }
+    for (level, primary) in touched {
+        let Some(tilemaps) = layers.levels.get_mut(level as usize) else { continue };
+        if let Some(sprite) = tilemaps.structure_sprites.remove(&primary) { commands.entity(sprite).despawn(); }
+        if primary.x >= map.size.w || primary.y >= map.size.h { continue }
+        let Some(part) = map.get_structure(level, primary.x, primary.y) else { continue };
+        if part.offset != tileset.structure(part.structure).primary(part.rotation) { continue }
+        spawn_structure_sprite(&mut commands, tilemaps, &tileset, primary - part.offset, part, origin, px);
+    }
+}
+
+/**
+ * Spawns the sprite of one structure under its deck's structure parent: sized to the unrotated footprint,
+ * turned clockwise by the part's rotation and centered on the rotated footprint.
+ *
+ * @param tilemaps - deck to draw on; the sprite is tracked by the structure's primary cell
+ * @param anchor - storage cell of the rotated footprint's bottom-left corner
+ * @param part - any part of the structure (only its id and rotation are used)
+ * @param origin - logical cell of storage (0,0), see `MapState::origin`
+ * @param px - grid cell size in world units
+ */
+fn spawn_structure_sprite(
+    commands: &mut Commands,
+    tilemaps: &mut LevelTilemaps,
+    tileset: &Tileset,
+    anchor: UVec2,
+    part: StructurePart,
+    origin: IVec2,
+    px: f32,
+) {
+    let def = tileset.structure(part.structure);
+    let span = (def.rotated_size(part.rotation).as_vec2() - Vec2::ONE) * px / 2.0;
+    let center = world_from_grid_with_tile(px, anchor.x, anchor.y) + (origin.as_vec2() * px + span).extend(0.0);
+    let mut sprite = Sprite { color: def.color, custom_size: Some(def.size.as_vec2() * px), ..default() };
+    if let Some(image) = &def.sprite { sprite.image = image.clone(); }
+    let turns = part.rotation.quarter_turns() as f32;
+    let entity = commands.spawn((
+        sprite,
+        Transform::from_translation(center).with_rotation(Quat::from_rotation_z(-FRAC_PI_2 * turns)),
+        GridPos { x: anchor.x, y: anchor.y },
+        Name::new(def.name.clone()),
+        ChildOf(tilemaps.structures),
+    )).id();
+    tilemaps.structure_sprites.insert(anchor + def.primary(part.rotation), entity);
+}
@@ -1,96 +1,339 @@
+/**
+ * Reactive render sync: the base, overlay, blueprint, pipe and component tilemaps are derived from
+ * `MapState` and `PipeMap` alone. Whatever changed a cell (tools, undo, loads, scripts) marks it dirty in
+ * the layer container, and the systems here repaint exactly those cells at the end of the frame; a map
+ * resize or load repaints everything from scratch instead.
+ */
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::prelude::*;
+use crate::core::events::MapResized;
+use crate::core::map::{MapSet, MapState};
+use crate::core::pipes::{MaskRefresh, PipeComponent, PipeMap, UtilityKind, MASK_DOWN, MASK_UP};
+use crate::core::tile::{TileId, TileLayer, Tileset};
+use crate::render::tilemaps::TilemapLayers;
+use crate::render::LayerView;
 
+/** Storage cell an entity drawn over the map is anchored on, e.g. a structure sprite's anchor. */
 #[derive(Component)]
 pub struct GridPos { pub x: u32, pub y: u32 }
 
 pub struct TileSyncPlugin;
 impl Plugin for TileSyncPlugin {
-    fn build(&self, _app: &mut App) {
-        // no-op: helpers used by placement
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, (sync_map_tiles, sync_pipe_tiles).in_set(MapSet::Sync));
     }
 }
 
-pub fn world_from_grid_with_tile(px: f32, x: u32, y: u32) -> Vec3 {
-    // Center sprites within px grid cells whose top-left origin is at (0,0)
-    Vec3::new(x as f32 * px + px / 2.0, y as f32 * px + px / 2.0, 0.0)
+/** Alpha multiplier for tiles on the ghosted deck below the active one. */
+const GHOST_ALPHA: f32 = 0.35;
+
+/** Alpha multiplier for blueprints (planned, not yet built tiles). */
+const PLANNED_ALPHA: f32 = 0.45;
+
+/** Tint for pipe cells with a riser, so vertical segments stand out from plain runs. */
+const RISER_TINT: Color = Color::srgb(1.0, 0.75, 0.4);
+
+/** Tint of each utility kind in the engineering view. */
+pub fn engineering_color(kind: UtilityKind) -> Color {
+    match kind {
+        UtilityKind::Gas => Color::srgb(0.6, 0.9, 1.0),
+        UtilityKind::Liquid => Color::srgb(0.3, 0.5, 1.0),
+        UtilityKind::Power => Color::srgb(1.0, 0.85, 0.2),
+        UtilityKind::Data => Color::srgb(0.4, 1.0, 0.5),
+    }
 }
 
-pub fn set_tile_in_tilemap(
-    commands: &mut Commands,
-    storage: &mut TileStorage,
-    tilemap_entity: Entity,
-    color: Color,
-    x: u32,
-    y: u32,
-) {
-    let pos = TilePos { x, y };
-    // If there's an existing tile at this position, we can choose to overwrite it:
-    if let Some(existing) = storage.get(&pos) {
-        commands.entity(existing).despawn();
+/**
+ * Frame of `component` in the component atlas: open and closed valves, pumps facing N, E, S and W, filters,
+ * then a junction, a crossing, a vent and a scrubber. Inline parts have one frame per axis (N-S first).
+ */
+fn component_frame(component: PipeComponent) -> u32 {
+    match component {
+        PipeComponent::Valve { rotation, open } => (if open { 0 } else { 2 }) + rotation.quarter_turns() as u32 % 2,
+        PipeComponent::Pump { rotation } => 4 + rotation.quarter_turns() as u32,
+        PipeComponent::Filter { rotation, .. } => 8 + rotation.quarter_turns() as u32 % 2,
+        PipeComponent::Junction => 10,
+        PipeComponent::Crossing => 11,
+        PipeComponent::Vent => 12,
+        PipeComponent::Scrubber => 13,
     }
-    let tile_entity = commands.spawn((TileBundle {
-        position: pos,
-        tilemap_id: TilemapId(tilemap_entity),
-        texture_index: TileTextureIndex(0),
-        color: TileColor(color),
-        ..Default::default()
-    }, Name::new("Tile"))).id();
-    storage.set(&pos, tile_entity);
 }
 
+/** Every cell of a `w` x `h` layer, row by row. */
+fn all_cells(w: u32, h: u32) -> Vec<UVec2> { (0..h).flat_map(|y| (0..w).map(move |x| UVec2::new(x, y))).collect() }
+
+/** Reads the tile one tilemap shows at a cell: `(map, level, x, y)`; `None` clears the tile. */
+type TileSource = fn(&MapState, u32, u32, u32) -> Option<TileId>;
+
 /**
- * Spawns or replaces a tile at (x,y) with a specific texture index and color in a tilemap.
- * Useful when selecting a sprite variant based on connectivity masks or animation frames.
+ * Repaints the base, overlay and blueprint tilemaps of each deck at every `MapState` cell modified since the
+ * last run, so cost scales with edits rather than map size. A resize or load (`MapResized`) and a tileset
+ * rebuild repaint every deck in full; when the ghosted deck changes, the decks entering or leaving ghost
+ * state are repainted in full.
  *
- * @param commands - ECS command buffer
- * @param storage - tile storage for the tilemap
- * @param tilemap_entity - target tilemap entity
- * @param texture_index - tile texture index to assign
- * @param color - tile color tint
- * @param x - tile x coordinate
- * @param y - tile y coordinate
+ * @param resized - map geometry changes; any of them forces a full rebuild
+ * @param map - map state; its dirty cell sets are drained here
+ * @param tileset - tile colors
+ * @param layers - tilemap entities to repaint, and the texture index (and variant) of each tile's art
+ * @param view - decides which deck is drawn translucent
  */
-pub fn set_tile_with_index(
-    commands: &mut Commands,
-    storage: &mut TileStorage,
-    tilemap_entity: Entity,
-    texture_index: u32,
-    color: Color,
-    x: u32,
-    y: u32,
+fn sync_map_tiles(
+    mut resized: MessageReader<MapResized>,
+    mut map: ResMut<MapState>,
+    tileset: Res<Tileset>,
+    layers: Option<Res<TilemapLayers>>,
+    view: Res<LayerView>,
+    mut last_ghost: Local<Option<u32>>,
+    mut tiles: TileWriter,
 ) {
-    let pos = TilePos { x, y };
-    if let Some(existing) = storage.get(&pos) {
-        commands.entity(existing).despawn();
+    let rebuild = resized.read().count() > 0 || tileset.is_changed();
+    let Some(layers) = layers else { return };
+    let ghost = view.ghost_level();
+    let ghost_changed = *last_ghost != ghost;
+    if !map.is_changed() && !ghost_changed && !rebuild { return }
+    let map = map.bypass_change_detection();
+    let (w, h) = (map.size.w, map.size.h);
+    let origin = map.origin;
+
+    for (level, tilemaps) in layers.levels.iter().enumerate().take(map.levels() as usize) {
+        let level = level as u32;
+        let mut dirty = map.take_dirty_cells(level);
+        if rebuild || (ghost_changed && (*last_ghost == Some(level) || ghost == Some(level))) { dirty = all_cells(w, h); }
+        if dirty.is_empty() { continue }
+        let alpha = if ghost == Some(level) { GHOST_ALPHA } else { 1.0 };
+        let index = |id: TileId, x: u32, y: u32| layers.tiles.index(id, origin + UVec2::new(x, y).as_ivec2());
+
+        let sources: [(Entity, TileSource, f32); 4] = [
+            (tilemaps.base, |m, l, x, y| Some(m.get_base(l, x, y)).filter(|&id| id != TileId::EMPTY), 1.0),
+            (tilemaps.overlay, |m, l, x, y| m.get_overlay(l, x, y), 1.0),
+            (tilemaps.planned_base, |m, l, x, y| m.get_planned(l, x, y, TileLayer::Base), PLANNED_ALPHA),
+            (tilemaps.planned_overlay, |m, l, x, y| m.get_planned(l, x, y, TileLayer::Overlay), PLANNED_ALPHA),
+        ];
+        for (tilemap, source, layer_alpha) in sources {
+            let tint = |id: TileId| { let c = tileset.def(id).color; c.with_alpha(c.alpha() * alpha * layer_alpha) };
+            let map = &*map;
+            tiles.apply(tilemap, dirty.iter().map(|&UVec2 { x, y }| (x, y, source(map, level, x, y).map(|id| (index(id, x, y), tint(id))))));
+        }
     }
-    let tile_entity = commands.spawn((TileBundle {
-        position: pos,
-        tilemap_id: TilemapId(tilemap_entity),
-        texture_index: TileTextureIndex(texture_index),
-        color: TileColor(color),
-        ..Default::default()
-    }, Name::new("Tile"))).id();
-    storage.set(&pos, tile_entity);
+    *last_ghost = ghost;
 }
 
 /**
- * Removes a tile at (x,y) from a tilemap if it exists.
+ * Recomputes pipe connectivity for the cells changed since the last run and their four neighbours (whose
+ * masks depend on them; see `PipeMap::refresh_masks`), for every utility kind on every deck. Changed cells are repainted in both tilemaps
+ * of their kind and deck and in its component tilemap; a neighbour's pipe tiles are only repainted when its
+ * mask actually changed, so a one-cell edit touches at most five tiles per view. A resize or load recomputes
+ * and repaints every cell.
+ * For now, the NESW part of the mask is used directly as the tile texture index; riser cells are tinted.
  *
- * @param commands - ECS command buffer
- * @param storage - tile storage for the tilemap
- * @param x - tile x coordinate
- * @param y - tile y coordinate
+ * @param resized - map geometry changes; any of them forces a full rebuild
+ * @param layers - tilemap entities to repaint
+ * @param pipemap - pipe layers; their dirty cell sets are drained and their masks updated here
  */
-pub fn remove_tile_in_tilemap(
-    commands: &mut Commands,
-    storage: &mut TileStorage,
-    x: u32,
-    y: u32,
+fn sync_pipe_tiles(
+    mut resized: MessageReader<MapResized>,
+    layers: Option<Res<TilemapLayers>>,
+    mut tiles: TileWriter,
+    mut pipemap: ResMut<PipeMap>,
 ) {
-    let pos = TilePos { x, y };
-    if let Some(existing) = storage.get(&pos) {
-        commands.entity(existing).despawn();
-        storage.remove(&pos);
+    let rebuild = resized.read().count() > 0;
+    let Some(layers) = layers else { return };
+    if !pipemap.is_changed() && !rebuild { return }
+    let pipemap = pipemap.bypass_change_detection();
+    let (w, h) = pipemap.size();
+
+    for (level, tilemaps) in layers.levels.iter().enumerate().take(pipemap.levels() as usize) { for kind in UtilityKind::ALL {
+        let level = level as u32;
+        let mut dirty = pipemap.take_dirty_cells(kind, level);
+        if rebuild { dirty = all_cells(w, h); }
+        if dirty.is_empty() { continue }
+        let MaskRefresh { masks, components } = pipemap.refresh_masks(kind, level, &dirty, rebuild);
+
+        let frames = components.into_iter().map(|(x, y, component)| (x, y, component.map(|c| (component_frame(c), Color::WHITE))));
+        tiles.apply(tilemaps.components[kind.index()], frames);
+
+        let views = [(tilemaps.pipes[kind.index()], Color::WHITE), (tilemaps.pipes_eng[kind.index()], engineering_color(kind))];
+        for (tilemap, base_color) in views {
+            tiles.apply(tilemap, masks.iter().map(|&(x, y, mask_opt)| (x, y, mask_opt.map(|mask| {
+                let vertical = mask & (MASK_UP | MASK_DOWN) != 0;
+                ((mask & 0x0F) as u32, if vertical { RISER_TINT } else { base_color })
+            }))));
+        }
+    }}
+}
+
+/** Center of storage cell (x,y) relative to the map's bottom-left corner, in world units. */
+pub fn world_from_grid_with_tile(px: f32, x: u32, y: u32) -> Vec3 {
+    Vec3::new(x as f32 * px + px / 2.0, y as f32 * px + px / 2.0, 0.0)
+}
+
+/** New content of one tile: texture index and tint, or `None` to clear it. */
+pub type TileUpdate = Option<(u32, Color)>;
+
+/**
+ * Writes tiles into tilemaps without churning entities: a tile that already exists keeps its entity and
+ * only has its `TileTextureIndex` / `TileColor` changed (and only when they differ, so unchanged tiles are
+ * not re-extracted); a missing tile is spawned and a cleared one despawned.
+ */
+#[derive(SystemParam)]
+pub struct TileWriter<'w, 's> {
+    commands: Commands<'w, 's>,
+    storages: Query<'w, 's, &'static mut TileStorage>,
+    tiles: Query<'w, 's, (&'static mut TileTextureIndex, &'static mut TileColor)>,
+}
+
+impl TileWriter<'_, '_> {
+    /**
+     * Applies cell updates to one tilemap, looking its storage up once. Cells outside the tilemap, or any
+     * cell when `tilemap` has no storage, are skipped.
+     *
+     * @param tilemap - target tilemap entity
+     * @param updates - `(x, y, update)` per cell, applied in order
+     */
+    pub fn apply(&mut self, tilemap: Entity, updates: impl IntoIterator<Item = (u32, u32, TileUpdate)>) {
+        let Ok(mut storage) = self.storages.get_mut(tilemap) else { return };
+        for (x, y, update) in updates {
+            let pos = TilePos { x, y };
+            if x >= storage.size.x || y >= storage.size.y { continue }
+            match (storage.get(&pos), update) {
+                (Some(existing), Some((index, color))) => match self.tiles.get_mut(existing) {
+                    Ok((mut texture, mut tint)) => {
+                        texture.set_if_neq(TileTextureIndex(index));
+                        if tint.0 != color { tint.0 = color; }
+                    }
+                    // Spawned earlier this frame: its components only exist once commands are applied.
+                    Err(_) => { self.commands.entity(existing).insert((TileTextureIndex(index), TileColor(color))); }
+                },
+                (None, Some((index, color))) => {
+                    let tile = self.commands.spawn((TileBundle {
+                        position: pos,
+                        tilemap_id: TilemapId(tilemap),
+                        texture_index: TileTextureIndex(index),
+                        color: TileColor(color),
+                        ..Default::default()
+                    }, Name::new("Tile"))).id();
+                    storage.set(&pos, tile);
+                }
+                (Some(existing), None) => {
+                    self.commands.entity(existing).despawn();
+                    storage.remove(&pos);
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use crate::core::catalog::test_tileset;
+    use crate::core::grid::GridConfig;
+    use crate::core::map::MapSize;
+    use crate::render::tilemaps::setup_test_tilemaps;
+
+    const RED: Color = Color::srgb(1.0, 0.0, 0.0);
+
+    /** A headless app running only tile sync over tilemaps for an empty 4x4 two-deck map. */
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_message::<MapResized>()
+            .insert_resource(MapState::new(MapSize { w: 4, h: 4 }, 2))
+            .insert_resource(PipeMap::new((4, 4), 2))
+            .insert_resource(test_tileset(r#"{ "tiles": [
+                { "id": "Empty", "layer": "Base", "color": [0, 0, 0, 0] },
+                { "id": "Deck", "layer": "Base", "color": [1, 0, 0, 1] }
+            ] }"#))
+            .init_resource::<GridConfig>()
+            .init_resource::<LayerView>()
+            .add_plugins(TileSyncPlugin)
+            .add_systems(Startup, setup_test_tilemaps);
+        app.update();
+        app
+    }
+
+    /** Tile shown by the tilemap `pick`s from deck 0 at (x,y). */
+    fn shown(app: &App, pick: fn(&crate::render::tilemaps::LevelTilemaps) -> Entity, x: u32, y: u32) -> Option<Entity> {
+        tile(app.world(), pick(&app.world().resource::<TilemapLayers>().levels[0]), x, y)
+    }
+
+    fn deck(app: &App) -> TileId { app.world().resource::<Tileset>().id("Deck").unwrap() }
+
+    /** Applies `updates` to `tilemap` through a `TileWriter`, as one system run. */
+    fn write(world: &mut World, tilemap: Entity, updates: Vec<(u32, u32, TileUpdate)>) {
+        world.run_system_once(move |mut tiles: TileWriter| tiles.apply(tilemap, updates.clone())).unwrap();
+    }
+
+    fn tile(world: &World, tilemap: Entity, x: u32, y: u32) -> Option<Entity> {
+        world.get::<TileStorage>(tilemap).unwrap().get(&TilePos { x, y })
+    }
+
+    fn look(world: &World, tile: Entity) -> (u32, Color) {
+        (world.get::<TileTextureIndex>(tile).unwrap().0, world.get::<TileColor>(tile).unwrap().0)
+    }
+
+    #[test]
+    fn tile_writer_updates_spawns_and_despawns_tiles() {
+        let mut world = World::new();
+        let tilemap = world.spawn(TileStorage::empty(TilemapSize { x: 4, y: 4 })).id();
+        write(&mut world, tilemap, vec![(1, 1, Some((3, RED))), (2, 1, Some((1, Color::WHITE)))]);
+        let (kept, cleared) = (tile(&world, tilemap, 1, 1).unwrap(), tile(&world, tilemap, 2, 1).unwrap());
+        assert_eq!(look(&world, kept), (3, RED), "a missing tile is spawned");
+
+        write(&mut world, tilemap, vec![(1, 1, Some((5, Color::WHITE))), (2, 1, None), (9, 9, Some((1, RED)))]);
+        assert_eq!(tile(&world, tilemap, 1, 1), Some(kept), "an existing tile keeps its entity");
+        assert_eq!(look(&world, kept), (5, Color::WHITE));
+        assert_eq!(tile(&world, tilemap, 2, 1), None);
+        assert!(world.get_entity(cleared).is_err(), "a cleared tile is despawned");
+        assert_eq!(world.query::<&TilePos>().iter(&world).count(), 1, "cells outside the tilemap are skipped");
+    }
+
+    #[test]
+    fn tile_writer_updates_a_tile_spawned_in_the_same_run() {
+        let mut world = World::new();
+        let tilemap = world.spawn(TileStorage::empty(TilemapSize { x: 2, y: 2 })).id();
+        write(&mut world, tilemap, vec![(0, 0, Some((1, Color::WHITE))), (0, 0, Some((2, RED)))]);
+        assert_eq!(look(&world, tile(&world, tilemap, 0, 0).unwrap()), (2, RED));
+        assert_eq!(world.query::<&TilePos>().iter(&world).count(), 1);
+    }
+
+    #[test]
+    fn map_changes_made_outside_the_tools_are_drawn() {
+        let mut app = app();
+        let deck = deck(&app);
+        app.world_mut().resource_mut::<MapState>().set_base(0, 1, 2, deck);
+        app.world_mut().resource_mut::<PipeMap>().set(UtilityKind::Gas, 0, 3, 3, true);
+        app.update();
+        let painted = shown(&app, |l| l.base, 1, 2).expect("a base write is drawn");
+        assert_eq!(look(app.world(), painted), (0, RED));
+        assert!(shown(&app, |l| l.pipes[UtilityKind::Gas.index()], 3, 3).is_some());
+
+        app.world_mut().resource_mut::<MapState>().set_base(0, 1, 2, TileId::EMPTY);
+        app.update();
+        assert_eq!(shown(&app, |l| l.base, 1, 2), None, "clearing a cell despawns its tile");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn resizes_and_tileset_changes_repaint_every_cell() {
+        let mut app = app();
+        let deck = deck(&app);
+        let mut map = app.world_mut().resource_mut::<MapState>();
+        map.set_base(0, 2, 2, deck);
+        map.bypass_change_detection().take_dirty_cells(0);
+        app.update();
+        assert_eq!(shown(&app, |l| l.base, 2, 2), None, "nothing is dirty, so nothing is repainted");
+
+        app.world_mut().write_message(MapResized { growth: default() });
+        app.update();
+        assert!(shown(&app, |l| l.base, 2, 2).is_some(), "a load repaints every cell");
+
+        let mut map = app.world_mut().resource_mut::<MapState>();
+        map.set_base(0, 0, 0, deck);
+        map.bypass_change_detection().take_dirty_cells(0);
+        app.world_mut().resource_mut::<Tileset>().set_changed();
+        app.update();
+        assert!(shown(&app, |l| l.base, 0, 0).is_some(), "a tileset rebuild repaints every cell");
+    }
+}
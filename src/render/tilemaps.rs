@@ -2,121 +2,419 @@ use bevy::prelude::*;
 use bevy::render::render_resource::{TextureFormat, Extent3d, TextureDimension};
 use bevy::asset::RenderAssetUsages;
 use bevy_ecs_tilemap::prelude::*;
-use crate::core::map::MapState;
+use std::collections::HashMap;
+use crate::core::map::{MapSet, MapState};
+use crate::core::tile::{TileId, Tileset};
+use crate::core::events::MapResized;
 use crate::core::grid::GridConfig;
+use crate::core::chunk::CHUNK_SIZE;
+use crate::core::pipes::UtilityKind;
+
+/** World-space z distance between deck roots, so the active deck draws above a ghosted one. */
+const LEVEL_Z_STEP: f32 = 1.0;
 
 /**
- * Groups the tilemap entity IDs for each render layer so systems can find and update them.
- * Layers:
+ * Tilemap entity IDs for one deck. All tilemaps are children of `root`, which is hidden
+ * when the deck is not shown. Layers:
  * - base: terrain/background
  * - overlay: general markers/UI tiles
- * - pipes: normal view for pipes
- * - pipes_eng: engineering view for pipes (toggled visible in engineering mode)
+ * - planned_base / planned_overlay: translucent blueprints awaiting construction, drawn above the rest
+ * - pipes: normal view of each utility kind, indexed by `UtilityKind::index`
+ * - pipes_eng: engineering view of each utility kind (toggled visible in engineering mode)
+ * - components: inline pipe components of each utility kind, drawn over both views
+ * - pipe_preview: ghost tiles of the pipe path being dragged, drawn above everything else
+ * - structures: parent of one sprite per placed structure, tracked by primary cell in `structure_sprites`
+ */
+pub struct LevelTilemaps {
+    pub root: Entity,
+    pub base: Entity,
+    pub overlay: Entity,
+    pub planned_base: Entity,
+    pub planned_overlay: Entity,
+    pub pipes: [Entity; UtilityKind::ALL.len()],
+    pub pipes_eng: [Entity; UtilityKind::ALL.len()],
+    pub components: [Entity; UtilityKind::ALL.len()],
+    pub pipe_preview: Entity,
+    pub structures: Entity,
+    pub structure_sprites: HashMap<UVec2, Entity>,
+}
+
+impl LevelTilemaps {
+    /** Every tilemap of the deck (not the root). */
+    pub fn tilemaps(&self) -> Vec<Entity> {
+        [self.base, self.overlay, self.planned_base, self.planned_overlay].into_iter().chain(self.pipes).chain(self.pipes_eng).chain(self.components).chain([self.pipe_preview]).collect()
+    }
+}
+
+/** Local z of the blueprint tilemaps within a deck, so ghosts draw over built tiles and pipes. */
+const PLANNED_Z: f32 = 0.5;
+
+/** Local z of the pipe drag preview, above blueprints. */
+const PIPE_PREVIEW_Z: f32 = 0.75;
+
+/** Local z between the utility kinds' tilemaps, so kinds sharing a cell always stack in the same order. */
+const UTILITY_Z_STEP: f32 = 0.01;
+
+/** Local z of a kind's component tilemap above its pipe tilemaps. */
+const COMPONENT_Z: f32 = UTILITY_Z_STEP / 2.0;
+
+/** Atlas of pipe component sprites, one tile-sized frame per component variant (see `render::sync::component_frame`). */
+const COMPONENTS_ATLAS: &str = "pipes/components.png";
+
+/** Local z of structure sprites within a deck: above built tiles, below blueprints. */
+const STRUCTURE_Z: f32 = 0.25;
+
+/**
+ * Groups the tilemap entity IDs for each deck so systems can find and update them.
+ * `levels[i]` renders `MapState` level `i`. `texture` is the plain white tile used by the pipe tilemaps and
+ * `components` the atlas of the component tilemaps;
+ * `tiles` is the catalog art shared by every base/overlay tilemap.
  */
 #[derive(Resource)]
-pub struct TilemapLayers { pub base: Entity, pub overlay: Entity, pub pipes: Entity, pub pipes_eng: Entity }
+pub struct TilemapLayers { pub levels: Vec<LevelTilemaps>, pub texture: Handle<Image>, pub components: Handle<Image>, pub tiles: TileTextures }
+
+/**
+ * Catalog art for the base/overlay tilemaps: `texture` holds one image per atlas cell in use, and `frames`
+ * lists each tile's texture indices, indexed by `TileId`. Index 0 is the white tile, used by tiles without
+ * a sprite (or whose atlas cell is missing) so they render as their catalog color.
+ */
+pub struct TileTextures { pub texture: TilemapTexture, frames: Vec<Vec<u32>> }
+
+impl TileTextures {
+    /**
+     * Texture index for tile `id` drawn at world cell `cell`. Variants are picked by a stable hash of the
+     * world cell, so they don't reshuffle when the map grows or is reloaded.
+     */
+    pub fn index(&self, id: TileId, cell: IVec2) -> u32 {
+        match self.frames.get(id.index()) {
+            Some(frames) if !frames.is_empty() => {
+                let hash = (cell.x as u32).wrapping_mul(73_856_093) ^ (cell.y as u32).wrapping_mul(19_349_663);
+                frames[hash as usize % frames.len()]
+            }
+            _ => 0,
+        }
+    }
+}
 
 // Removed TilemapParams; gameplay now converts world->grid via core GridConfig
 
 pub struct GameTilemapsPlugin;
 impl Plugin for GameTilemapsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_tilemaps);
+        app.add_systems(Startup, setup_tilemaps)
+            .add_systems(PostUpdate, (resize_tilemaps, rebuild_tile_textures).chain().in_set(MapSet::Rebuild));
     }
 }
 
 /**
- * Creates all tilemap layers (base, overlay, pipes, pipes_eng) with consistent sizing and grid params.
- * The engineering pipes layer starts Hidden; we toggle between pipes and pipes_eng at runtime.
+ * Creates one tilemap set (base, overlay, blueprints, pipes, pipes_eng and components per utility kind, pipe preview) per deck with consistent sizing and grid params.
+ * Visibility of decks and layers is owned by `apply_layer_visibility` in the render plugin.
  *
  * @param commands - ECS command buffer for spawning entities/resources
  * @param images - asset store used to create a placeholder tile texture
+ * @param assets - loads the pipe component atlas
  * @param map - current map state to size the tilemaps
  * @param grid - grid configuration (tile size, etc.)
  */
 fn setup_tilemaps(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
+    assets: Res<AssetServer>,
     map: Res<MapState>,
     grid: Res<GridConfig>,
 ) {
     let tile_px = grid.tile_size as u32;
     let white_tex = Image::new_fill(Extent3d { width: tile_px, height: tile_px, depth_or_array_layers: 1 }, TextureDimension::D2, &[255, 255, 255, 255], TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::RENDER_WORLD);
-    let tex_handle = images.add(white_tex);
+    let texture = images.add(white_tex);
+
+    let tiles = TileTextures { texture: TilemapTexture::Vector(vec![texture.clone()]), frames: Vec::new() };
+    let components = assets.load(COMPONENTS_ATLAS);
+    let levels = (0..map.levels())
+        .map(|level| spawn_level_tilemaps(&mut commands, &texture, &components, &tiles.texture, &map, &grid, level))
+        .collect();
+    commands.insert_resource(TilemapLayers { levels, texture, components, tiles });
+}
 
+/**
+ * Spawns the deck root and its tilemaps sized and anchored to the current map.
+ *
+ * @param texture - plain white tile for the pipe layers
+ * @param components - component atlas for the component layers
+ * @param tile_texture - catalog art for the base, overlay and blueprint layers
+ * @param level - deck index, used for naming and z ordering
+ */
+fn spawn_level_tilemaps(
+    commands: &mut Commands,
+    texture: &Handle<Image>,
+    components: &Handle<Image>,
+    tile_texture: &TilemapTexture,
+    map: &MapState,
+    grid: &GridConfig,
+    level: u32,
+) -> LevelTilemaps {
     let map_size = TilemapSize { x: map.size.w, y: map.size.h };
     let tile_size = TilemapTileSize { x: grid.tile_size, y: grid.tile_size };
     let grid_size = TilemapGridSize { x: grid.tile_size, y: grid.tile_size };
     let map_type = TilemapType::Square;
     let anchor = TilemapAnchor::TopLeft;
+    let transform = Transform::from_translation(map.world_anchor(grid.tile_size).extend(0.0));
+    // Match render chunks to storage chunks so one edit only remeshes the chunk it touched.
+    let render_settings = TilemapRenderSettings { render_chunk_size: UVec2::splat(CHUNK_SIZE), y_sort: false };
 
-    // Base layer
-    let base_storage = TileStorage::empty(map_size);
-    let base_entity = commands.spawn_empty().id();
-    commands.entity(base_entity).insert(TilemapBundle {
-        grid_size,
-        size: map_size,
-        storage: base_storage.clone(),
-        texture: TilemapTexture::Single(tex_handle.clone()),
-        tile_size,
-        map_type,
-        anchor,
-        transform: Transform::default(),
-        ..Default::default()
-    });
+    let root = commands.spawn((
+        Name::new(format!("Level{level}")),
+        Transform::from_xyz(0.0, 0.0, level as f32 * LEVEL_Z_STEP),
+        Visibility::Hidden,
+    )).id();
+
+    let mut spawn_layer = |name: &str, texture: TilemapTexture, z: f32| {
+        commands.spawn((
+            TilemapBundle {
+                grid_size,
+                size: map_size,
+                storage: TileStorage::empty(map_size),
+                texture,
+                tile_size,
+                map_type,
+                anchor,
+                render_settings,
+                transform: transform.with_translation(transform.translation.with_z(z)),
+                ..Default::default()
+            },
+            Name::new(format!("{name}{level}")),
+            ChildOf(root),
+        )).id()
+    };
 
-    // Overlay layer
-    let overlay_storage = TileStorage::empty(map_size);
-    let overlay_entity = commands.spawn_empty().id();
-    commands.entity(overlay_entity).insert(TilemapBundle {
-        grid_size,
-        size: map_size,
-        storage: overlay_storage.clone(),
-        texture: TilemapTexture::Single(tex_handle.clone()),
-        tile_size,
-        map_type,
-        anchor,
-        transform: Transform::default(),
-        ..Default::default()
+    let base = spawn_layer("Base", tile_texture.clone(), 0.0);
+    let overlay = spawn_layer("Overlay", tile_texture.clone(), 0.0);
+    let planned_base = spawn_layer("PlannedBase", tile_texture.clone(), PLANNED_Z);
+    let planned_overlay = spawn_layer("PlannedOverlay", tile_texture.clone(), PLANNED_Z);
+    let mut utility_layers = |view: &str, texture: &Handle<Image>, z: f32| UtilityKind::ALL.map(|kind| {
+        let name = format!("{view}{kind:?}");
+        spawn_layer(&name, TilemapTexture::Single(texture.clone()), kind.index() as f32 * UTILITY_Z_STEP + z)
     });
+    let pipes = utility_layers("Pipes", texture, 0.0);
+    let pipes_eng = utility_layers("PipesEngineering", texture, 0.0);
+    let components = utility_layers("PipeComponents", components, COMPONENT_Z);
+    let pipe_preview = spawn_layer("PipePreview", TilemapTexture::Single(texture.clone()), PIPE_PREVIEW_Z);
+    let structures = commands.spawn((
+        Name::new(format!("Structures{level}")),
+        Transform::from_xyz(0.0, 0.0, STRUCTURE_Z),
+        Visibility::Inherited,
+        ChildOf(root),
+    )).id();
+    let structure_sprites = HashMap::new();
+    LevelTilemaps { root, base, overlay, planned_base, planned_overlay, pipes, pipes_eng, components, pipe_preview, structures, structure_sprites }
+}
+
+/**
+ * Re-sizes every deck's tilemaps in place after the map geometry changed: despawns their tiles, swaps in
+ * empty storage of the new size and moves them to the new anchor. Existing entities are kept so visibility
+ * survives; decks are spawned or despawned if the deck count changed (e.g. after a load).
+ * Render sync then repaints every tile from the map (see `render::sync`).
+ *
+ * @param resized - map geometry change notifications
+ * @param map - current map state (new size, origin and deck count)
+ * @param layers - tilemap entities to rebuild
+ */
+fn resize_tilemaps(
+    mut commands: Commands,
+    mut resized: MessageReader<MapResized>,
+    map: Res<MapState>,
+    grid: Res<GridConfig>,
+    mut layers: ResMut<TilemapLayers>,
+    mut q_maps: Query<(&mut TilemapSize, &mut TileStorage, &mut Transform)>,
+) {
+    if resized.read().count() == 0 { return }
+    let map_size = TilemapSize { x: map.size.w, y: map.size.h };
+    let origin = map.world_anchor(grid.tile_size);
+
+    while layers.levels.len() > map.levels() as usize {
+        let Some(level) = layers.levels.pop() else { break };
+        for entity in level.tilemaps() {
+            if let Ok((_, mut storage, _)) = q_maps.get_mut(entity) {
+                for tile in storage.drain() { commands.entity(tile).despawn(); }
+            }
+        }
+        commands.entity(level.root).despawn();
+    }
+
+    for level in &layers.levels {
+        for entity in level.tilemaps() {
+            let Ok((mut size, mut storage, mut transform)) = q_maps.get_mut(entity) else { continue };
+            for tile in storage.drain() { commands.entity(tile).despawn(); }
+            *storage = TileStorage::empty(map_size);
+            *size = map_size;
+            transform.translation.x = origin.x;
+            transform.translation.y = origin.y;
+        }
+    }
+
+    let (texture, components, tile_texture) = (layers.texture.clone(), layers.components.clone(), layers.tiles.texture.clone());
+    for level in layers.levels.len() as u32..map.levels() {
+        let spawned = spawn_level_tilemaps(&mut commands, &texture, &components, &tile_texture, &map, &grid, level);
+        layers.levels.push(spawned);
+    }
+}
+
+/** Test stand-in for `setup_tilemaps`: the same tilemaps, without creating or loading any image. */
+#[cfg(test)]
+pub(crate) fn setup_test_tilemaps(mut commands: Commands, map: Res<MapState>, grid: Res<GridConfig>) {
+    let (texture, components) = (Handle::default(), Handle::default());
+    let tiles = TileTextures { texture: TilemapTexture::Single(Handle::default()), frames: Vec::new() };
+    let levels = (0..map.levels())
+        .map(|level| spawn_level_tilemaps(&mut commands, &texture, &components, &tiles.texture, &map, &grid, level))
+        .collect();
+    commands.insert_resource(TilemapLayers { levels, texture, components, tiles });
+}
+
+/**
+ * Slices every catalog sprite's atlas cells into tile-sized images whenever the `Tileset` is rebuilt, and
+ * swaps the resulting `TilemapTexture::Vector` into all base/overlay/blueprint tilemaps. Tiles whose art is missing
+ * fall back to the white tile and are reported. Render sync then repaints every tile with the new indices,
+ * since it repaints in full whenever the `Tileset` changed (see `render::sync`).
+ *
+ * @param tileset - tile definitions with their atlas references
+ * @param images - loaded atlases; receives the sliced tile images
+ * @param layers - tilemap entities; their tile textures are rebuilt here
+ */
+fn rebuild_tile_textures(
+    tileset: Res<Tileset>,
+    grid: Res<GridConfig>,
+    mut images: ResMut<Assets<Image>>,
+    layers: Option<ResMut<TilemapLayers>>,
+    mut q_textures: Query<&mut TilemapTexture>,
+) {
+    let Some(mut layers) = layers else { return };
+    if !tileset.is_changed() { return }
+    let px = grid.tile_size as u32;
+    let mut handles = vec![layers.texture.clone()];
+    let mut sliced: HashMap<(AssetId<Image>, u32), u32> = HashMap::new();
+    let mut tile_frames = Vec::with_capacity(tileset.defs.len());
+
+    for def in &tileset.defs {
+        let mut frames = Vec::new();
+        if let Some(sprite) = &def.sprite {
+            let atlas = images.get(&sprite.atlas).and_then(|img| img.convert(TextureFormat::Rgba8UnormSrgb));
+            let Some(atlas) = atlas else {
+                warn!("tile '{}': atlas is not loaded or not convertible to RGBA8; drawing plain color", def.name);
+                tile_frames.push(frames);
+                continue;
+            };
+            for &cell in &sprite.frames {
+                if let Some(&index) = sliced.get(&(sprite.atlas.id(), cell)) { frames.push(index); continue }
+                let Some(image) = slice_atlas_cell(&atlas, px, cell) else {
+                    warn!("tile '{}': atlas cell {cell} is outside the {}x{} px atlas", def.name, atlas.width(), atlas.height());
+                    continue;
+                };
+                let index = handles.len() as u32;
+                handles.push(images.add(image));
+                sliced.insert((sprite.atlas.id(), cell), index);
+                frames.push(index);
+            }
+        }
+        tile_frames.push(frames);
+    }
+
+    layers.tiles = TileTextures { texture: TilemapTexture::Vector(handles), frames: tile_frames };
+    for level in &layers.levels {
+        for entity in [level.base, level.overlay, level.planned_base, level.planned_overlay] {
+            if let Ok(mut texture) = q_textures.get_mut(entity) { *texture = layers.tiles.texture.clone(); }
+        }
+    }
+}
+
+/**
+ * Copies one `px` x `px` cell out of a row-major RGBA8 atlas. Returns `None` if the cell is out of range.
+ *
+ * @param atlas - atlas image in `Rgba8UnormSrgb`
+ * @param px - cell edge length in pixels
+ * @param cell - row-major cell index
+ */
+fn slice_atlas_cell(atlas: &Image, px: u32, cell: u32) -> Option<Image> {
+    let (cols, rows) = (atlas.width() / px, atlas.height() / px);
+    if cell >= cols * rows { return None }
+    let data = atlas.data.as_ref()?;
+    let (x0, y0) = ((cell % cols * px) as usize, (cell / cols * px) as usize);
+    let (stride, row_bytes) = (atlas.width() as usize * 4, px as usize * 4);
+    let mut out = Vec::with_capacity(row_bytes * px as usize);
+    for y in y0..y0 + px as usize {
+        let start = y * stride + x0 * 4;
+        out.extend_from_slice(&data[start..start + row_bytes]);
+    }
+    Some(Image::new(
+        Extent3d { width: px, height: px, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        out,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::map::{MapGrowth, MapSize};
+
+    /** An app running only `resize_tilemaps`, with tilemaps spawned for `map` as at startup. */
+    fn app(map: MapState) -> App {
+        let mut app = App::new();
+        app.add_message::<MapResized>()
+            .insert_resource(map)
+            .init_resource::<GridConfig>()
+            .add_systems(Startup, setup_test_tilemaps)
+            .add_systems(Update, resize_tilemaps);
+        app.update();
+        app
+    }
 
-    // Pipes (normal view) layer
-    let pipes_storage = TileStorage::empty(map_size);
-    let pipes_entity = commands.spawn_empty().id();
-    commands.entity(pipes_entity).insert((
-        TilemapBundle {
-            grid_size,
-            size: map_size,
-            storage: pipes_storage.clone(),
-            texture: TilemapTexture::Single(tex_handle.clone()),
-            tile_size,
-            map_type,
-            anchor,
-            transform: Transform::default(),
-            ..Default::default()
-        },
-        Name::new("Pipes"),
-    ));
-
-    // Pipes (engineering view) layer
-    let pipes_eng_storage = TileStorage::empty(map_size);
-    let pipes_eng_entity = commands.spawn_empty().id();
-    commands.entity(pipes_eng_entity).insert((
-        TilemapBundle {
-            grid_size,
-            size: map_size,
-            storage: pipes_eng_storage.clone(),
-            texture: TilemapTexture::Single(tex_handle.clone()),
-            tile_size,
-            map_type,
-            anchor,
-            transform: Transform::default(),
-            ..Default::default()
-        },
-        Name::new("PipesEngineering"),
-    ));
-    // Set initial visibility after inserting the bundle to avoid duplicate Visibility in the same bundle
-    commands.entity(pipes_eng_entity).insert(Visibility::Hidden);
-
-    commands.insert_resource(TilemapLayers { base: base_entity, overlay: overlay_entity, pipes: pipes_entity, pipes_eng: pipes_eng_entity });
-}
\ No newline at end of file
+    /** Replaces the map and announces it as `MapResized { growth }`, then runs a frame. */
+    fn resize(app: &mut App, map: MapState, growth: MapGrowth) {
+        app.insert_resource(map);
+        app.world_mut().write_message(MapResized { growth });
+        app.update();
+    }
+
+    #[test]
+    fn growing_left_and_down_resizes_tilemaps_in_place() {
+        let map = MapState::new(MapSize { w: 4, h: 4 }, 2);
+        let grown = map.expanded(MapGrowth { left: 16, bottom: 16, ..default() });
+        let anchor = grown.world_anchor(GridConfig::default().tile_size);
+        let mut app = app(map);
+        let before: Vec<Entity> = app.world().resource::<TilemapLayers>().levels.iter().flat_map(|l| l.tilemaps()).collect();
+        let base = app.world().resource::<TilemapLayers>().levels[0].base;
+        let tile = app.world_mut().spawn_empty().id();
+        app.world_mut().get_mut::<TileStorage>(base).unwrap().set(&TilePos { x: 1, y: 1 }, tile);
+
+        resize(&mut app, grown, MapGrowth { left: 16, bottom: 16, ..default() });
+        let after: Vec<Entity> = app.world().resource::<TilemapLayers>().levels.iter().flat_map(|l| l.tilemaps()).collect();
+        assert_eq!(before, after, "tilemap entities are kept");
+        for entity in after {
+            let world = app.world();
+            assert_eq!(*world.get::<TilemapSize>(entity).unwrap(), TilemapSize { x: 20, y: 20 });
+            assert_eq!(world.get::<Transform>(entity).unwrap().translation.truncate(), anchor);
+            assert!(world.get::<TileStorage>(entity).unwrap().iter().all(Option::is_none), "tiles are left for sync to repaint");
+        }
+        assert!(app.world().get_entity(tile).is_err(), "old tiles are despawned");
+    }
+
+    #[test]
+    fn a_load_with_another_deck_count_spawns_and_despawns_decks() {
+        let mut app = app(MapState::new(MapSize { w: 4, h: 4 }, 3));
+        let roots = |app: &App| app.world().resource::<TilemapLayers>().levels.iter().map(|l| l.root).collect::<Vec<_>>();
+        let first = roots(&app);
+
+        resize(&mut app, MapState::new(MapSize { w: 4, h: 4 }, 1), MapGrowth::default());
+        assert_eq!(roots(&app), first[..1], "the remaining deck keeps its tilemaps");
+        assert!(first[1..].iter().all(|&root| app.world().get_entity(root).is_err()));
+
+        resize(&mut app, MapState::new(MapSize { w: 6, h: 2 }, 4), MapGrowth::default());
+        let layers = app.world().resource::<TilemapLayers>();
+        assert_eq!(layers.levels.len(), 4);
+        assert_eq!(layers.levels[0].root, first[0]);
+        for level in &layers.levels[1..] {
+            assert_eq!(*app.world().get::<TilemapSize>(level.base).unwrap(), TilemapSize { x: 6, y: 2 }, "new decks match the loaded map");
+        }
+    }
+}